@@ -0,0 +1,6 @@
+#!/bin/sh
+# Refreshes the bundled public suffix list.
+set -e
+cd "$(dirname "$0")/.."
+curl -fsSL https://publicsuffix.org/list/public_suffix_list.dat -o data/public_suffix_list.dat.new
+mv data/public_suffix_list.dat.new data/public_suffix_list.dat
@@ -8,28 +8,79 @@
 //! ## Register a new account
 //! ```
 //! let store = storage::FileStore::init(&"/tmp/certifika").unwrap()
-//! let account = acme::Account::new("some@email.com".as_str(), &store).unwrap();
+//! let account = acme::Account::new("some@email.com".as_str(), &[], acme::AccountKeyType::default(), &store, acme::LETSENCRYPT_DIRECTORY_URL, None, false, Some(terms_url)).unwrap();
 //! ```
-use crate::storage::{ObjectKind, Store};
+use crate::backoff::{self, Backoff};
+use crate::csr;
+use crate::dns::{self, ZoneResolver};
+use crate::http;
+use crate::metrics::{self, Counter};
+use crate::psl;
+use crate::request::{CertRequest, ChallengeType, HookStage, KeyType};
+use crate::storage::{self, ObjectKind, Store};
+use crate::systemd;
 use crate::{APP_NAME, APP_VERSION};
 use anyhow::anyhow;
-use ring::{
-    digest, rand,
-    signature::{self, EcdsaKeyPair, KeyPair},
-};
+use ring::digest;
 use serde::{Deserialize, Serialize};
-use std::{thread, time};
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
 use thiserror::Error;
+mod bundle;
+mod ca;
+mod capabilities;
+mod http01;
 mod jws;
+mod key;
+mod nonce;
+mod problem;
+mod ratelimit;
+mod revocation;
+mod stats;
+mod status;
+mod strict;
+#[cfg(test)]
+mod tests;
+mod transit;
+
+pub use bundle::Bundle;
+pub use ca::{Ca, Eab};
+pub use capabilities::Capabilities;
+pub use http01::Http01;
+use key::AccountKey;
+pub use key::{
+    generate_rsa_pkcs8, pkcs8_from_jwk, pkcs8_from_pkcs1, pkcs8_from_sec1, AccountKeyType,
+};
+use nonce::Nonce;
+pub use problem::AcmeProblem;
+use ratelimit::RateLimits;
+pub use ratelimit::Throttle;
+pub use revocation::Reason;
+pub use stats::Stats;
+use stats::{Phase, Timings};
+pub use status::{AuthzStatus, OrderStatus};
 
 pub const HTTP_CLIENT_LIB: &str = "ureq 2.0.1";
-pub const LETSENCRYPT_DIRECTORY_URL: &str =
+const BAD_NONCE_RETRIES: u8 = 3;
+const DEFAULT_CONCURRENCY: usize = 10;
+const DEFAULT_ORDER_TIMEOUT: Duration = Duration::from_secs(600);
+/// how long the waits sleep at a time, see `Account::pause()`
+const PAUSE_STEP: Duration = Duration::from_secs(60);
+/// the profile Let's Encrypt offers short-lived certificates under
+const SHORT_LIVED_PROFILE: &str = "shortlived";
+/// the lifetime to ask for with notAfter, when the CA has no short-lived profile
+const SHORT_LIVED_DAYS: i64 = 6;
+pub const LETSENCRYPT_DIRECTORY_URL: &str = "https://acme-v02.api.letsencrypt.org/directory";
+pub const LETSENCRYPT_STAGING_DIRECTORY_URL: &str =
     "https://acme-staging-v02.api.letsencrypt.org/directory";
 
 #[derive(Error, Debug)]
 pub enum AcmeError {
     #[error("ACME API: {0:?}")]
     Api(ureq::Error),
+    #[error("ACME problem: {0}")]
+    Problem(AcmeProblem),
     #[error("JSON encode: {0:?}")]
     JsonEncode(std::io::Error),
     #[error("JSON decode: {0:?}")]
@@ -42,6 +93,27 @@ pub enum AcmeError {
     KeyGen(ring::error::Unspecified),
     #[error("UTF8 processing: {0:?}")]
     Utf8(std::str::Utf8Error),
+    #[error("http-01 solver: {0:?}")]
+    Http01(std::io::Error),
+    #[error("the CA requires External Account Binding, but no EAB credentials were provided (--eab-kid/--eab-hmac-key)")]
+    EabRequired,
+    #[error("the terms of service of the CA ({0}) have to be agreed to, see --agree-tos")]
+    TermsNotAgreed(String),
+    #[error("{} challenge for {domain} failed: {problem}", .challenge.name())]
+    ChallengeFailed {
+        domain: String,
+        challenge: ChallengeType,
+        problem: String,
+    },
+    #[error(
+        "order {order} timed out after {timeout}s, its pending authorizations are deactivated"
+    )]
+    OrderTimeout { order: String, timeout: u64 },
+    #[error("RFC8555 violations in the response from {url}: {}", .violations.join("; "))]
+    Strict {
+        url: String,
+        violations: Vec<String>,
+    },
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
@@ -55,20 +127,33 @@ struct Directory {
     directory: serde_json::Value,
 }
 
+/// The [metadata](https://tools.ietf.org/html/rfc8555#section-9.7.6) of a directory, all of it is optional.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Meta {
+    pub terms_of_service: Option<String>,
+    pub website: Option<String>,
+    pub caa_identities: Vec<String>,
+    pub external_account_required: bool,
+    /// certificate profiles, names mapped to their descriptions
+    pub profiles: serde_json::Map<String, serde_json::Value>,
+}
+
 impl Directory {
-    /// a wrapper around `Self::from_url()` method to create
-    /// a new instance from the default Let's Encrypt URL.
-    pub fn lets_encrypt() -> Result<Directory, AcmeError> {
-        Directory::from_url(LETSENCRYPT_DIRECTORY_URL)
+    /// Parses the `meta` object, a missing or malformed one counts as empty.
+    pub fn meta(&self) -> Meta {
+        serde_json::from_value(self.directory["meta"].clone()).unwrap_or_default()
     }
+
     /// method to create a new Directory instance from an URL.
     pub fn from_url(url: &str) -> Result<Directory, AcmeError> {
-        let agent = ureq::AgentBuilder::new().build();
-        let response = agent
-            .get(url)
-            .set("User-Agent", &http_user_agent())
-            .call()
-            .map_err(AcmeError::Api)?;
+        let response = http::timed(url, || {
+            http::agent()
+                .get(url)
+                .set("User-Agent", &http_user_agent())
+                .call()
+        })
+        .map_err(AcmeError::Api)?;
         Ok(Directory {
             url: url.to_owned(),
             directory: response.into_json().map_err(AcmeError::JsonEncode)?,
@@ -91,8 +176,8 @@ impl Directory {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Identifier {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identifier {
     #[serde(rename = "type")]
     _type: String,
     value: String,
@@ -100,11 +185,261 @@ struct Identifier {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Order {
-    status: String,
+    #[serde(skip)]
+    url: String,
+    status: OrderStatus,
     expires: String,
     identifiers: Vec<Identifier>,
     authorizations: Vec<String>,
     finalize: String,
+    certificate: Option<String>,
+    /// the validity window asked for, CAs that don't honor it leave it out
+    #[serde(rename = "notBefore", default, skip_serializing_if = "Option::is_none")]
+    not_before: Option<String>,
+    #[serde(rename = "notAfter", default, skip_serializing_if = "Option::is_none")]
+    not_after: Option<String>,
+    /// why the order has become invalid, e.g. the CA refused to issue after finalization
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<AcmeProblem>,
+    #[serde(skip)]
+    retry_after: Option<Duration>,
+    #[serde(skip)]
+    key_type: KeyType,
+    #[serde(skip)]
+    must_staple: bool,
+    /// the name of the certificate request, see `CertRequest::name()`
+    #[serde(skip)]
+    name: String,
+}
+
+/// The suggested renewal window of a certificate, RFC3339 timestamps,
+/// see `Account::renewal_info()`.
+#[derive(Debug, Deserialize)]
+pub struct RenewalWindow {
+    pub start: String,
+    pub end: String,
+}
+
+/// What the CA knows about the account key, see `Account::lookup()`.
+#[derive(Debug, Serialize)]
+pub struct AccountLookup {
+    /// the account URL the CA returned for the key, `None` if it doesn't know the key
+    pub kid: Option<String>,
+    /// whether it's the same URL as the stored one
+    pub kid_matches: bool,
+    /// `valid`, `deactivated` or `revoked`
+    pub status: Option<String>,
+}
+
+/// The account as the CA sees it, see `Account::info()`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountInfo {
+    #[serde(default)]
+    pub kid: String,
+    #[serde(default)]
+    pub directory_url: String,
+    /// `valid`, `deactivated` or `revoked`
+    pub status: String,
+    #[serde(default)]
+    pub contact: Vec<String>,
+    /// the URL of the account's orders list, not all CAs provide it
+    pub orders: Option<String>,
+    /// an extension of Let's Encrypt (Boulder)
+    pub created_at: Option<String>,
+}
+
+/// A stored account as `accounts()` lists it.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSummary {
+    pub email: String,
+    pub staging: bool,
+    pub directory_url: String,
+    pub kid: String,
+    /// RFC3339, unknown for the accounts registered before it was kept (and the recovered ones)
+    pub created: Option<String>,
+}
+
+/// Everything issued for an order: the certificate's private key and
+/// the certificate chain, both PEM encoded.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IssuedCertificate {
+    /// `None` when the order was finalized with a supplied CSR, the key is kept elsewhere then
+    pub key_pem: Option<String>,
+    pub chain_pem: String,
+    pub order_url: String,
+    /// the final state of the order as JSON, `None` for the certificates read from the store
+    #[serde(skip)]
+    pub order: Option<String>,
+    /// notAfter of the leaf certificate, seconds since the UNIX epoch.
+    pub expires: i64,
+}
+
+impl IssuedCertificate {
+    /// The private key, for the consumers that can't do without it.
+    pub fn key(&self) -> anyhow::Result<&str> {
+        self.key_pem.as_deref().ok_or_else(|| {
+            anyhow!("the certificate was issued for a supplied CSR, its key is not available")
+        })
+    }
+}
+
+impl Order {
+    /// Checks that the order may move to the status of its `next` state.
+    fn check_transition(&self, next: &Order) -> Result<(), AcmeError> {
+        if !self.status.can_become(next.status) {
+            return Err(AcmeError::Other(anyhow!(
+                "order {} can't go from {} to {}",
+                self.url,
+                self.status,
+                next.status
+            )));
+        }
+        if self.status != next.status {
+            log::info!(
+                r#"{{"op":"order transition","order":"{}","from":"{}","to":"{}"}}"#,
+                self.url,
+                self.status,
+                next.status
+            );
+        }
+        Ok(())
+    }
+
+    /// Finalizes the order with a freshly generated certificate key and downloads the
+    /// certificate chain. The key and the chain are returned together, and are written
+    /// to the account's store only if `persist` is set, so embedding code can decide
+    /// where the key material lives. The issuance is only recorded for the rate limits
+    /// and the stats then too, e.g. dry runs leave the store alone.
+    pub fn finalize_with_key(
+        &self,
+        account: &mut Account<'_>,
+        persist: bool,
+    ) -> Result<IssuedCertificate, AcmeError> {
+        let csr = csr::build(&self.domains(), self.key_type, self.must_staple)?;
+        self.finalize_csr(account, &csr.der, Some(csr.key_pem), persist)
+    }
+
+    /// Same as `finalize_with_key()`, but with an existing PEM encoded certificate key,
+    /// e.g. to keep the key pinned across renewals.
+    pub fn finalize_with_own_key(
+        &self,
+        account: &mut Account<'_>,
+        key_pem: &str,
+        persist: bool,
+    ) -> Result<IssuedCertificate, AcmeError> {
+        // Let's Encrypt (and others) reject CSRs signed with the account key
+        if csr::public_key(key_pem)? == account.key_pair.public_key() {
+            return Err(AcmeError::Other(anyhow!(
+                "the account key can't be used as the certificate key"
+            )));
+        }
+        let csr = csr::build_for_key(&self.domains(), key_pem, self.must_staple)?;
+        self.finalize_csr(account, &csr.der, Some(csr.key_pem), persist)
+    }
+
+    /// Same as `finalize_with_own_key()`, with the key of the previously issued certificate
+    /// from the account's store. Falls back to a fresh key when there is none yet.
+    pub fn finalize_reusing_key(
+        &self,
+        account: &mut Account<'_>,
+        persist: bool,
+    ) -> Result<IssuedCertificate, AcmeError> {
+        let name = self.name()?;
+        match account.store.read(ObjectKind::CertKey, name) {
+            Ok(key_pem) => {
+                log::info!(r#"{{"op":"certificate key reused","domain":"{}"}}"#, name);
+                let key_pem =
+                    String::from_utf8(key_pem).map_err(|e| AcmeError::Utf8(e.utf8_error()))?;
+                self.finalize_with_own_key(account, &key_pem, persist)
+            }
+            Err(_) => self.finalize_with_key(account, persist),
+        }
+    }
+
+    /// Same as `finalize_with_key()`, but with a DER encoded `csr` made elsewhere,
+    /// e.g. by an HSM that never lets the key out. The CSR has to cover all the
+    /// identifiers of the order, the CA rejects it otherwise.
+    pub fn finalize_with_csr(
+        &self,
+        account: &mut Account<'_>,
+        csr: &[u8],
+        persist: bool,
+    ) -> Result<IssuedCertificate, AcmeError> {
+        self.finalize_csr(account, csr, None, persist)
+    }
+
+    /// The name the certificate is stored under, see `CertRequest::name()`.
+    fn name(&self) -> Result<&str, AcmeError> {
+        if self.name.is_empty() {
+            return Err(AcmeError::Other(anyhow!(
+                "order {} has no certificate request",
+                self.url
+            )));
+        }
+        Ok(&self.name)
+    }
+
+    fn domains(&self) -> Vec<String> {
+        self.identifiers
+            .iter()
+            .map(|i| i.value.to_owned())
+            .collect()
+    }
+
+    fn finalize_csr(
+        &self,
+        account: &mut Account<'_>,
+        csr: &[u8],
+        key_pem: Option<String>,
+        persist: bool,
+    ) -> Result<IssuedCertificate, AcmeError> {
+        if self.status != OrderStatus::Ready {
+            return Err(AcmeError::Other(anyhow!(
+                "order is {}, only ready orders can be finalized",
+                self.status
+            )));
+        }
+        let name = self.name()?;
+        let started = Instant::now();
+        let finalized = account.finalize(self, csr)?;
+        self.check_transition(&finalized)?;
+        let order =
+            account.poll_order(finalized, &[OrderStatus::Ready, OrderStatus::Processing])?;
+        account.timings.record(Phase::Finalize, started);
+        let order_json = serde_json::to_string(&order).map_err(AcmeError::JsonDecode)?;
+        // the order stays processing until the CA has issued, poll_order() waits it out
+        let certificate_url = match (order.status, order.certificate, order.error) {
+            (OrderStatus::Valid, Some(url), _) => url,
+            (OrderStatus::Invalid, _, Some(problem)) => return Err(AcmeError::Problem(problem)),
+            (status, _, _) => {
+                return Err(AcmeError::Other(anyhow!(
+                    "order is {}, no certificate issued",
+                    status
+                )))
+            }
+        };
+        let started = Instant::now();
+        let chain_pem = account.download_certificate(&certificate_url)?;
+        account.timings.record(Phase::Download, started);
+        let issued = IssuedCertificate {
+            key_pem,
+            expires: crate::cert::not_after(&chain_pem)?,
+            chain_pem,
+            order_url: self.url.to_owned(),
+            order: Some(order_json),
+        };
+        if persist {
+            account.save_certificate(name, &issued)?;
+            account.rate_limits.record_certificate(&self.domains());
+            account.rate_limits.save(account.store, &account.name)?;
+            Stats::add(account.store, &account.directory.url, &account.timings)?;
+        }
+        metrics::increment(Counter::Renewals);
+        account.timings = Timings::default();
+        Ok(issued)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -114,204 +449,1368 @@ struct Challenge {
     status: String,
     url: String,
     token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<AcmeProblem>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Authorization {
     identifier: Identifier,
-    status: String,
+    status: AuthzStatus,
     expires: String,
     challenges: Vec<Challenge>,
+    #[serde(skip)]
+    url: String,
+    #[serde(skip)]
+    retry_after: Option<Duration>,
+}
+
+/// A challenge to solve, along with what to publish for it: the TXT record and its
+/// value for dns-01, the URL path and the key authorization for http-01.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingChallenge {
+    pub domain: String,
+    /// bundles exported before there was a choice have dns-01 ones
+    #[serde(default)]
+    pub challenge: ChallengeType,
+    pub authorization: String,
+    pub url: String,
+    /// bundles exported before the validation hooks had it have none, and are
+    /// signed without it
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub token: String,
+    pub record: String,
+    pub value: String,
+    /// the zone the dns-01 record goes into, see `dns::ZoneResolver`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+}
+
+/// A challenge being validated, see `Account::solve()`.
+struct InFlight<'c> {
+    challenge: &'c PendingChallenge,
+    authorization: Authorization,
+    started: Instant,
+    attempt: u32,
+    /// when to poll the authorization next
+    next: Instant,
+}
+
+/// A signed request to a resource (either a directory resource name, or an URL).
+enum Request<'r> {
+    /// [POST-as-GET](https://tools.ietf.org/html/rfc8555#section-6.3), i.e. signed with the empty payload
+    Get(&'r str),
+    /// a POST with the JSON `body`, `{}` when there is nothing to say
+    Post(&'r str, serde_json::Value),
+}
+
+impl<'r> Request<'r> {
+    fn resource(&self) -> &'r str {
+        match self {
+            Request::Get(resource) | Request::Post(resource, _) => resource,
+        }
+    }
+
+    /// The JWS payload: an empty string for POST-as-GET, the JSON body otherwise.
+    fn payload(&self) -> Result<String, AcmeError> {
+        match self {
+            Request::Get(_) => Ok(String::new()),
+            Request::Post(_, body) => serde_json::to_string(body).map_err(AcmeError::JsonDecode),
+        }
+    }
+}
+
+/// What is left of an API response once it has been read.
+struct ApiResponse {
+    status: u16,
+    location: Option<String>,
+    retry_after: Option<Duration>,
+    /// the `Link` with `rel="terms-of-service"`
+    terms_of_service: Option<String>,
+    body: String,
+}
+
+impl ApiResponse {
+    /// Turns an error response into `AcmeError::Problem`, if it has a problem document.
+    fn into_error(self) -> AcmeError {
+        match serde_json::from_str::<AcmeProblem>(&self.body) {
+            Ok(mut problem) => {
+                problem.terms_of_service = self.terms_of_service;
+                AcmeError::Problem(problem)
+            }
+            Err(_) => AcmeError::Other(anyhow!("request failed: {} {}", self.status, self.body)),
+        }
+    }
+}
+
+/// The account objects as they are kept in the store.
+struct StoredAccount {
+    pkcs8: Vec<u8>,
+    directory: Directory,
+    kid: String,
+    eab: Option<Eab>,
+    terms_of_service: Option<String>,
+}
+
+impl StoredAccount {
+    fn read(store: &dyn Store, name: &str) -> Result<Self, AcmeError> {
+        let pkcs8 = store
+            .read(ObjectKind::KeyPair, name)
+            .map_err(AcmeError::Store)?;
+        let directory = serde_json::from_slice(
+            &store
+                .read(ObjectKind::Directory, name)
+                .map_err(AcmeError::Store)?,
+        )
+        .map_err(AcmeError::JsonDecode)?;
+        let kid = std::str::from_utf8(
+            &store
+                .read(ObjectKind::Account, name)
+                .map_err(AcmeError::Store)?,
+        )
+        .map_err(AcmeError::Utf8)?
+        .to_string();
+        // EAB credentials are kept only for the CAs that require them
+        let eab = match store.read(ObjectKind::Eab, name) {
+            Ok(eab) => Some(serde_json::from_slice(&eab).map_err(AcmeError::JsonDecode)?),
+            Err(_) => None,
+        };
+        // accounts registered before 0.3.0 have agreed implicitly
+        let terms_of_service = store
+            .read(ObjectKind::TermsOfService, name)
+            .ok()
+            .and_then(|terms| String::from_utf8(terms).ok());
+        Ok(StoredAccount {
+            pkcs8,
+            directory,
+            kid,
+            eab,
+            terms_of_service,
+        })
+    }
+
+    fn write(&self, store: &dyn Store, name: &str) -> Result<(), AcmeError> {
+        store
+            .write(ObjectKind::KeyPair, name, &self.pkcs8)
+            .map_err(AcmeError::Store)?;
+        store
+            .write(ObjectKind::Account, name, self.kid.as_bytes())
+            .map_err(AcmeError::Store)?;
+        let payload = serde_json::to_string(&self.directory).map_err(AcmeError::JsonDecode)?;
+        store
+            .write(ObjectKind::Directory, name, payload.as_bytes())
+            .map_err(AcmeError::Store)?;
+        if let Some(eab) = &self.eab {
+            let payload = serde_json::to_string(eab).map_err(AcmeError::JsonDecode)?;
+            store
+                .write(ObjectKind::Eab, name, payload.as_bytes())
+                .map_err(AcmeError::Store)?;
+        }
+        if let Some(terms) = &self.terms_of_service {
+            store
+                .write(ObjectKind::TermsOfService, name, terms.as_bytes())
+                .map_err(AcmeError::Store)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the accounts in the `store`, without talking to the CAs.
+pub fn accounts(store: &dyn Store) -> Result<Vec<AccountSummary>, AcmeError> {
+    let mut accounts = Vec::new();
+    for name in store.list(ObjectKind::Account).map_err(AcmeError::Store)? {
+        let read = |kind| -> Result<String, AcmeError> {
+            let payload = store.read(kind, &name).map_err(AcmeError::Store)?;
+            Ok(std::str::from_utf8(&payload)
+                .map_err(AcmeError::Utf8)?
+                .to_string())
+        };
+        let directory: serde_json::Value =
+            serde_json::from_str(&read(ObjectKind::Directory)?).map_err(AcmeError::JsonDecode)?;
+        let (email, staging) = match name.strip_suffix(".staging") {
+            Some(email) => (email.to_string(), true),
+            None => (name.to_owned(), false),
+        };
+        accounts.push(AccountSummary {
+            email,
+            staging,
+            directory_url: directory["url"].as_str().unwrap_or_default().to_string(),
+            kid: read(ObjectKind::Account)?,
+            created: read(ObjectKind::Created).ok(),
+        });
+    }
+    Ok(accounts)
+}
+
+/// Removes the objects of the account stored under the `name` (see `store_name()`),
+/// the CA is not told about it. The directory capabilities and stats are shared
+/// with the other accounts, so they stay.
+pub fn delete_account(store: &dyn Store, name: &str) -> Result<(), AcmeError> {
+    for kind in &[
+        ObjectKind::KeyPair,
+        ObjectKind::Account,
+        ObjectKind::Directory,
+        ObjectKind::Eab,
+        ObjectKind::RateLimits,
+        ObjectKind::TermsOfService,
+        ObjectKind::Created,
+    ] {
+        store.delete(*kind, name).map_err(AcmeError::Store)?;
+    }
+    Ok(())
+}
+
+/// Fetches the directory at `url` and returns its metadata.
+pub fn directory_meta(url: &str) -> Result<Meta, AcmeError> {
+    Ok(Directory::from_url(url)?.meta())
+}
+
+/// Accounts of certifika 0.1.0 are stored under the bare email, and since the staging
+/// environment was the default back then, most of them are staging accounts. Such an
+/// account is copied under its staging name, returns whether there was one to copy.
+fn migrate_legacy_account(store: &dyn Store, email: &str) -> Result<bool, AcmeError> {
+    let legacy = match StoredAccount::read(store, email) {
+        Ok(legacy) => legacy,
+        Err(_) => return Ok(false),
+    };
+    let url = legacy.directory.url.as_str();
+    let staging_url = Ca::from_directory_url(url).and_then(|ca| ca.directory_url(true).ok());
+    if staging_url != Some(url) {
+        return Ok(false);
+    }
+    let name = store_name(email, true);
+    legacy.write(store, &name)?;
+    log::info!(
+        r#"{{"op":"account migrated","from":"{}","to":"{}"}}"#,
+        email,
+        name
+    );
+    Ok(true)
 }
 
 /// struct for the ACME [Account](https://tools.ietf.org/html/rfc8555#section-7.1.2) object.
 pub struct Account<'a> {
     store: &'a dyn Store,
     email: String,
+    /// the name the account's objects are stored under, see `store_name()`
+    name: String,
     directory: Directory,
-    key_pair: EcdsaKeyPair,
+    key_pair: AccountKey,
     pkcs8: Vec<u8>,
-    nonce: Option<String>,
+    nonce: Option<Nonce>,
+    /// the nonce sent with the last request
+    used_nonce: Option<Nonce>,
+    nonce_max_age: Duration,
+    /// see the `strict` module
+    strict: bool,
+    /// phase timings of the current issuance
+    timings: Timings,
+    /// how many challenges are validated at once
+    concurrency: usize,
+    /// how long the challenges of an order may take altogether
+    order_timeout: Duration,
     kid: Option<String>,
+    eab: Option<Eab>,
+    /// the URL of the terms of service the account has agreed to
+    terms_of_service: Option<String>,
+    capabilities: Capabilities,
+    backoff: Backoff,
+    rate_limits: RateLimits,
+    throttle: Throttle,
+    /// how the http-01 challenges are served
+    http01: Http01,
 }
 
 impl<'a> Account<'a> {
-    /// Tries to register a new ACME account.
-    pub fn new(email: String, store: &'a dyn Store) -> Result<Account<'a>, AcmeError> {
-        let (key_pair, pkcs8) = Account::generate_keypair()?;
+    /// Tries to register a new ACME account with the CA at `directory_url`.
+    /// The directory is saved with the account, so later loads talk to the same CA.
+    /// Accounts registered with a `staging` CA are stored separately from the production ones.
+    /// The `email` names the account, the `contacts` are registered along with it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        email: String,
+        contacts: &[String],
+        key_type: AccountKeyType,
+        store: &'a dyn Store,
+        directory_url: &str,
+        eab: Option<Eab>,
+        staging: bool,
+        agreed_terms: Option<&str>,
+    ) -> Result<Account<'a>, AcmeError> {
+        let directory = Directory::from_url(directory_url)?;
+        let capabilities = Capabilities::load(store, &directory)?;
+        let eab = match Ca::from_directory_url(directory_url) {
+            Some(ca) => ca.eab(&email, eab)?,
+            None => eab,
+        };
+        if capabilities.external_account_required && eab.is_none() {
+            return Err(AcmeError::EabRequired);
+        }
+        let terms_of_service = match (directory.meta().terms_of_service, agreed_terms) {
+            (Some(terms), Some(agreed)) if terms == agreed => Some(terms),
+            (Some(terms), _) => return Err(AcmeError::TermsNotAgreed(terms)),
+            (None, _) => None,
+        };
+        let (key_pair, pkcs8) = AccountKey::generate(key_type)?;
         let mut acc = Account {
+            name: store_name(&email, staging),
             email,
             store,
-            directory: Directory::lets_encrypt()?,
+            directory,
             key_pair,
             pkcs8,
             nonce: None,
+            used_nonce: None,
+            nonce_max_age: nonce::DEFAULT_MAX_AGE,
+            strict: false,
+            timings: Timings::default(),
+            concurrency: DEFAULT_CONCURRENCY,
+            order_timeout: DEFAULT_ORDER_TIMEOUT,
             kid: None,
+            eab,
+            terms_of_service,
+            capabilities,
+            backoff: Backoff::default(),
+            rate_limits: RateLimits::default(),
+            throttle: Throttle::default(),
+            http01: Http01::default(),
         };
         acc.nonce = Some(acc.get_nonce()?);
-        acc.register()?;
+        acc.register(contacts)?;
         acc.save()?;
+        let created = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        store
+            .write(ObjectKind::Created, &acc.name, created.as_bytes())
+            .map_err(AcmeError::Store)?;
+        acc.probe_capabilities()?;
         Ok(acc)
     }
 
     pub fn save(&self) -> Result<(), AcmeError> {
         self.store
-            .write(ObjectKind::KeyPair, &self.email, self.pkcs8.as_ref())
+            .write(ObjectKind::KeyPair, &self.name, self.pkcs8.as_ref())
             .map_err(AcmeError::Store)?;
         self.store
             .write(
                 ObjectKind::Account,
-                &self.email,
+                &self.name,
                 self.kid.to_owned().unwrap().as_bytes(),
             )
             .map_err(AcmeError::Store)?;
         let payload = serde_json::to_string(&self.directory).map_err(AcmeError::JsonDecode)?;
         self.store
-            .write(ObjectKind::Directory, &self.email, payload.as_bytes())
+            .write(ObjectKind::Directory, &self.name, payload.as_bytes())
             .map_err(AcmeError::Store)?;
+        if let Some(eab) = &self.eab {
+            let payload = serde_json::to_string(eab).map_err(AcmeError::JsonDecode)?;
+            self.store
+                .write(ObjectKind::Eab, &self.name, payload.as_bytes())
+                .map_err(AcmeError::Store)?;
+        }
+        if let Some(terms) = &self.terms_of_service {
+            self.store
+                .write(ObjectKind::TermsOfService, &self.name, terms.as_bytes())
+                .map_err(AcmeError::Store)?;
+        }
         Ok(())
     }
 
-    pub fn load(email: String, store: &'a dyn Store) -> Result<Account<'a>, AcmeError> {
-        let alg = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+    pub fn load(
+        email: String,
+        store: &'a dyn Store,
+        staging: bool,
+    ) -> Result<Account<'a>, AcmeError> {
+        let name = store_name(&email, staging);
+        if staging && store.read(ObjectKind::KeyPair, &name).is_err() {
+            migrate_legacy_account(store, &email)?;
+        }
+        let stored = StoredAccount::read(store, &name)?;
+        let mut acc = Account::from_stored(email, name, store, stored)?;
+        acc.probe_capabilities()?;
+        Ok(acc)
+    }
+
+    /// Recovers an account whose record is lost, while its key is still in the store:
+    /// the CA is asked which account the key belongs to
+    /// ([onlyReturnExisting](https://tools.ietf.org/html/rfc8555#section-7.3.1)),
+    /// and the account is saved again. The stored directory takes precedence
+    /// over the `directory_url`.
+    pub fn find(
+        email: String,
+        store: &'a dyn Store,
+        directory_url: &str,
+        staging: bool,
+    ) -> Result<Account<'a>, AcmeError> {
+        let name = store_name(&email, staging);
         let pkcs8 = store
-            .read(ObjectKind::KeyPair, &email)
+            .read(ObjectKind::KeyPair, &name)
             .map_err(AcmeError::Store)?;
-        let key_pair = signature::EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref())
-            .map_err(AcmeError::KeyDecode)?;
-        let dir = serde_json::from_slice(
-            &store
-                .read(ObjectKind::Directory, &email)
-                .map_err(AcmeError::Store)?,
-        )
-        .map_err(AcmeError::JsonDecode)?;
+        let directory = match store.read(ObjectKind::Directory, &name) {
+            Ok(stored) => serde_json::from_slice(&stored).map_err(AcmeError::JsonDecode)?,
+            Err(_) => Directory::from_url(directory_url)?,
+        };
+        let stored = StoredAccount {
+            pkcs8,
+            directory,
+            kid: String::new(),
+            eab: store
+                .read(ObjectKind::Eab, &name)
+                .ok()
+                .and_then(|eab| serde_json::from_slice(&eab).ok()),
+            terms_of_service: store
+                .read(ObjectKind::TermsOfService, &name)
+                .ok()
+                .and_then(|terms| String::from_utf8(terms).ok()),
+        };
+        let mut acc = Account::from_stored(email, name, store, stored)?;
+        // the kid is what we are looking for
+        acc.kid = None;
+        let kid = acc.lookup()?.kid.ok_or_else(|| {
+            anyhow!(
+                "the CA doesn't know the key of {}, register a new account",
+                acc.email
+            )
+        })?;
+        log::info!(r#"{{"op":"account found","kid":"{}"}}"#, kid);
+        acc.kid = Some(kid);
+        acc.save()?;
+        acc.probe_capabilities()?;
+        Ok(acc)
+    }
+
+    /// Takes over an account registered by another ACME client: its PKCS#8 encoded
+    /// key, the `directory_url` of its CA, and its `kid`. The CA has to confirm
+    /// the key belongs to the account before it's stored.
+    pub fn import(
+        email: String,
+        store: &'a dyn Store,
+        pkcs8: Vec<u8>,
+        directory_url: &str,
+        kid: &str,
+        staging: bool,
+    ) -> Result<Account<'a>, AcmeError> {
+        let name = store_name(&email, staging);
+        let stored = StoredAccount {
+            pkcs8,
+            directory: Directory::from_url(directory_url)?,
+            kid: kid.to_string(),
+            eab: None,
+            terms_of_service: None,
+        };
+        let mut acc = Account::from_stored(email, name, store, stored)?;
+        let lookup = acc.lookup()?;
+        if !lookup.kid_matches {
+            return Err(AcmeError::Other(anyhow!(
+                "the CA doesn't know the key as the account {}",
+                kid
+            )));
+        }
+        // the terms were agreed to when the other client registered the account
+        acc.terms_of_service = acc.current_terms();
+        acc.save()?;
+        acc.probe_capabilities()?;
+        Ok(acc)
+    }
+
+    fn from_stored(
+        email: String,
+        name: String,
+        store: &'a dyn Store,
+        stored: StoredAccount,
+    ) -> Result<Account<'a>, AcmeError> {
+        let key_pair = AccountKey::load(&stored.pkcs8)?;
+        // the directory is refetched, so that resource URLs are always up to date
+        let directory = Directory::from_url(&stored.directory.url)?;
+        let capabilities = Capabilities::load(store, &directory)?;
+        let rate_limits = RateLimits::load(store, &name);
         let mut acc = Account {
             email,
-            directory: dir,
+            name,
+            directory,
             store,
             key_pair,
-            pkcs8,
+            pkcs8: stored.pkcs8,
             nonce: None,
-            kid: None,
+            used_nonce: None,
+            nonce_max_age: nonce::DEFAULT_MAX_AGE,
+            strict: false,
+            timings: Timings::default(),
+            concurrency: DEFAULT_CONCURRENCY,
+            order_timeout: DEFAULT_ORDER_TIMEOUT,
+            kid: Some(stored.kid),
+            eab: stored.eab,
+            terms_of_service: stored.terms_of_service,
+            capabilities,
+            backoff: Backoff::default(),
+            rate_limits,
+            throttle: Throttle::default(),
+            http01: Http01::default(),
         };
         acc.nonce = Some(acc.get_nonce()?);
-        acc.kid = Some(
-            std::str::from_utf8(
-                &acc.store
-                    .read(ObjectKind::Account, &acc.email)
-                    .map_err(AcmeError::Store)?,
-            )
-            .map_err(AcmeError::Utf8)?
-            .to_string(),
-        );
         Ok(acc)
     }
 
-    pub fn order(&mut self, domains: Vec<String>) -> Result<(), AcmeError> {
+    /// Sets the policy for retries and polling.
+    pub fn set_backoff(&mut self, backoff: Backoff) {
+        self.backoff = backoff;
+    }
+
+    /// Sets how long a nonce may be kept before it's considered stale.
+    pub fn set_nonce_max_age(&mut self, max_age: Duration) {
+        self.nonce_max_age = max_age;
+    }
+
+    /// Sets how long the challenges of an order may take altogether.
+    pub fn set_order_timeout(&mut self, timeout: Duration) {
+        self.order_timeout = timeout;
+    }
+
+    /// Sets how many challenges of an order may be validated at once.
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency.max(1);
+    }
+
+    /// Turns the strict mode on, see the `strict` module.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Sets what to do when the client-side rate limit tracking sees a full bucket.
+    pub fn set_throttle(&mut self, throttle: Throttle) {
+        self.throttle = throttle;
+    }
+
+    /// Sets how the http-01 challenges are served.
+    pub fn set_http01(&mut self, http01: Http01) {
+        self.http01 = http01;
+    }
+
+    /// Checks the rate limit buckets before an order, and warns or waits for a free slot.
+    fn throttle(&mut self, ca: Option<Ca>, identifiers: &[String]) -> Result<(), AcmeError> {
+        let wait = match self.rate_limits.check(ca, identifiers) {
+            Some(wait) => wait,
+            None => return Ok(()),
+        };
+        match self.throttle {
+            Throttle::Delay(max) if wait <= max => {
+                log::warn!(r#"{{"op":"rate limit delay","wait":{}}}"#, wait.as_secs());
+                self.pause(wait)
+            }
+            Throttle::Delay(_) => Err(AcmeError::Other(anyhow!(
+                "rate limit reached, the next slot is in {}s",
+                wait.as_secs()
+            ))),
+            Throttle::Warn => {
+                log::warn!(r#"{{"op":"rate limit reached","wait":{}}}"#, wait.as_secs());
+                Ok(())
+            }
+        }
+    }
+
+    /// Sleeps for the `duration`, renewing the store lock held in the meantime (see
+    /// `storage::lock`) and pinging the systemd watchdog: a wait for the rate limits
+    /// alone may outlast both. A lock taken over in the meantime ends the wait, and
+    /// the order along with it.
+    fn pause(&self, duration: Duration) -> Result<(), AcmeError> {
+        let until = Instant::now() + duration;
+        let step = systemd::watchdog_interval()
+            .map_or(PAUSE_STEP, |watchdog| PAUSE_STEP.min(watchdog / 2));
+        loop {
+            storage::renew_lock(self.store).map_err(AcmeError::Store)?;
+            systemd::notify("WATCHDOG=1");
+            let left = until.saturating_duration_since(Instant::now());
+            if left == Duration::from_secs(0) {
+                return Ok(());
+            }
+            thread::sleep(left.min(step));
+        }
+    }
+
+    /// Orders a certificate for the `request`, and solves the challenges.
+    pub fn order(
+        &mut self,
+        request: &CertRequest,
+        zones: &ZoneResolver,
+    ) -> Result<Order, AcmeError> {
+        // challenge types that have failed, the next order falls back to the others
+        let mut failed: Vec<ChallengeType> = Vec::new();
+        loop {
+            let order = self.new_order(request)?;
+            if order.status != OrderStatus::Pending {
+                // the authorizations are still valid from a previous order
+                log::info!(
+                    r#"{{"op":"validation skipped","order":"{}","status":"{}"}}"#,
+                    order.url,
+                    order.status
+                );
+                return Ok(order);
+            }
+            let challenges = self.pending_challenges(&order, request, zones, &failed, false)?;
+            for c in challenges
+                .iter()
+                .filter(|c| c.challenge == ChallengeType::Dns01)
+            {
+                log::info!(
+                    r#"{{"op":"dns challenge","domain":"{}","record":"{}","zone":"{}","value":"{}"}}"#,
+                    c.domain,
+                    c.record,
+                    c.zone.as_deref().unwrap_or_default(),
+                    c.value
+                );
+            }
+            // served after the hooks, which may stop the web server holding port 80,
+            // and until the authorizations are done with
+            let solved = request
+                .run_validation_hooks(HookStage::PreValidation, &challenges)
+                .map_err(AcmeError::Other)
+                .and_then(|_| {
+                    self.serve_http01(&challenges)
+                        .and_then(|_serving| self.solve(order, &challenges))
+                });
+            let solved = post_validation(request, &challenges, solved)?;
+            match solved {
+                Err(AcmeError::ChallengeFailed {
+                    domain, challenge, ..
+                }) if self.has_fallback(request, &domain, &failed, challenge) => {
+                    log::warn!(
+                        r#"{{"op":"challenge fallback","domain":"{}","failed":"{}"}}"#,
+                        domain,
+                        challenge.name()
+                    );
+                    failed.push(challenge);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Serves the key authorizations of the http-01 `challenges`, if there are any.
+    fn serve_http01(
+        &self,
+        challenges: &[PendingChallenge],
+    ) -> Result<Option<http01::Serving>, AcmeError> {
+        let tokens: HashMap<String, String> = challenges
+            .iter()
+            .filter(|c| c.challenge == ChallengeType::Http01)
+            .map(|c| (c.token.to_owned(), c.value.to_owned()))
+            .collect();
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+        for c in challenges
+            .iter()
+            .filter(|c| c.challenge == ChallengeType::Http01)
+        {
+            log::info!(
+                r#"{{"op":"http challenge","domain":"{}","path":"{}"}}"#,
+                c.domain,
+                c.record
+            );
+        }
+        self.http01.serve(tokens).map(Some)
+    }
+
+    /// Whether the `challenge` can be solved here for the `request`, and if not, why.
+    /// There is no DNS provider built in, the pre-validation hooks publish the TXT
+    /// records. They may also stop the web server holding port 80, so with them
+    /// around, a busy port doesn't rule http-01 out.
+    fn challenge_unavailable(
+        &self,
+        request: &CertRequest,
+        challenge: ChallengeType,
+    ) -> Option<String> {
+        let hooks = !request.pre_validation_hooks.is_empty();
+        match challenge {
+            ChallengeType::Dns01 if hooks => None,
+            ChallengeType::Dns01 => {
+                Some("no pre-validation hooks to publish the TXT records".to_string())
+            }
+            ChallengeType::Http01 if hooks && matches!(self.http01, Http01::Standalone(_)) => None,
+            ChallengeType::Http01 => self.http01.unavailable(),
+        }
+    }
+
+    /// Whether there is a challenge type to fall back to for the `domain` once the
+    /// `current` one has failed too.
+    fn has_fallback(
+        &self,
+        request: &CertRequest,
+        domain: &str,
+        failed: &[ChallengeType],
+        current: ChallengeType,
+    ) -> bool {
+        request.challenge_prefs(domain).into_iter().any(|t| {
+            t != current && !failed.contains(&t) && self.challenge_unavailable(request, t).is_none()
+        })
+    }
+
+    /// Capabilities that need an account to be probed, once per directory.
+    fn probe_capabilities(&mut self) -> Result<(), AcmeError> {
+        if self.capabilities.post_as_get_only.is_none() {
+            if let Some(kid) = &self.kid {
+                self.capabilities.probe_post_as_get(kid);
+                self.capabilities.save(self.store)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Caches whether the CA honors `notBefore`/`notAfter`, as an order asking for them
+    /// has shown. They can't be discovered otherwise.
+    fn record_validity_window(&mut self, honored: bool) -> Result<(), AcmeError> {
+        if self.capabilities.validity_window == Some(honored) {
+            return Ok(());
+        }
+        self.capabilities.validity_window = Some(honored);
+        self.capabilities.save(self.store)
+    }
+
+    /// Places a new order for the `request`.
+    fn new_order(&mut self, request: &CertRequest) -> Result<Order, AcmeError> {
+        let name = request.name()?.to_string();
         #[derive(Debug, Serialize, Deserialize)]
         struct OrderReq {
             identifiers: Vec<Identifier>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            profile: Option<String>,
+            #[serde(rename = "notBefore", skip_serializing_if = "Option::is_none")]
+            not_before: Option<String>,
+            #[serde(rename = "notAfter", skip_serializing_if = "Option::is_none")]
+            not_after: Option<String>,
+        }
+        let mut profile = request.profile_name.to_owned();
+        let mut not_after = request.not_after.to_owned();
+        if request.short_lived && profile.is_none() && not_after.is_none() {
+            // the profile of the CA, or else an explicit validity window
+            if self
+                .directory
+                .meta()
+                .profiles
+                .contains_key(SHORT_LIVED_PROFILE)
+            {
+                profile = Some(SHORT_LIVED_PROFILE.to_string());
+            } else if self.capabilities.validity_window != Some(false) {
+                let expires = chrono::Utc::now() + chrono::Duration::days(SHORT_LIVED_DAYS);
+                not_after = Some(expires.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+            } else {
+                return Err(AcmeError::Other(anyhow!(
+                    "the CA doesn't offer short-lived certificates"
+                )));
+            }
+        }
+        let window = request.not_before.is_some() || not_after.is_some();
+        if window {
+            if self.capabilities.validity_window == Some(false) {
+                return Err(AcmeError::Other(anyhow!(
+                    "the CA doesn't honor notBefore/notAfter in orders"
+                )));
+            }
+            request.check_validity()?;
+        }
+        if let Some(profile) = &profile {
+            // the directory is fresh, unlike the cached capabilities
+            let profiles = self.directory.meta().profiles;
+            if !profiles.contains_key(profile) {
+                return Err(AcmeError::Other(anyhow!(
+                    "the CA doesn't offer the {} profile, available: {:?}",
+                    profile,
+                    profiles.keys().collect::<Vec<&String>>()
+                )));
+            }
         }
         let mut ids: Vec<Identifier> = Vec::new();
-        for domain in domains {
+        let mut registered: Vec<String> = Vec::new();
+        let ca = Ca::from_directory_url(&self.directory.url);
+        for domain in request.identifiers.iter().cloned() {
+            psl::check_wildcard(&domain)?;
+            if domain.starts_with("*.") && matches!(ca, Some(c) if !c.supports_wildcards()) {
+                return Err(AcmeError::Other(anyhow!(
+                    "the CA doesn't issue wildcard certificates: {}",
+                    domain
+                )));
+            }
+            if let Some(r) = psl::registered_domain(&domain) {
+                if !registered.contains(&r) {
+                    registered.push(r);
+                }
+            }
             ids.push(Identifier {
                 _type: "dns".to_string(),
                 value: domain,
             });
         }
-        let payload =
-            serde_json::to_string(&OrderReq { identifiers: ids }).map_err(AcmeError::JsonDecode)?;
-        let (status_code, response) = self.request("newOrder", payload)?;
-        if http_status_ok(status_code) {
-            let order: Order = serde_json::from_str(&response).map_err(AcmeError::JsonDecode)?;
-            for auth in &order.authorizations {
-                let a = self.authorization(&auth)?;
-                for c in &a.challenges {
-                    if c._type == "dns-01" {
-                        let ka = self.key_authorization(&c.token);
-                        self.trigger_challenge(&c.url);
-                        let two_seconds = time::Duration::new(2, 0);
-                        thread::sleep(two_seconds);
-                        self.challenge_status(&c.url);
-                    }
+        log::info!(r#"{{"op":"order","registered_domains":{:?}}}"#, registered);
+        let names: Vec<String> = ids.iter().map(|i| i.value.to_owned()).collect();
+        self.throttle(ca, &names)?;
+        let payload = serde_json::to_value(&OrderReq {
+            identifiers: ids,
+            profile,
+            not_before: request.not_before.to_owned(),
+            not_after,
+        })
+        .map_err(AcmeError::JsonDecode)?;
+        let started = Instant::now();
+        let response = match self.request(Request::Post("newOrder", payload)) {
+            Err(AcmeError::Problem(problem)) if window && rejects_validity_window(&problem) => {
+                self.record_validity_window(false)?;
+                return Err(AcmeError::Problem(problem));
+            }
+            response => response?,
+        };
+        self.timings.record(Phase::NewOrder, started);
+        if !http_status_ok(response.status) {
+            return Err(AcmeError::Other(anyhow!(
+                "order failed: {:?}",
+                response.body
+            )));
+        }
+        let mut order: Order =
+            serde_json::from_str(&response.body).map_err(AcmeError::JsonDecode)?;
+        order.url = response.location.unwrap_or_default();
+        order.key_type = request.key_type;
+        order.must_staple = request.must_staple;
+        order.name = name;
+        if window {
+            // the CAs that ignore the window leave it out of the order
+            self.record_validity_window(order.not_before.is_some() || order.not_after.is_some())?;
+        }
+        metrics::increment(Counter::Orders);
+        self.rate_limits.record_order();
+        self.rate_limits.save(self.store, &self.name)?;
+        Ok(order)
+    }
+
+    /// Picks a challenge for every authorization of the `order` that isn't valid yet:
+    /// the first one by the challenge preferences of the `request` that is offered, can
+    /// be solved here (unless they are solved `by_hand`), and hasn't `failed` before.
+    /// The dns-01 ones come with the zone of their record, as the `zones` resolve it.
+    fn pending_challenges(
+        &mut self,
+        order: &Order,
+        request: &CertRequest,
+        zones: &ZoneResolver,
+        failed: &[ChallengeType],
+        by_hand: bool,
+    ) -> Result<Vec<PendingChallenge>, AcmeError> {
+        let mut pending = Vec::new();
+        for auth in &order.authorizations {
+            let a = self.authorization(&auth)?;
+            if a.status == AuthzStatus::Valid {
+                continue;
+            }
+            let prefs = request.challenge_prefs(&a.identifier.value);
+            let mut chosen = None;
+            for t in prefs.iter().filter(|t| !failed.contains(t)) {
+                let offered = match a.challenges.iter().find(|c| c._type == t.name()) {
+                    Some(c) => c,
+                    None => continue,
+                };
+                let unavailable = if by_hand {
+                    None
+                } else {
+                    self.challenge_unavailable(request, *t)
+                };
+                if let Some(reason) = unavailable {
+                    log::warn!(
+                        r#"{{"op":"challenge skipped","domain":"{}","challenge":"{}","reason":"{}"}}"#,
+                        a.identifier.value,
+                        t.name(),
+                        reason
+                    );
+                    continue;
                 }
+                chosen = Some((*t, offered));
+                break;
             }
-            Ok(())
-        } else {
-            Err(AcmeError::Other(anyhow!("order failed: {:?}", response)))
+            let (t, c) = chosen.ok_or_else(|| {
+                anyhow!(
+                    "none of the preferred challenges can be solved for {}",
+                    a.identifier.value
+                )
+            })?;
+            let key_authorization = self.key_authorization(&c.token);
+            let (record, value, zone) = match t {
+                ChallengeType::Dns01 => (
+                    dns::challenge_record(&a.identifier.value),
+                    dns_txt_value(&key_authorization),
+                    zones.zone_for(&a.identifier.value),
+                ),
+                ChallengeType::Http01 => (
+                    format!("{}{}", http01::PATH, c.token),
+                    key_authorization,
+                    None,
+                ),
+            };
+            pending.push(PendingChallenge {
+                domain: a.identifier.value.to_owned(),
+                challenge: t,
+                authorization: auth.to_owned(),
+                url: c.url.to_owned(),
+                token: c.token.to_owned(),
+                record,
+                value,
+                zone,
+            });
+        }
+        Ok(pending)
+    }
+
+    /// Tells the CA the `challenges` are ready to be validated, and waits for
+    /// the authorizations, and then the order, to become valid. Up to `concurrency`
+    /// challenges are validated at once: the CA does the slow part, so their
+    /// authorizations are just polled in turns. Once the order timeout has passed,
+    /// the authorizations left are deactivated, and `OrderTimeout` is returned.
+    fn solve(&mut self, order: Order, challenges: &[PendingChallenge]) -> Result<Order, AcmeError> {
+        let deadline = Instant::now() + self.order_timeout;
+        let mut queue = challenges.iter();
+        let mut in_flight: Vec<InFlight<'_>> = Vec::new();
+        loop {
+            while in_flight.len() < self.concurrency {
+                let challenge = match queue.next() {
+                    Some(challenge) => challenge,
+                    None => break,
+                };
+                let started = Instant::now();
+                self.trigger_challenge(&challenge.url)?;
+                let authorization = self.authorization(&challenge.authorization)?;
+                // past the deadline, the next round of polling cancels the order
+                let next = self
+                    .next_poll(&authorization, 0, deadline)
+                    .unwrap_or(deadline);
+                in_flight.push(InFlight {
+                    challenge,
+                    authorization,
+                    started,
+                    attempt: 0,
+                    next,
+                });
+            }
+            let mut i = 0;
+            while i < in_flight.len() {
+                if in_flight[i].authorization.status == AuthzStatus::Pending {
+                    i += 1;
+                    continue;
+                }
+                let done = in_flight.swap_remove(i);
+                self.settle(done)?;
+            }
+            let next = match in_flight.iter().map(|f| f.next).min() {
+                Some(next) => next,
+                None if queue.len() == 0 => break,
+                None => continue,
+            };
+            let now = Instant::now();
+            if next > now {
+                self.pause(next - now)?;
+            }
+            let now = Instant::now();
+            let mut timed_out = false;
+            for f in in_flight.iter_mut().filter(|f| f.next <= now) {
+                f.authorization = self.authorization(&f.authorization.url)?;
+                f.attempt += 1;
+                log::info!(
+                    r#"{{"op":"authorization status","domain":"{}","status":"{}"}}"#,
+                    f.authorization.identifier.value,
+                    f.authorization.status
+                );
+                match self.next_poll(&f.authorization, f.attempt, deadline) {
+                    Some(next) => f.next = next,
+                    None => timed_out = true,
+                }
+            }
+            if timed_out {
+                let left = in_flight
+                    .iter()
+                    .filter(|f| f.authorization.status == AuthzStatus::Pending)
+                    .map(|f| f.challenge)
+                    .chain(queue)
+                    .map(|c| c.authorization.to_owned())
+                    .collect::<Vec<String>>();
+                return Err(self.cancel(&order, &left));
+            }
+        }
+        let order = self.poll_order(order, &[OrderStatus::Pending])?;
+        if order.status == OrderStatus::Invalid {
+            return Err(AcmeError::Other(anyhow!(
+                "order {} is invalid, some of the authorizations failed",
+                order.url
+            )));
+        }
+        Ok(order)
+    }
+
+    fn fetch_order(&mut self, url: &str) -> Result<Order, AcmeError> {
+        let response = self.request(Request::Get(url))?;
+        let mut order: Order =
+            serde_json::from_str(&response.body).map_err(AcmeError::JsonDecode)?;
+        order.url = url.to_owned();
+        order.retry_after = response.retry_after;
+        Ok(order)
+    }
+
+    /// Polls the order as long as it stays in one of the `waiting` statuses, checking
+    /// every transition on the way. Gives up once the backoff timeout has passed.
+    fn poll_order(&mut self, order: Order, waiting: &[OrderStatus]) -> Result<Order, AcmeError> {
+        let deadline = self.backoff.deadline();
+        let mut order = order;
+        let mut attempt = 0;
+        while waiting.contains(&order.status) {
+            let delay = self
+                .backoff
+                .poll_delay(attempt, order.retry_after, deadline)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "order {} is still {} after {}s",
+                        order.url,
+                        order.status,
+                        self.backoff.timeout.as_secs()
+                    )
+                })?;
+            self.pause(delay)?;
+            attempt += 1;
+            let next = self.fetch_order(&order.url)?;
+            order.check_transition(&next)?;
+            order = next;
+        }
+        Ok(order)
+    }
+
+    /// Sends the DER encoded `csr` to the order's finalize URL.
+    fn finalize(&mut self, order: &Order, csr: &[u8]) -> Result<Order, AcmeError> {
+        let payload = serde_json::json!({ "csr": jws::b64(csr) });
+        let response = self.request(Request::Post(&order.finalize, payload))?;
+        let mut finalized: Order =
+            serde_json::from_str(&response.body).map_err(AcmeError::JsonDecode)?;
+        finalized.url = order.url.to_owned();
+        finalized.retry_after = response.retry_after;
+        Ok(finalized)
+    }
+
+    /// Downloads the certificate chain, with a plain GET from the CAs that aren't
+    /// strict about POST-as-GET: it takes no nonce nor signature. POST-as-GET is
+    /// the fallback, should the CA refuse it after all.
+    fn download_certificate(&mut self, url: &str) -> Result<String, AcmeError> {
+        if self.capabilities.post_as_get_only == Some(false) {
+            let response = http::timed(url, || {
+                http::agent()
+                    .get(url)
+                    .set("User-Agent", &http_user_agent())
+                    .call()
+            });
+            match response.and_then(|response| response.into_string().map_err(Into::into)) {
+                Ok(chain) => return Ok(chain),
+                Err(e) => log::warn!(r#"{{"op":"certificate get","error":"{}"}}"#, e),
+            }
+        }
+        Ok(self.request(Request::Get(url))?.body)
+    }
+
+    /// Stores the certificate under the `name`, along with its intermediates, and
+    /// its key and order if they are known.
+    pub fn save_certificate(
+        &self,
+        name: &str,
+        issued: &IssuedCertificate,
+    ) -> Result<(), AcmeError> {
+        if let Some(key_pem) = &issued.key_pem {
+            self.store
+                .write(ObjectKind::CertKey, name, key_pem.as_bytes())
+                .map_err(AcmeError::Store)?;
+        }
+        self.store
+            .write(ObjectKind::Certificate, name, issued.chain_pem.as_bytes())
+            .map_err(AcmeError::Store)?;
+        let certs = crate::cert::chain_der(&issued.chain_pem)?;
+        let intermediates = crate::cert::pem(certs.get(1..).unwrap_or_default());
+        self.store
+            .write(ObjectKind::Chain, name, intermediates.as_bytes())
+            .map_err(AcmeError::Store)?;
+        if let Some(order) = &issued.order {
+            self.store
+                .write(ObjectKind::Order, name, order.as_bytes())
+                .map_err(AcmeError::Store)?;
         }
+        Ok(())
     }
 
     fn authorization(&mut self, url: &str) -> Result<Authorization, AcmeError> {
-        let (status_code, response) = self.request(url, "".to_string())?;
-        if http_status_ok(status_code) {
-            Ok(serde_json::from_str(&response).map_err(AcmeError::JsonDecode)?)
+        let response = self.request(Request::Get(url))?;
+        if http_status_ok(response.status) {
+            let mut authorization: Authorization =
+                serde_json::from_str(&response.body).map_err(AcmeError::JsonDecode)?;
+            authorization.url = url.to_owned();
+            authorization.retry_after = response.retry_after;
+            Ok(authorization)
         } else {
             Err(AcmeError::Other(anyhow!(
                 "authorization failed: {:?}",
-                response
+                response.body
             )))
         }
     }
 
-    fn trigger_challenge(&mut self, url: &str) {
-        let (status_code, response) = self.request(url, "{}".to_string()).unwrap();
+    fn trigger_challenge(&mut self, url: &str) -> Result<(), AcmeError> {
+        let response = self.request(Request::Post(url, serde_json::json!({})))?;
         log::info!(
             r#"{{"op":"challenge start","status":{},"response":{}}}"#,
-            status_code,
-            response
+            response.status,
+            response.body
+        );
+        Ok(())
+    }
+
+    /// When to poll the `authorization` next, unless it's no longer `pending`.
+    /// `None` once the `deadline` has passed.
+    fn next_poll(
+        &self,
+        authorization: &Authorization,
+        attempt: u32,
+        deadline: Instant,
+    ) -> Option<Instant> {
+        if authorization.status != AuthzStatus::Pending {
+            return Some(Instant::now());
+        }
+        let delay = self
+            .backoff
+            .poll_delay(attempt, authorization.retry_after, deadline)?;
+        Some(Instant::now() + delay)
+    }
+
+    /// Gives up on the `order`: its `authorizations` left pending are
+    /// [deactivated](https://tools.ietf.org/html/rfc8555#section-7.5.2), so they
+    /// don't count against the pending authorizations limit of the CA.
+    fn cancel(&mut self, order: &Order, authorizations: &[String]) -> AcmeError {
+        for url in authorizations {
+            let deactivate = serde_json::json!({ "status": "deactivated" });
+            if let Err(e) = self.request(Request::Post(url, deactivate)) {
+                log::warn!(
+                    r#"{{"op":"authorization deactivation","url":"{}","error":"{}"}}"#,
+                    url,
+                    e
+                );
+            }
+        }
+        AcmeError::OrderTimeout {
+            order: order.url.to_owned(),
+            timeout: self.order_timeout.as_secs(),
+        }
+    }
+
+    /// Records the outcome of a challenge whose authorization is no longer pending.
+    fn settle(&mut self, done: InFlight<'_>) -> Result<(), AcmeError> {
+        self.timings.record(Phase::Challenge, done.started);
+        let a = done.authorization;
+        if a.status == AuthzStatus::Valid {
+            metrics::increment(Counter::ChallengesValid);
+            return Ok(());
+        }
+        metrics::increment(Counter::ChallengesInvalid);
+        let problem = a
+            .challenges
+            .iter()
+            .find_map(|c| c.error.as_ref())
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| format!("authorization is {}", a.status));
+        Err(AcmeError::ChallengeFailed {
+            domain: a.identifier.value,
+            challenge: done.challenge.challenge,
+            problem,
+        })
+    }
+
+    /// The current terms of service of the CA, if it has any.
+    pub fn current_terms(&self) -> Option<String> {
+        self.directory.meta().terms_of_service
+    }
+
+    /// Agrees to the (updated) terms of service of the CA. That's the action
+    /// CAs usually ask for with the `userActionRequired` error.
+    /// The agreed URL is kept with the account.
+    pub fn agree_tos(&mut self) -> Result<(), AcmeError> {
+        let url = self
+            .kid
+            .to_owned()
+            .ok_or_else(|| anyhow!("account is not registered"))?;
+        let payload = serde_json::json!({ "termsOfServiceAgreed": true });
+        let response = self.request(Request::Post(&url, payload))?;
+        log::info!(
+            r#"{{"op":"terms of service agreed","status":{}}}"#,
+            response.status
         );
+        self.terms_of_service = self.current_terms();
+        self.save()
     }
 
-    fn challenge_status(&mut self, url: &str) {
-        let (status_code, response) = self.request(url, "".to_string()).unwrap();
+    /// [Deactivates](https://tools.ietf.org/html/rfc8555#section-7.3.6) the account,
+    /// the CA won't accept its requests anymore. There is no undoing it.
+    pub fn deactivate(&mut self) -> Result<(), AcmeError> {
+        let url = self
+            .kid
+            .to_owned()
+            .ok_or_else(|| anyhow!("account is not registered"))?;
+        let payload = serde_json::json!({ "status": "deactivated" });
+        let response = self.request(Request::Post(&url, payload))?;
         log::info!(
-            r#"{{"op":"challenge status","status":{},"response":{}}}"#,
-            status_code,
-            response
+            r#"{{"op":"account deactivated","status":{}}}"#,
+            response.status
         );
+        Ok(())
     }
 
-    pub fn info(&mut self) {
-        let url = self.kid.as_ref().unwrap().to_owned();
-        let (status_code, response) = self.request(&url, "".to_string()).unwrap();
+    /// [Revokes](https://tools.ietf.org/html/rfc8555#section-7.6) the leaf certificate
+    /// of the PEM encoded `chain`, for the `reason` if there is one.
+    pub fn revoke(&mut self, chain: &str, reason: Option<Reason>) -> Result<(), AcmeError> {
+        let leaf = crate::cert::chain_der(chain)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("no certificate to revoke"))?;
+        let mut payload = serde_json::json!({ "certificate": jws::b64(&leaf) });
+        if let Some(reason) = reason {
+            payload["reason"] = reason.code().into();
+        }
+        let response = self.request(Request::Post("revokeCert", payload))?;
         log::info!(
-            r#"{{"op":"account info","status":{},"response":{}}}"#,
-            status_code,
-            response
+            r#"{{"op":"certificate revoked","status":{},"reason":{}}}"#,
+            response.status,
+            reason
+                .map(|r| r.code().to_string())
+                .unwrap_or_else(|| "null".to_string())
         );
+        Ok(())
     }
 
-    /// Generates an ECDSA (P-265 curve) keypair.
-    fn generate_keypair() -> Result<(EcdsaKeyPair, Vec<u8>), AcmeError> {
-        // Generate a key pair in PKCS#8 (v2) format.
-        let rng = rand::SystemRandom::new();
-        let alg = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
-        let pkcs8 = EcdsaKeyPair::generate_pkcs8(alg, &rng).map_err(AcmeError::KeyGen)?;
-        let key_pair =
-            EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref()).map_err(AcmeError::KeyDecode)?;
-        Ok((key_pair, pkcs8.as_ref().to_owned()))
+    /// Fetches the window the CA suggests to renew the leaf certificate of the `chain`
+    /// in, with [ARI](https://datatracker.ietf.org/doc/draft-ietf-acme-ari/).
+    /// `None` if the CA doesn't offer the `renewalInfo` resource.
+    pub fn renewal_info(&self, chain: &str) -> Result<Option<RenewalWindow>, AcmeError> {
+        let base = match self.directory.url_for("renewalInfo") {
+            Some(base) if self.capabilities.renewal_info => base,
+            _ => return Ok(None),
+        };
+        let url = format!(
+            "{}/{}",
+            base.trim_end_matches('/'),
+            crate::cert::ari_id(chain)?
+        );
+        let response = http::timed(&url, || {
+            http::agent()
+                .get(&url)
+                .set("User-Agent", &http_user_agent())
+                .call()
+        })
+        .map_err(AcmeError::Api)?;
+        let info: serde_json::Value = response.into_json().map_err(AcmeError::JsonEncode)?;
+        Ok(Some(
+            serde_json::from_value(info["suggestedWindow"].clone())
+                .map_err(AcmeError::JsonDecode)?,
+        ))
     }
 
-    fn register(&mut self) -> Result<(), AcmeError> {
+    /// Looks the account up by its key with
+    /// [onlyReturnExisting](https://tools.ietf.org/html/rfc8555#section-7.3.1),
+    /// to catch the drift between the stored account and the CA's idea of it. CAs turn
+    /// the key of a deactivated account away as unauthorized, it's reported as such.
+    pub fn lookup(&mut self) -> Result<AccountLookup, AcmeError> {
+        // newAccount requests are signed with the JWK, not the kid
+        let stored = self.kid.take();
+        let response = self.request(Request::Post(
+            "newAccount",
+            serde_json::json!({ "onlyReturnExisting": true }),
+        ));
+        let found = std::mem::replace(&mut self.kid, stored);
+        let response = match response {
+            Ok(response) => response,
+            Err(AcmeError::Problem(problem)) if problem.is("accountDoesNotExist") => {
+                return Ok(AccountLookup {
+                    kid: None,
+                    kid_matches: false,
+                    status: None,
+                })
+            }
+            Err(AcmeError::Problem(problem)) if problem.is("unauthorized") => {
+                return Ok(AccountLookup {
+                    kid_matches: self.kid.is_some(),
+                    kid: self.kid.clone(),
+                    status: Some("deactivated".to_string()),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+        let body: serde_json::Value =
+            serde_json::from_str(&response.body).map_err(AcmeError::JsonDecode)?;
+        Ok(AccountLookup {
+            kid_matches: found.is_some() && found == self.kid,
+            kid: found,
+            status: body["status"].as_str().map(str::to_string),
+        })
+    }
+
+    /// Fetches the [account object](https://tools.ietf.org/html/rfc8555#section-7.1.2).
+    /// CAs that don't report when the account was created get the stored date.
+    pub fn info(&mut self) -> Result<AccountInfo, AcmeError> {
+        let url = self
+            .kid
+            .to_owned()
+            .ok_or_else(|| anyhow!("account is not registered"))?;
+        let response = self.request(Request::Get(&url))?;
+        let mut info: AccountInfo =
+            serde_json::from_str(&response.body).map_err(AcmeError::JsonDecode)?;
+        info.kid = url;
+        info.directory_url = self.directory.url.to_owned();
+        if info.created_at.is_none() {
+            info.created_at = self
+                .store
+                .read(ObjectKind::Created, &self.name)
+                .ok()
+                .and_then(|created| String::from_utf8(created).ok());
+        }
+        Ok(info)
+    }
+
+    fn register(&mut self, contacts: &[String]) -> Result<(), AcmeError> {
         #[derive(Debug, Serialize, Deserialize)]
         struct Registration {
             contact: Vec<String>,
-            #[serde(rename = "termsOfServiceAgreed")]
-            terms_of_service_agreed: bool,
+            #[serde(
+                rename = "termsOfServiceAgreed",
+                skip_serializing_if = "Option::is_none"
+            )]
+            terms_of_service_agreed: Option<bool>,
+            #[serde(
+                rename = "externalAccountBinding",
+                skip_serializing_if = "Option::is_none"
+            )]
+            external_account_binding: Option<serde_json::Value>,
         }
-        let payload = serde_json::to_string(&Registration {
-            contact: vec![format!("mailto:{}", self.email.to_owned())],
-            terms_of_service_agreed: true,
+        let external_account_binding = match &self.eab {
+            None => None,
+            Some(eab) => Some(jws::eab(
+                &eab.kid,
+                &eab.hmac_key,
+                self.directory.url_for("newAccount").unwrap_or_default(),
+                self.key_pair.jwk()?,
+            )?),
+        };
+        let mut contact = vec![format!("mailto:{}", self.email)];
+        for c in contacts {
+            let c = format!("mailto:{}", c);
+            if !contact.contains(&c) {
+                contact.push(c);
+            }
+        }
+        let payload = serde_json::to_value(&Registration {
+            contact,
+            terms_of_service_agreed: self.terms_of_service.as_ref().map(|_| true),
+            external_account_binding,
         })
         .map_err(AcmeError::JsonDecode)?;
-        let (status_code, response) = self.request("newAccount", payload)?;
-        if http_status_ok(status_code) {
+        let response = self.request(Request::Post("newAccount", payload))?;
+        if http_status_ok(response.status) {
             Ok(())
         } else {
             Err(AcmeError::Other(anyhow!(
                 "registration failed: {:?}",
-                response
+                response.body
             )))
         }
     }
@@ -319,68 +1818,251 @@ impl<'a> Account<'a> {
     /// Function to calculate [Key Authorization](https://tools.ietf.org/html/rfc8555#section-8.1). Basically, it's a token from the challenge + base64url encoded SHA256 hash
     /// of the jwk.
     pub fn key_authorization(&self, token: &str) -> String {
-        let jwk = jws::jwk(self.key_pair.public_key().as_ref())
-            .unwrap()
-            .to_string();
+        let jwk = self.key_pair.jwk().unwrap().to_string();
         let hash = digest::digest(&digest::SHA256, jwk.as_bytes());
         let key_authorization = format!("{}.{}", token, jws::b64(hash.as_ref()));
         key_authorization
     }
 
-    fn get_nonce(&self) -> Result<String, AcmeError> {
-        let url = self.directory.url_for("newNonce").unwrap();
-        let agent = ureq::AgentBuilder::new().build();
-        let response = agent
-            .head(url)
-            .set("User-Agent", &http_user_agent())
-            .call()
-            .map_err(AcmeError::Api)?;
-        let nonce = response.header("Replay-Nonce").unwrap();
-        Ok(nonce.to_string())
+    fn get_nonce(&mut self) -> Result<Nonce, AcmeError> {
+        let url = self.directory.url_for("newNonce").unwrap().to_string();
+        let url = url.as_str();
+        let started = Instant::now();
+        let response = http::timed(url, || {
+            http::agent()
+                .head(url)
+                .set("User-Agent", &http_user_agent())
+                .call()
+        })
+        .map_err(AcmeError::Api)?;
+        self.timings.record(Phase::Nonce, started);
+        let nonce = response
+            .header("Replay-Nonce")
+            .ok_or_else(|| anyhow!("no Replay-Nonce from {}", url))?;
+        Nonce::parse(nonce, url)
     }
 
-    fn request(&mut self, resource: &str, payload: String) -> Result<(u16, String), AcmeError> {
-        let url = match self.directory.url_for(resource) {
-            None => resource,
-            Some(u) => u,
+    /// Sends the signed `request`. Requests rejected with `badNonce` are retried with the fresh nonce from
+    /// the error response, as [RFC8555](https://tools.ietf.org/html/rfc8555#section-6.5) suggests.
+    /// 503 and 429 responses are retried according to the backoff policy.
+    fn request(&mut self, request: Request<'_>) -> Result<ApiResponse, AcmeError> {
+        let mut bad_nonces = 0;
+        let mut attempt = 0;
+        loop {
+            let response = self.send_signed(&request)?;
+            if http_status_ok(response.status) {
+                return Ok(response);
+            }
+            let status = response.status;
+            let retry_after = response.retry_after;
+            let error = response.into_error();
+            match &error {
+                AcmeError::Problem(problem)
+                    if problem.is("badNonce") && bad_nonces < BAD_NONCE_RETRIES =>
+                {
+                    bad_nonces += 1;
+                    metrics::increment(Counter::Retries);
+                    log::warn!(
+                        r#"{{"op":"bad nonce retry","attempt":{},"nonce":"{}"}}"#,
+                        bad_nonces,
+                        self.used_nonce
+                            .as_ref()
+                            .map(Nonce::to_string)
+                            .unwrap_or_default()
+                    );
+                    continue;
+                }
+                _ if status == 503 || status == 429 => {
+                    if let Some(delay) = self.backoff.delay(attempt, retry_after) {
+                        attempt += 1;
+                        metrics::increment(Counter::Retries);
+                        log::warn!(
+                            r#"{{"op":"backoff retry","status":{},"attempt":{},"delay":{}}}"#,
+                            status,
+                            attempt,
+                            delay.as_secs()
+                        );
+                        self.pause(delay)?;
+                        continue;
+                    }
+                }
+                _ => (),
+            }
+            if let AcmeError::Problem(problem) = &error {
+                if problem.is("rateLimited") {
+                    self.rate_limits.record_rate_limited(retry_after);
+                    self.rate_limits.save(self.store, &self.name)?;
+                }
+            }
+            return Err(error);
+        }
+    }
+
+    fn send_signed(&mut self, request: &Request<'_>) -> Result<ApiResponse, AcmeError> {
+        let resource = request.resource();
+        let payload = request.payload()?;
+        let url = self
+            .directory
+            .url_for(resource)
+            .unwrap_or(resource)
+            .to_string();
+        // a nonce can be used only once
+        let nonce = match self.nonce.take() {
+            Some(nonce) if !nonce.is_stale(self.nonce_max_age) => nonce,
+            Some(stale) => {
+                log::debug!(r#"{{"op":"stale nonce dropped","nonce":"{}"}}"#, stale);
+                self.get_nonce()?
+            }
+            None => self.get_nonce()?,
         };
-        let nonce = self.nonce.as_ref().unwrap();
-        let body = if !payload.is_empty() {
-            payload.clone()
-        } else {
-            "\"\"".to_string()
+        let body = match request {
+            Request::Get(_) => "\"\"",
+            Request::Post(..) => payload.as_str(),
         };
         log::debug!(r#"{{"op":"request","url":"{}","body":{}}}"#, url, body);
-        let jws = jws::sign(&self.key_pair, &nonce, &url, payload, self.kid.as_deref())
-            .map_err(AcmeError::Other)?;
-        let agent = ureq::AgentBuilder::new().build();
-        let response = agent
-            .post(url)
-            .set("User-Agent", &http_user_agent())
-            .set("Content-Type", "application/jose+json")
-            .send_string(&jws)
-            .map_err(AcmeError::Api)?;
-        let nonce = response.header("Replay-Nonce").unwrap();
-        self.nonce = Some(nonce.to_string());
+        metrics::increment(Counter::Requests);
+        let jws = jws::sign(
+            &self.key_pair,
+            nonce.value(),
+            &url,
+            payload,
+            self.kid.as_deref(),
+        )
+        .map_err(AcmeError::Other)?;
+        self.used_nonce = Some(nonce);
+        let response = match http::timed(&url, || {
+            http::agent()
+                .post(&url)
+                .set("User-Agent", &http_user_agent())
+                .set("Content-Type", "application/jose+json")
+                .send_string(&jws)
+        }) {
+            Ok(response) => response,
+            // error responses carry a problem document (and a fresh nonce)
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(e) => return Err(AcmeError::Api(e)),
+        };
+        self.nonce = match response
+            .header("Replay-Nonce")
+            .map(|n| Nonce::parse(n, &url))
+        {
+            Some(Ok(nonce)) => Some(nonce),
+            Some(Err(e)) => {
+                log::warn!(r#"{{"op":"nonce dropped","error":"{}"}}"#, e);
+                None
+            }
+            None => None,
+        };
         log::debug!(
             r#"{{"op":"request responded","status":{}}}"#,
             response.status()
         );
-        if http_status_ok(response.status()) {
-            if resource == "newAccount" {
-                let kid = response.header("Location").unwrap_or("none");
-                self.kid = Some(kid.to_string());
-            }
-            Ok((
-                response.status(),
-                response.into_string().map_err(AcmeError::JsonEncode)?,
-            ))
+        let mut violations = if self.strict {
+            strict::check_headers(request, &response)
         } else {
-            Err(AcmeError::Other(anyhow!("request failed: {:?}", response)))
+            Vec::new()
+        };
+        if http_status_ok(response.status()) && resource == "newAccount" {
+            let kid = response.header("Location").unwrap_or("none");
+            self.kid = Some(kid.to_string());
+        }
+        let response = ApiResponse {
+            status: response.status(),
+            location: response.header("Location").map(str::to_string),
+            retry_after: response
+                .header("Retry-After")
+                .and_then(backoff::parse_retry_after),
+            terms_of_service: link(&response, "terms-of-service"),
+            body: response.into_string().map_err(AcmeError::JsonEncode)?,
+        };
+        if self.strict {
+            violations.extend(strict::check_body(response.status, &response.body));
+            if !violations.is_empty() {
+                return Err(AcmeError::Strict { url, violations });
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// Runs the post-validation hooks whatever the outcome of the validation, a failed
+/// pre-validation hook included, e.g. a service stopped for it comes back. Their
+/// failure fails a validation that succeeded, a failed one keeps its own error.
+fn post_validation<T>(
+    request: &CertRequest,
+    challenges: &[PendingChallenge],
+    solved: Result<T, AcmeError>,
+) -> Result<Result<T, AcmeError>, AcmeError> {
+    match (
+        request.run_validation_hooks(HookStage::PostValidation, challenges),
+        solved,
+    ) {
+        (Err(e), Ok(_)) => Err(AcmeError::Other(e)),
+        (Err(e), Err(failed)) => {
+            log::error!(
+                r#"{{"op":"hook","stage":"post-validation","error":"{}"}}"#,
+                e
+            );
+            Ok(Err(failed))
         }
+        (Ok(()), solved) => Ok(solved),
+    }
+}
+
+/// Whether the CA has refused an order for its validity window, as Let's Encrypt does
+/// with `malformed: NotBefore and NotAfter are not supported`.
+fn rejects_validity_window(problem: &AcmeProblem) -> bool {
+    let detail = problem.detail.to_lowercase();
+    problem.is("malformed") && (detail.contains("notbefore") || detail.contains("notafter"))
+}
+
+/// The value of the `_acme-challenge` TXT record is the base64url encoded
+/// SHA256 digest of the key authorization.
+fn dns_txt_value(key_authorization: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, key_authorization.as_bytes());
+    jws::b64(hash.as_ref())
+}
+
+/// Returns the name the objects of the `email` account are stored under.
+/// Staging accounts are tagged, so they never collide with the production ones.
+pub fn store_name(email: &str, staging: bool) -> String {
+    if staging {
+        format!("{}.staging", email)
+    } else {
+        email.to_string()
     }
 }
 
+/// Decodes a recorded JWS (e.g. from debug logs or CA server logs) and verifies
+/// its signature against the stored key of the `account_name` account.
+pub fn decode_jws(
+    account_name: &str,
+    store: &dyn Store,
+    jws: &str,
+) -> Result<serde_json::Value, AcmeError> {
+    let pkcs8 = store
+        .read(ObjectKind::KeyPair, account_name)
+        .map_err(AcmeError::Store)?;
+    let key_pair = AccountKey::load(&pkcs8)?;
+    Ok(jws::decode(jws, &key_pair)?)
+}
+
+/// Finds the target of a `Link` header with the `rel` relation, i.e. for
+/// `Link: <https://example.com/acme/terms/2017-6-02>;rel="terms-of-service"`.
+fn link(response: &ureq::Response, rel: &str) -> Option<String> {
+    let relation = format!("rel=\"{}\"", rel);
+    response
+        .all("Link")
+        .into_iter()
+        .flat_map(|h| h.split(','))
+        .find(|l| l.contains(&relation))
+        .and_then(|l| {
+            let start = l.find('<')? + 1;
+            let end = l.find('>')?;
+            l.get(start..end).map(str::to_string)
+        })
+}
+
 fn http_status_ok(status: u16) -> bool {
     (200..300).contains(&status)
 }
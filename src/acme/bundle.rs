@@ -0,0 +1,108 @@
+//! bundles for the air-gapped mode: the order is placed on a machine that can reach
+//! the CA, its challenges are solved by hand inside the protected network, and then
+//! the order is resumed (again, with the CA reachable) to get the certificate.
+//!
+//! A bundle is signed with the account key, so the resuming side can tell the
+//! bundle it got back is the one exported with the account, and not a tampered one.
+//! ```text
+//! certifika order export some@email.com --domain example.com --out bundle.json
+//! # publish the TXT records listed in bundle.json
+//! certifika order resume some@email.com bundle.json
+//! ```
+use super::{jws, post_validation, Account, AcmeError, Order, OrderStatus, PendingChallenge};
+use crate::dns::ZoneResolver;
+use crate::request::{CertRequest, HookStage};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bundle {
+    pub version: u32,
+    /// the name of the account the order belongs to
+    pub account: String,
+    pub order_url: String,
+    pub expires: String,
+    pub request: CertRequest,
+    pub challenges: Vec<PendingChallenge>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+impl Bundle {
+    /// The bundle as it's signed, i.e. without the signature.
+    fn signed_content(&self) -> Result<Vec<u8>, AcmeError> {
+        let mut unsigned = self.clone();
+        unsigned.signature = None;
+        serde_json::to_vec(&unsigned).map_err(AcmeError::JsonDecode)
+    }
+}
+
+impl<'a> Account<'a> {
+    /// Places an order for the `request` and bundles it up with the challenges to solve,
+    /// the dns-01 ones with the zones the `zones` resolve.
+    pub fn export_order(
+        &mut self,
+        request: &CertRequest,
+        zones: &ZoneResolver,
+    ) -> Result<Bundle, AcmeError> {
+        let order = self.new_order(request)?;
+        let challenges = if order.status == OrderStatus::Pending {
+            // solved by hand, the prerequisites are of no concern here
+            self.pending_challenges(&order, request, zones, &[], true)?
+        } else {
+            Vec::new()
+        };
+        let mut bundle = Bundle {
+            version: BUNDLE_VERSION,
+            account: self.name.to_owned(),
+            order_url: order.url,
+            expires: order.expires,
+            request: request.clone(),
+            challenges,
+            signature: None,
+        };
+        let signature = self.key_pair.sign(&bundle.signed_content()?)?;
+        bundle.signature = Some(jws::b64(&signature));
+        Ok(bundle)
+    }
+
+    /// Checks the `bundle` was exported with this account, and picks
+    /// its order up where the export left it.
+    pub fn resume_order(&mut self, bundle: &Bundle) -> Result<Order, AcmeError> {
+        if bundle.version != BUNDLE_VERSION {
+            return Err(AcmeError::Other(anyhow!(
+                "unsupported bundle version {}",
+                bundle.version
+            )));
+        }
+        if bundle.account != self.name {
+            return Err(AcmeError::Other(anyhow!(
+                "the bundle belongs to the {} account",
+                bundle.account
+            )));
+        }
+        let signature = bundle
+            .signature
+            .as_deref()
+            .ok_or_else(|| anyhow!("the bundle is not signed"))
+            .and_then(jws::b64_decode)?;
+        if !self.key_pair.verify(&bundle.signed_content()?, &signature) {
+            return Err(AcmeError::Other(anyhow!("the bundle signature is invalid")));
+        }
+        let mut order = self.fetch_order(&bundle.order_url)?;
+        order.key_type = bundle.request.key_type;
+        order.must_staple = bundle.request.must_staple;
+        order.name = bundle.request.name()?.to_string();
+        if order.status != OrderStatus::Pending {
+            return Ok(order);
+        }
+        let request = &bundle.request;
+        let solved = request
+            .run_validation_hooks(HookStage::PreValidation, &bundle.challenges)
+            .map_err(AcmeError::Other)
+            .and_then(|_| self.solve(order, &bundle.challenges));
+        post_validation(request, &bundle.challenges, solved)?
+    }
+}
@@ -0,0 +1,138 @@
+//! presets for the well-known ACME certificate authorities.
+//!
+//! Some CAs require [External Account Binding](https://tools.ietf.org/html/rfc8555#section-7.3.4)
+//! to register an account. ZeroSSL hands out EAB credentials via its API, for
+//! Google Trust Services they have to be created in the Google Cloud console and
+//! passed with `--eab-kid`/`--eab-hmac-key` (or `CERTIFIKA_EAB_KID`/`CERTIFIKA_EAB_HMAC_KEY`).
+use super::{
+    http_user_agent, AcmeError, LETSENCRYPT_DIRECTORY_URL, LETSENCRYPT_STAGING_DIRECTORY_URL,
+};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::str::FromStr;
+
+pub const ZEROSSL_DIRECTORY_URL: &str = "https://acme.zerossl.com/v2/DV90";
+pub const ZEROSSL_EAB_URL: &str = "https://api.zerossl.com/acme/eab-credentials";
+pub const ZEROSSL_EAB_EMAIL_URL: &str = "https://api.zerossl.com/acme/eab-credentials-email";
+pub const BUYPASS_DIRECTORY_URL: &str = "https://api.buypass.com/acme/directory";
+pub const BUYPASS_STAGING_DIRECTORY_URL: &str = "https://api.test4.buypass.no/acme/directory";
+pub const GOOGLE_DIRECTORY_URL: &str = "https://dv.acme-v02.api.pki.goog/directory";
+pub const GOOGLE_STAGING_DIRECTORY_URL: &str = "https://dv.acme-v02.test-api.pki.goog/directory";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ca {
+    LetsEncrypt,
+    ZeroSsl,
+    Buypass,
+    Google,
+}
+
+/// External Account Binding credentials, issued by a CA out-of-band.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Eab {
+    pub kid: String,
+    pub hmac_key: String,
+}
+
+impl FromStr for Ca {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "letsencrypt" | "le" => Ok(Ca::LetsEncrypt),
+            "zerossl" => Ok(Ca::ZeroSsl),
+            "buypass" => Ok(Ca::Buypass),
+            "google" | "gts" => Ok(Ca::Google),
+            _ => Err(anyhow!("unknown CA: {}", name)),
+        }
+    }
+}
+
+impl Ca {
+    /// Returns the directory URL of the CA's production or `staging` environment.
+    pub fn directory_url(&self, staging: bool) -> Result<&'static str, AcmeError> {
+        match (self, staging) {
+            (Ca::LetsEncrypt, false) => Ok(LETSENCRYPT_DIRECTORY_URL),
+            (Ca::LetsEncrypt, true) => Ok(LETSENCRYPT_STAGING_DIRECTORY_URL),
+            (Ca::ZeroSsl, false) => Ok(ZEROSSL_DIRECTORY_URL),
+            (Ca::ZeroSsl, true) => Err(AcmeError::Other(anyhow!(
+                "ZeroSSL has no staging environment"
+            ))),
+            (Ca::Buypass, false) => Ok(BUYPASS_DIRECTORY_URL),
+            (Ca::Buypass, true) => Ok(BUYPASS_STAGING_DIRECTORY_URL),
+            (Ca::Google, false) => Ok(GOOGLE_DIRECTORY_URL),
+            (Ca::Google, true) => Ok(GOOGLE_STAGING_DIRECTORY_URL),
+        }
+    }
+
+    /// Finds the preset a directory URL (production or staging) belongs to.
+    pub fn from_directory_url(url: &str) -> Option<Ca> {
+        [Ca::LetsEncrypt, Ca::ZeroSsl, Ca::Buypass, Ca::Google]
+            .iter()
+            .find(|ca| {
+                ca.directory_url(false).ok() == Some(url)
+                    || ca.directory_url(true).ok() == Some(url)
+            })
+            .copied()
+    }
+
+    /// Whether a directory URL is the staging environment of one of the presets,
+    /// whatever its host is called, e.g. Buypass' `api.test4.buypass.no`.
+    pub fn is_staging_url(url: &str) -> bool {
+        Ca::from_directory_url(url)
+            .and_then(|ca| ca.directory_url(true).ok())
+            .map_or(false, |staging| staging == url)
+    }
+
+    /// Buypass Go doesn't issue wildcard certificates.
+    pub fn supports_wildcards(&self) -> bool {
+        !matches!(self, Ca::Buypass)
+    }
+
+    /// Returns EAB credentials for the CAs that require them. Credentials
+    /// `provided` by the user always take precedence.
+    pub fn eab(&self, email: &str, provided: Option<Eab>) -> Result<Option<Eab>, AcmeError> {
+        match (self, provided) {
+            (_, Some(eab)) => Ok(Some(eab)),
+            (Ca::ZeroSsl, None) => Ok(Some(zerossl_eab(email)?)),
+            (Ca::Google, None) => Err(AcmeError::Other(anyhow!(
+                "Google Trust Services requires EAB credentials"
+            ))),
+            (Ca::LetsEncrypt, None) | (Ca::Buypass, None) => Ok(None),
+        }
+    }
+}
+
+/// ZeroSSL hands out EAB credentials for an API key (`ZEROSSL_API_KEY`), or,
+/// if there is no key, for an email address.
+fn zerossl_eab(email: &str) -> Result<Eab, AcmeError> {
+    #[derive(Debug, Deserialize)]
+    struct EabResponse {
+        success: bool,
+        eab_kid: Option<String>,
+        eab_hmac_key: Option<String>,
+        error: Option<serde_json::Value>,
+    }
+    let agent = crate::http::agent();
+    let response = match env::var("ZEROSSL_API_KEY") {
+        Ok(key) => agent
+            .post(ZEROSSL_EAB_URL)
+            .query("access_key", &key)
+            .set("User-Agent", &http_user_agent())
+            .call(),
+        Err(_) => agent
+            .post(ZEROSSL_EAB_EMAIL_URL)
+            .set("User-Agent", &http_user_agent())
+            .send_form(&[("email", email)]),
+    }
+    .map_err(AcmeError::Api)?;
+    let r: EabResponse = response.into_json().map_err(AcmeError::JsonEncode)?;
+    match (r.success, r.eab_kid, r.eab_hmac_key) {
+        (true, Some(kid), Some(hmac_key)) => Ok(Eab { kid, hmac_key }),
+        _ => Err(AcmeError::Other(anyhow!(
+            "ZeroSSL EAB negotiation failed: {:?}",
+            r.error
+        ))),
+    }
+}
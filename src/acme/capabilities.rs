@@ -0,0 +1,89 @@
+//! optional features of a CA. They are probed on the first contact with a directory
+//! and cached in the store, so the client can adapt to the CA it talks to.
+use super::{http_user_agent, AcmeError, Directory};
+use crate::storage::{ObjectKind, Store};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub directory_url: String,
+    /// the `renewalInfo` resource, i.e. [ARI](https://datatracker.ietf.org/doc/draft-ietf-acme-ari/)
+    pub renewal_info: bool,
+    /// certificate profiles offered in `meta.profiles`
+    pub profiles: Vec<String>,
+    /// whether `notBefore`/`notAfter` of new orders are honored. It can't be discovered,
+    /// so it's `None` until the first order asking for them, see `Account::new_order()`.
+    pub validity_window: Option<bool>,
+    pub external_account_required: bool,
+    /// whether resources can be fetched with POST-as-GET only, as RFC8555 mandates.
+    /// `None` until it's probed with an account URL.
+    pub post_as_get_only: Option<bool>,
+}
+
+impl Capabilities {
+    pub fn probe(directory: &Directory) -> Self {
+        let meta = directory.meta();
+        Capabilities {
+            directory_url: directory.url.to_owned(),
+            renewal_info: directory.url_for("renewalInfo").is_some(),
+            profiles: meta.profiles.keys().cloned().collect(),
+            validity_window: None,
+            external_account_required: meta.external_account_required,
+            post_as_get_only: None,
+        }
+    }
+
+    /// CAs accepting POST-as-GET only refuse an unauthenticated GET of the `account_url`.
+    pub fn probe_post_as_get(&mut self, account_url: &str) {
+        let refused = crate::http::agent()
+            .get(account_url)
+            .set("User-Agent", &http_user_agent())
+            .call()
+            .is_err();
+        self.post_as_get_only = Some(refused);
+    }
+
+    /// Returns the cached capabilities of the `directory`, probing and caching them on a miss.
+    pub fn load(store: &dyn Store, directory: &Directory) -> Result<Self, AcmeError> {
+        match store.read(ObjectKind::Capabilities, &cache_name(&directory.url)) {
+            Ok(cached) => serde_json::from_slice(&cached).map_err(AcmeError::JsonDecode),
+            Err(_) => {
+                let capabilities = Capabilities::probe(directory);
+                capabilities.save(store)?;
+                Ok(capabilities)
+            }
+        }
+    }
+
+    /// Same as `load()`, but for a directory URL. With `refresh` the cache is ignored.
+    pub fn fetch(store: &dyn Store, directory_url: &str, refresh: bool) -> Result<Self, AcmeError> {
+        let directory = Directory::from_url(directory_url)?;
+        if refresh {
+            let capabilities = Capabilities::probe(&directory);
+            capabilities.save(store)?;
+            return Ok(capabilities);
+        }
+        Capabilities::load(store, &directory)
+    }
+
+    pub fn save(&self, store: &dyn Store) -> Result<(), AcmeError> {
+        let payload = serde_json::to_string(self).map_err(AcmeError::JsonDecode)?;
+        store
+            .write(
+                ObjectKind::Capabilities,
+                &cache_name(&self.directory_url),
+                payload.as_bytes(),
+            )
+            .map_err(AcmeError::Store)
+    }
+}
+
+/// URLs make poor object names, so the host and the path of the directory are flattened.
+pub(super) fn cache_name(url: &str) -> String {
+    url.trim_start_matches("https://")
+        .trim_start_matches("http://")
+        .replace(
+            |c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '-',
+            "_",
+        )
+}
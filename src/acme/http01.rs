@@ -0,0 +1,144 @@
+//! the built-in http-01 solver: the key authorizations are served at
+//! `http://<domain>/.well-known/acme-challenge/<token>`, either by a server of its own
+//! (`CERTIFIKA_HTTP01_LISTEN`, port 80 on all the addresses by default) or as files
+//! in the webroot of the web server already running there (`CERTIFIKA_WEBROOT`).
+use super::AcmeError;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub const PATH: &str = "/.well-known/acme-challenge/";
+/// how often the server checks whether it's time to stop
+const POLL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Http01 {
+    /// a server of its own, listening on the address
+    Standalone(String),
+    /// files in the webroot of another server
+    Webroot(PathBuf),
+}
+
+impl Default for Http01 {
+    fn default() -> Self {
+        Http01::Standalone("0.0.0.0:80".to_string())
+    }
+}
+
+/// The challenges being served, until it's dropped.
+pub struct Serving {
+    stop: Arc<AtomicBool>,
+    server: Option<JoinHandle<()>>,
+    files: Vec<PathBuf>,
+}
+
+impl Http01 {
+    /// Serves the key authorizations of the `challenges` (by their tokens).
+    pub fn serve(&self, challenges: HashMap<String, String>) -> Result<Serving, AcmeError> {
+        if let Some(token) = challenges.keys().find(|t| !is_token(t)) {
+            return Err(AcmeError::Other(anyhow::anyhow!(
+                "invalid http-01 token {:?}",
+                token
+            )));
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut serving = Serving {
+            stop: stop.clone(),
+            server: None,
+            files: Vec::new(),
+        };
+        match self {
+            Http01::Standalone(listen) => {
+                let listener = TcpListener::bind(listen).map_err(AcmeError::Http01)?;
+                listener.set_nonblocking(true).map_err(AcmeError::Http01)?;
+                serving.server = Some(thread::spawn(move || {
+                    while !stop.load(Ordering::Relaxed) {
+                        match listener.accept() {
+                            Ok((stream, _)) => respond(stream, &challenges),
+                            Err(_) => thread::sleep(POLL),
+                        }
+                    }
+                }));
+            }
+            Http01::Webroot(webroot) => {
+                let dir = webroot.join(PATH.trim_start_matches('/'));
+                fs::create_dir_all(&dir).map_err(AcmeError::Http01)?;
+                for (token, key_authorization) in &challenges {
+                    let path = dir.join(token);
+                    // in the list first, so it's removed even if the write fails halfway
+                    serving.files.push(path.to_owned());
+                    fs::write(&path, key_authorization).map_err(AcmeError::Http01)?;
+                }
+            }
+        }
+        Ok(serving)
+    }
+
+    /// Why the challenges can't be served this way now, if they can't:
+    /// the port is taken (or privileged), or there is no such webroot.
+    pub fn unavailable(&self) -> Option<String> {
+        match self {
+            Http01::Standalone(listen) => TcpListener::bind(listen)
+                .err()
+                .map(|e| format!("can't listen on {}: {}", listen, e)),
+            Http01::Webroot(webroot) if !webroot.is_dir() => Some(format!(
+                "the webroot {} is not a directory",
+                webroot.display()
+            )),
+            Http01::Webroot(_) => None,
+        }
+    }
+}
+
+impl Drop for Serving {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(server) = self.server.take() {
+            let _ = server.join();
+        }
+        for file in &self.files {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+/// Whether the `token` is base64url, as the CAs send it, so it's safe in a path.
+fn is_token(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Answers the request on the `stream` with the key authorization it asks for.
+fn respond(mut stream: TcpStream, challenges: &HashMap<String, String>) {
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    let mut request = [0; 2048];
+    let read = stream.read(&mut request).unwrap_or_default();
+    let request = String::from_utf8_lossy(&request[..read]);
+    let key_authorization = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|path| Path::new(path).strip_prefix(PATH).ok())
+        .and_then(|token| challenges.get(token.to_str().unwrap_or_default()));
+    let _ = match key_authorization {
+        Some(body) => write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        ),
+        None => write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        ),
+    };
+}
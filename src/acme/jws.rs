@@ -1,10 +1,10 @@
 //! module to work with JSON Web Signatures -- [RFC7515](https://tools.ietf.org/html/rfc7515).
-//! The module supports signing with ECDSA P-256 keys only.
+//! The module supports signing with ECDSA P-256, RSA and Ed25519 keys, see `AccountKey`.
 
-use anyhow::Result;
-use ring::rand;
-use ring::signature::EcdsaKeyPair;
-use ring::signature::KeyPair;
+use super::key::AccountKey;
+use anyhow::{anyhow, Result};
+use ring::hmac;
+use serde::Deserialize;
 use std::collections::HashMap;
 
 /// a shortcut function to use base64 URL-safe encoding with no padding.
@@ -19,6 +19,11 @@ pub fn b64(data: &[u8]) -> String {
     base64::encode_config(data, base64::URL_SAFE_NO_PAD)
 }
 
+/// The counterpart of `b64()`.
+pub fn b64_decode(data: &str) -> Result<Vec<u8>> {
+    Ok(base64::decode_config(data, base64::URL_SAFE_NO_PAD)?)
+}
+
 /// Generates JWK from a public key of EcdsaKeyPair. See [RFC7517](https://tools.ietf.org/html/rfc7517) on JWK,
 /// and [RFC7518](https://tools.ietf.org/html/rfc7518) on JWA and different JWK parameters.
 pub fn jwk(public_key: &[u8]) -> Result<serde_json::Value> {
@@ -35,9 +40,54 @@ pub fn jwk(public_key: &[u8]) -> Result<serde_json::Value> {
     Ok(serde_json::to_value(jwk)?)
 }
 
+/// Generates JWK from the DER encoded `RSAPublicKey` ([RFC8017](https://tools.ietf.org/html/rfc8017#appendix-A.1.1)),
+/// i.e. the modulus and the public exponent.
+pub fn rsa_jwk(public_key: &[u8]) -> Result<serde_json::Value> {
+    let (sequence, _) = der_element(public_key, 0x30)?;
+    let (n, rest) = der_element(sequence, 0x02)?;
+    let (e, _) = der_element(rest, 0x02)?;
+    let unsigned = |i: &[u8]| b64(&i[i.iter().take_while(|b| **b == 0).count()..]);
+    let mut jwk: HashMap<String, String> = HashMap::new();
+    jwk.insert("e".to_owned(), unsigned(e));
+    jwk.insert("kty".to_owned(), "RSA".to_owned());
+    jwk.insert("n".to_owned(), unsigned(n));
+    Ok(serde_json::to_value(jwk)?)
+}
+
+/// Generates JWK from an Ed25519 public key, see [RFC8037](https://tools.ietf.org/html/rfc8037#section-2).
+pub fn okp_jwk(public_key: &[u8]) -> Result<serde_json::Value> {
+    let mut jwk: HashMap<String, String> = HashMap::new();
+    jwk.insert("crv".to_owned(), "Ed25519".to_owned());
+    jwk.insert("kty".to_owned(), "OKP".to_owned());
+    jwk.insert("x".to_owned(), b64(public_key));
+    Ok(serde_json::to_value(jwk)?)
+}
+
+/// Splits the DER element with the `tag` off the `data`, returns its contents and the rest.
+fn der_element(data: &[u8], tag: u8) -> Result<(&[u8], &[u8])> {
+    let malformed = || anyhow!("malformed DER, expected tag {:#x}", tag);
+    if data.first() != Some(&tag) {
+        return Err(malformed());
+    }
+    let first = *data.get(1).ok_or_else(malformed)?;
+    let (len, start) = if first < 0x80 {
+        (first as usize, 2)
+    } else {
+        let octets = (first & 0x7f) as usize;
+        let len = data
+            .get(2..2 + octets)
+            .ok_or_else(malformed)?
+            .iter()
+            .fold(0usize, |len, b| len << 8 | *b as usize);
+        (len, 2 + octets)
+    };
+    let contents = data.get(start..start + len).ok_or_else(malformed)?;
+    Ok((contents, &data[start + len..]))
+}
+
 /// Signs the `payload` and returns the signature as a string.
 pub fn sign(
-    key_pair: &EcdsaKeyPair,
+    key: &AccountKey,
     nonce: &str,
     url: &str,
     payload: String,
@@ -51,9 +101,9 @@ pub fn sign(
 
     // protected header
     let mut header: HashMap<String, serde_json::Value> = HashMap::new();
-    header.insert("alg".to_owned(), serde_json::to_value("ES256")?);
+    header.insert("alg".to_owned(), serde_json::to_value(key.alg())?);
     match kid {
-        None => header.insert("jwk".to_owned(), jwk(key_pair.public_key().as_ref())?),
+        None => header.insert("jwk".to_owned(), key.jwk()?),
         Some(k) => header.insert("kid".to_owned(), serde_json::to_value(k)?),
     };
     header.insert("nonce".to_owned(), serde_json::to_value(nonce)?);
@@ -62,13 +112,69 @@ pub fn sign(
     data.insert("protected".to_owned(), serde_json::to_value(&protected)?);
 
     // signature
-    let rng = rand::SystemRandom::new();
+    let signature = key.sign(&format!("{}.{}", protected, payload64).into_bytes())?;
     data.insert(
         "signature".to_owned(),
-        serde_json::to_value(b64(&key_pair
-            .sign(&rng, &format!("{}.{}", protected, payload64).into_bytes())
-            .unwrap()
-            .as_ref()))?,
+        serde_json::to_value(b64(&signature))?,
     );
     Ok(serde_json::to_string(&data)?)
 }
+
+/// Builds the [External Account Binding](https://tools.ietf.org/html/rfc8555#section-7.3.4) JWS:
+/// the account's `jwk` signed with the HMAC key the CA issued out-of-band.
+pub fn eab(
+    eab_kid: &str,
+    hmac_key: &str,
+    url: &str,
+    jwk: serde_json::Value,
+) -> Result<serde_json::Value> {
+    let key = base64::decode_config(hmac_key.trim_end_matches('='), base64::URL_SAFE_NO_PAD)?;
+    let mut data: HashMap<String, serde_json::Value> = HashMap::new();
+
+    let payload64 = b64(&jwk.to_string().into_bytes());
+    data.insert("payload".to_owned(), serde_json::to_value(&payload64)?);
+
+    let mut header: HashMap<String, serde_json::Value> = HashMap::new();
+    header.insert("alg".to_owned(), serde_json::to_value("HS256")?);
+    header.insert("kid".to_owned(), serde_json::to_value(eab_kid)?);
+    header.insert("url".to_owned(), serde_json::to_value(url)?);
+    let protected = b64(&serde_json::to_string(&header)?.into_bytes());
+    data.insert("protected".to_owned(), serde_json::to_value(&protected)?);
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, &key);
+    let tag = hmac::sign(&key, &format!("{}.{}", protected, payload64).into_bytes());
+    data.insert(
+        "signature".to_owned(),
+        serde_json::to_value(b64(tag.as_ref()))?,
+    );
+    Ok(serde_json::to_value(data)?)
+}
+
+/// Decodes a JWS in the flattened JSON serialization (i.e. what `sign()` produces)
+/// and verifies its signature with the `key`. Returns the decoded
+/// protected header and payload along with the verification result.
+pub fn decode(jws: &str, key: &AccountKey) -> Result<serde_json::Value> {
+    #[derive(Debug, Deserialize)]
+    struct Flattened {
+        protected: String,
+        payload: String,
+        signature: String,
+    }
+    let flattened: Flattened = serde_json::from_str(jws.trim())?;
+    let header: serde_json::Value = serde_json::from_slice(&b64_decode(&flattened.protected)?)?;
+    let raw_payload = b64_decode(&flattened.payload)?;
+    // POST-as-GET requests have an empty payload
+    let payload: serde_json::Value = if raw_payload.is_empty() {
+        serde_json::Value::String(String::new())
+    } else {
+        serde_json::from_slice(&raw_payload)?
+    };
+    let signed = format!("{}.{}", flattened.protected, flattened.payload);
+    let valid = header["alg"].as_str() == Some(key.alg())
+        && key.verify(signed.as_bytes(), &b64_decode(&flattened.signature)?);
+    Ok(serde_json::json!({
+        "protected": header,
+        "payload": payload,
+        "signature_valid": valid,
+    }))
+}
@@ -0,0 +1,275 @@
+//! account keys: ECDSA P-256 (`ES256`, the default), RSA (`RS256`) for those
+//! who have to stick to RSA, or Ed25519 (`EdDSA`) for the CAs that accept it. The type of a stored key is told from its PKCS#8 encoding.
+//! A P-256 key can also be kept in Vault, see `transit`.
+use super::transit::{self, TransitKey};
+use super::{jws, AcmeError};
+use anyhow::anyhow;
+use ring::rand;
+use ring::signature::{self, EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccountKeyType {
+    EcdsaP256,
+    Rsa2048,
+    Rsa4096,
+    Ed25519,
+    VaultTransit,
+}
+
+impl Default for AccountKeyType {
+    fn default() -> Self {
+        AccountKeyType::EcdsaP256
+    }
+}
+
+impl FromStr for AccountKeyType {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "ecdsa-p256" | "p256" => Ok(AccountKeyType::EcdsaP256),
+            "rsa-2048" | "rsa2048" => Ok(AccountKeyType::Rsa2048),
+            "rsa-4096" | "rsa4096" => Ok(AccountKeyType::Rsa4096),
+            "ed25519" => Ok(AccountKeyType::Ed25519),
+            "vault-transit" => Ok(AccountKeyType::VaultTransit),
+            _ => Err(anyhow!("unknown account key type: {}", name)),
+        }
+    }
+}
+
+pub enum AccountKey {
+    Ecdsa(EcdsaKeyPair),
+    Rsa(RsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+    Transit(TransitKey),
+}
+
+impl AccountKey {
+    /// Generates a key of the `key_type`, returns it along with its PKCS#8 encoding
+    /// (the reference for a transit key).
+    pub fn generate(key_type: AccountKeyType) -> Result<(Self, Vec<u8>), AcmeError> {
+        let pkcs8 = match key_type {
+            AccountKeyType::EcdsaP256 => {
+                let rng = rand::SystemRandom::new();
+                let alg = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+                EcdsaKeyPair::generate_pkcs8(alg, &rng)
+                    .map_err(AcmeError::KeyGen)?
+                    .as_ref()
+                    .to_owned()
+            }
+            AccountKeyType::Rsa2048 => generate_rsa_pkcs8(2048)?,
+            AccountKeyType::Rsa4096 => generate_rsa_pkcs8(4096)?,
+            AccountKeyType::Ed25519 => {
+                let rng = rand::SystemRandom::new();
+                Ed25519KeyPair::generate_pkcs8(&rng)
+                    .map_err(AcmeError::KeyGen)?
+                    .as_ref()
+                    .to_owned()
+            }
+            AccountKeyType::VaultTransit => {
+                let (key, reference) = TransitKey::generate()?;
+                return Ok((AccountKey::Transit(key), reference));
+            }
+        };
+        Ok((AccountKey::from_pkcs8(&pkcs8)?, pkcs8))
+    }
+
+    /// The key as it's stored: PKCS#8 encoded, or a reference to a transit key.
+    pub fn load(stored: &[u8]) -> Result<Self, AcmeError> {
+        if transit::is_reference(stored) {
+            return Ok(AccountKey::Transit(TransitKey::load(stored)?));
+        }
+        AccountKey::from_pkcs8(stored)
+    }
+
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Self, AcmeError> {
+        let alg = &signature::ECDSA_P256_SHA256_FIXED_SIGNING;
+        if let Ok(key_pair) = EcdsaKeyPair::from_pkcs8(alg, pkcs8) {
+            return Ok(AccountKey::Ecdsa(key_pair));
+        }
+        if let Ok(key_pair) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8) {
+            return Ok(AccountKey::Ed25519(key_pair));
+        }
+        // it's either RSA or of no use
+        Ok(AccountKey::Rsa(
+            RsaKeyPair::from_pkcs8(pkcs8).map_err(AcmeError::KeyDecode)?,
+        ))
+    }
+
+    /// The JWS `alg` of the key.
+    pub fn alg(&self) -> &'static str {
+        match self {
+            AccountKey::Ecdsa(_) | AccountKey::Transit(_) => "ES256",
+            AccountKey::Rsa(_) => "RS256",
+            AccountKey::Ed25519(_) => "EdDSA",
+        }
+    }
+
+    /// The raw public key: the uncompressed point for ECDSA, the DER encoded
+    /// `RSAPublicKey` for RSA, the 32 bytes for Ed25519.
+    pub fn public_key(&self) -> &[u8] {
+        match self {
+            AccountKey::Ecdsa(key_pair) => key_pair.public_key().as_ref(),
+            AccountKey::Rsa(key_pair) => key_pair.public_key().as_ref(),
+            AccountKey::Ed25519(key_pair) => key_pair.public_key().as_ref(),
+            AccountKey::Transit(key) => key.public_key(),
+        }
+    }
+
+    pub fn jwk(&self) -> anyhow::Result<serde_json::Value> {
+        match self {
+            AccountKey::Ecdsa(_) | AccountKey::Transit(_) => jws::jwk(self.public_key()),
+            AccountKey::Rsa(_) => jws::rsa_jwk(self.public_key()),
+            AccountKey::Ed25519(_) => jws::okp_jwk(self.public_key()),
+        }
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>, AcmeError> {
+        let rng = rand::SystemRandom::new();
+        match self {
+            AccountKey::Ecdsa(key_pair) => Ok(key_pair
+                .sign(&rng, data)
+                .map_err(AcmeError::KeyGen)?
+                .as_ref()
+                .to_owned()),
+            AccountKey::Rsa(key_pair) => {
+                let mut signature = vec![0; key_pair.public_modulus_len()];
+                key_pair
+                    .sign(&signature::RSA_PKCS1_SHA256, &rng, data, &mut signature)
+                    .map_err(AcmeError::KeyGen)?;
+                Ok(signature)
+            }
+            AccountKey::Ed25519(key_pair) => Ok(key_pair.sign(data).as_ref().to_owned()),
+            AccountKey::Transit(key) => key.sign(data),
+        }
+    }
+
+    pub fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        let alg: &'static dyn signature::VerificationAlgorithm = match self {
+            AccountKey::Ecdsa(_) | AccountKey::Transit(_) => &signature::ECDSA_P256_SHA256_FIXED,
+            AccountKey::Rsa(_) => &signature::RSA_PKCS1_2048_8192_SHA256,
+            AccountKey::Ed25519(_) => &signature::ED25519,
+        };
+        signature::UnparsedPublicKey::new(alg, self.public_key())
+            .verify(data, signature)
+            .is_ok()
+    }
+}
+
+/// Generates an RSA key of `bits` size, PKCS#8 encoded. ring can only use RSA keys,
+/// so it's up to the `rsa` crate to make them.
+pub fn generate_rsa_pkcs8(bits: usize) -> Result<Vec<u8>, AcmeError> {
+    use rsa::PrivateKeyEncoding;
+    let mut rng = rand_core::OsRng;
+    let key = rsa::RSAPrivateKey::new(&mut rng, bits)
+        .map_err(|e| anyhow!("RSA key generation: {}", e))?;
+    Ok(key
+        .to_pkcs8()
+        .map_err(|e| anyhow!("RSA key encoding: {}", e))?)
+}
+
+/// `rsaEncryption` and its NULL parameters
+const RSA_ALGORITHM: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00,
+];
+/// `id-ecPublicKey` and `prime256v1`
+const P256_ALGORITHM: &[u8] = &[
+    0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d,
+    0x03, 0x01, 0x07,
+];
+
+/// Converts a private JWK, the way other ACME clients keep account keys, to PKCS#8.
+/// RSA and ECDSA P-256 keys are supported.
+pub fn pkcs8_from_jwk(jwk: &serde_json::Value) -> Result<Vec<u8>, AcmeError> {
+    let param = |name: &str| -> Result<Vec<u8>, AcmeError> {
+        let value = jwk[name]
+            .as_str()
+            .ok_or_else(|| anyhow!("the JWK has no {}", name))?;
+        Ok(jws::b64_decode(value.trim_end_matches('='))?)
+    };
+    let pkcs8 = match (jwk["kty"].as_str(), jwk["crv"].as_str()) {
+        (Some("RSA"), _) => {
+            // RSAPrivateKey of RFC8017, the CRT parameters are all there in the JWK
+            let mut key = der_integer(&[0]);
+            for name in &["n", "e", "d", "p", "q", "dp", "dq", "qi"] {
+                key.extend(der_integer(&param(name)?));
+            }
+            private_key_info(RSA_ALGORITHM, &der(0x30, &key))
+        }
+        (Some("EC"), Some("P-256")) => {
+            let mut public_key = vec![0x04];
+            public_key.extend(param("x")?);
+            public_key.extend(param("y")?);
+            let mut key = der_integer(&[1]);
+            key.extend(der(0x04, &param("d")?));
+            key.extend(der(0xa1, &der(0x03, &[&[0][..], &public_key].concat())));
+            private_key_info(P256_ALGORITHM, &der(0x30, &key))
+        }
+        (kty, crv) => {
+            return Err(AcmeError::Other(anyhow!(
+                "unsupported JWK: {} {}",
+                kty.unwrap_or("unknown"),
+                crv.unwrap_or_default()
+            )))
+        }
+    };
+    AccountKey::from_pkcs8(&pkcs8)?;
+    Ok(pkcs8)
+}
+
+/// Wraps a P-256 key in the SEC1 `ECPrivateKey` encoding (`BEGIN EC PRIVATE KEY`,
+/// as OpenSSL writes them) in PKCS#8.
+pub fn pkcs8_from_sec1(sec1: &[u8]) -> Result<Vec<u8>, AcmeError> {
+    let pkcs8 = private_key_info(P256_ALGORITHM, sec1);
+    AccountKey::from_pkcs8(&pkcs8)?;
+    Ok(pkcs8)
+}
+
+/// Wraps an RSA key in the PKCS#1 `RSAPrivateKey` encoding (`BEGIN RSA PRIVATE KEY`)
+/// in PKCS#8.
+pub fn pkcs8_from_pkcs1(pkcs1: &[u8]) -> Result<Vec<u8>, AcmeError> {
+    let pkcs8 = private_key_info(RSA_ALGORITHM, pkcs1);
+    AccountKey::from_pkcs8(&pkcs8)?;
+    Ok(pkcs8)
+}
+
+/// `PrivateKeyInfo` of [RFC5208](https://tools.ietf.org/html/rfc5208#section-5).
+fn private_key_info(algorithm: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut info = der_integer(&[0]);
+    info.extend(der(0x30, algorithm));
+    info.extend(der(0x04, key));
+    der(0x30, &info)
+}
+
+/// A DER INTEGER of the unsigned big-endian `value`.
+fn der_integer(value: &[u8]) -> Vec<u8> {
+    let value = &value[value.iter().take_while(|b| **b == 0).count()..];
+    match value.first() {
+        None => der(0x02, &[0]),
+        Some(b) if b & 0x80 != 0 => der(0x02, &[&[0][..], value].concat()),
+        Some(_) => der(0x02, value),
+    }
+}
+
+/// A DER element with the `tag` and the `content`.
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut element = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        element.push(len as u8);
+    } else {
+        let octets: Vec<u8> = len
+            .to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|b| *b == 0)
+            .collect();
+        element.push(0x80 | octets.len() as u8);
+        element.extend(octets);
+    }
+    element.extend_from_slice(content);
+    element
+}
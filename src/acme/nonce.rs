@@ -0,0 +1,63 @@
+//! [Replay-Nonce](https://tools.ietf.org/html/rfc8555#section-6.5.1) values, along
+//! with where and when they were obtained.
+//!
+//! CAs expire unused nonces after a while, so nonces older than the max age
+//! (`CERTIFIKA_NONCE_MAX_AGE`, in seconds) are dropped before they are sent,
+//! rather than sent to get a `badNonce` back.
+use super::AcmeError;
+use anyhow::anyhow;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone)]
+pub struct Nonce {
+    value: String,
+    /// the URL of the response the nonce came with
+    source: String,
+    acquired: Instant,
+}
+
+impl Nonce {
+    /// Validates the `value` of the header we got from the `source`: a nonce is a non-empty
+    /// base64url string without padding, anything else is a broken CA or a proxy in the way.
+    pub fn parse(value: &str, source: &str) -> Result<Self, AcmeError> {
+        let valid = !value.is_empty()
+            && value
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !valid {
+            return Err(AcmeError::Other(anyhow!(
+                "invalid Replay-Nonce from {}: {:?}",
+                source,
+                value
+            )));
+        }
+        Ok(Nonce {
+            value: value.to_string(),
+            source: source.to_string(),
+            acquired: Instant::now(),
+        })
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        self.acquired.elapsed() > max_age
+    }
+}
+
+impl fmt::Display for Nonce {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (from {}, {}s old)",
+            self.value,
+            self.source,
+            self.acquired.elapsed().as_secs()
+        )
+    }
+}
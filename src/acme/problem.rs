@@ -0,0 +1,67 @@
+//! ACME [problem documents](https://tools.ietf.org/html/rfc8555#section-6.7), i.e.
+//! the `application/problem+json` bodies ([RFC7807](https://tools.ietf.org/html/rfc7807))
+//! CAs respond with when a request fails.
+use super::Identifier;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcmeProblem {
+    #[serde(rename = "type", default)]
+    pub _type: String,
+    #[serde(default)]
+    pub detail: String,
+    pub status: Option<u16>,
+    /// for `userActionRequired`, a page with the instructions for the user
+    pub instance: Option<String>,
+    #[serde(default)]
+    pub subproblems: Vec<Subproblem>,
+    /// the `Link: <...>;rel="terms-of-service"` header of the response, CAs send it
+    /// with `userActionRequired` when the terms of service have changed.
+    #[serde(skip)]
+    pub terms_of_service: Option<String>,
+}
+
+/// Problems with particular identifiers of a request, e.g. when an order
+/// has several domains and some of them are rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subproblem {
+    #[serde(rename = "type", default)]
+    pub _type: String,
+    #[serde(default)]
+    pub detail: String,
+    pub identifier: Option<Identifier>,
+}
+
+impl AcmeProblem {
+    /// Checks the problem type against an ACME error name, e.g. `badNonce`.
+    pub fn is(&self, error: &str) -> bool {
+        self._type.strip_prefix("urn:ietf:params:acme:error:") == Some(error)
+    }
+}
+
+impl fmt::Display for AcmeProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self._type, self.detail)?;
+        if self.is("userActionRequired") {
+            if let Some(instance) = &self.instance {
+                write!(f, "; action required, see {}", instance)?;
+            }
+            if let Some(tos) = &self.terms_of_service {
+                write!(
+                    f,
+                    "; review the terms of service at {} and agree to them \
+                     with `certifika account agree-tos <email>`",
+                    tos
+                )?;
+            }
+        }
+        for sub in &self.subproblems {
+            match &sub.identifier {
+                Some(id) => write!(f, "; {} ({}): {}", id.value, sub._type, sub.detail)?,
+                None => write!(f, "; {}: {}", sub._type, sub.detail)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+//! client-side tracking of the CA rate limits.
+//!
+//! Let's Encrypt limits [new orders per account](https://letsencrypt.org/docs/rate-limits/),
+//! and per week, duplicate certificates (the same set of identifiers) and certificates
+//! per registered domain (see `psl`). Orders and issued certificates are recorded with
+//! the account (the certificates of the other accounts are not seen), so that certifika
+//! warns when a bucket is about to fill up, and, with the `delay` policy, waits for
+//! a free slot instead of getting `rateLimited` from the CA. The policy is set with `CERTIFIKA_RATE_LIMIT`:
+//! ```text
+//! CERTIFIKA_RATE_LIMIT="warn"        # the default, only warn
+//! CERTIFIKA_RATE_LIMIT="delay=3600"  # wait up to an hour for a free slot
+//! ```
+use super::{AcmeError, Ca};
+use crate::psl;
+use crate::storage::{ObjectKind, Store};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+mod tests;
+
+/// Buckets at this share of their limit trigger a warning.
+const WARN_RATIO: f64 = 0.8;
+const DEFAULT_MAX_DELAY: u64 = 3 * 3600;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Throttle {
+    Warn,
+    /// wait for a free slot, as long as it takes no longer than that
+    Delay(Duration),
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Throttle::Warn
+    }
+}
+
+impl FromStr for Throttle {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut parts = spec.splitn(2, '=');
+        match (parts.next().map(str::trim), parts.next()) {
+            (Some("warn"), None) | (Some(""), None) => Ok(Throttle::Warn),
+            (Some("delay"), None) => Ok(Throttle::Delay(Duration::from_secs(DEFAULT_MAX_DELAY))),
+            (Some("delay"), Some(secs)) => {
+                Ok(Throttle::Delay(Duration::from_secs(secs.trim().parse()?)))
+            }
+            _ => Err(anyhow!("invalid rate limit policy: {}", spec)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    name: &'static str,
+    limit: usize,
+    /// seconds
+    window: i64,
+}
+
+const NEW_ORDERS: Bucket = Bucket {
+    name: "new orders per account",
+    limit: 300,
+    window: 3 * 3600,
+};
+const DUPLICATE_CERTIFICATES: Bucket = Bucket {
+    name: "duplicate certificates",
+    limit: 5,
+    window: 7 * 86400,
+};
+const CERTIFICATES_PER_DOMAIN: Bucket = Bucket {
+    name: "certificates per registered domain",
+    limit: 50,
+    window: 7 * 86400,
+};
+
+/// Events recorded for an account, seconds since the UNIX epoch.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RateLimits {
+    orders: Vec<i64>,
+    /// sorted identifiers of the certificate, and when it was issued
+    certificates: Vec<(String, i64)>,
+    /// set when the CA answered with `rateLimited`
+    blocked_until: Option<i64>,
+}
+
+impl RateLimits {
+    /// Loads the recorded events of the account, starting afresh if there are none.
+    pub fn load(store: &dyn Store, account_name: &str) -> Self {
+        store
+            .read(ObjectKind::RateLimits, account_name)
+            .ok()
+            .and_then(|stored| serde_json::from_slice(&stored).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&mut self, store: &dyn Store, account_name: &str) -> Result<(), AcmeError> {
+        self.prune(now());
+        let payload = serde_json::to_string(self).map_err(AcmeError::JsonDecode)?;
+        store
+            .write(ObjectKind::RateLimits, account_name, payload.as_bytes())
+            .map_err(AcmeError::Store)
+    }
+
+    /// Checks the buckets before ordering a certificate for `identifiers`. Returns
+    /// how long to wait for a free slot, if any of the buckets is full. Only the
+    /// Let's Encrypt limits are known, other CAs are not checked.
+    pub fn check(&mut self, ca: Option<Ca>, identifiers: &[String]) -> Option<Duration> {
+        let now = now();
+        self.prune(now);
+        let mut wait = self.blocked_until.map_or(0, |until| until - now).max(0);
+        if ca != Some(Ca::LetsEncrypt) {
+            return to_duration(wait);
+        }
+        let key = certificate_key(identifiers);
+        let duplicates: Vec<i64> = self
+            .certificates
+            .iter()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, issued)| *issued)
+            .collect();
+        for (bucket, events) in &[
+            (NEW_ORDERS, &self.orders),
+            (DUPLICATE_CERTIFICATES, &duplicates),
+        ] {
+            wait = wait.max(bucket.check(events, now));
+        }
+        // renewals aren't held back by it, even though they count towards it
+        if duplicates.is_empty() {
+            for domain in registered_domains(identifiers.iter().map(String::as_str)) {
+                let issued: Vec<i64> = self
+                    .certificates
+                    .iter()
+                    .filter(|(k, _)| registered_domains(k.split(',')).contains(&domain))
+                    .map(|(_, issued)| *issued)
+                    .collect();
+                wait = wait.max(CERTIFICATES_PER_DOMAIN.check(&issued, now));
+            }
+        }
+        to_duration(wait)
+    }
+
+    pub fn record_order(&mut self) {
+        self.orders.push(now());
+    }
+
+    pub fn record_certificate(&mut self, identifiers: &[String]) {
+        self.certificates
+            .push((certificate_key(identifiers), now()));
+    }
+
+    /// Records a `rateLimited` response, the CA tells when to come back with `Retry-After`.
+    pub fn record_rate_limited(&mut self, retry_after: Option<Duration>) {
+        let until = now() + retry_after.map_or(3600, |d| d.as_secs() as i64);
+        self.blocked_until = Some(until);
+    }
+
+    /// Forgets the events that are out of all the windows.
+    fn prune(&mut self, now: i64) {
+        self.orders.retain(|t| now - t < NEW_ORDERS.window);
+        let window = DUPLICATE_CERTIFICATES
+            .window
+            .max(CERTIFICATES_PER_DOMAIN.window);
+        self.certificates.retain(|(_, t)| now - t < window);
+        if matches!(self.blocked_until, Some(until) if until <= now) {
+            self.blocked_until = None;
+        }
+    }
+}
+
+impl Bucket {
+    /// Warns when the bucket is close to its limit, and returns how many
+    /// seconds are left until the oldest event frees a slot in a full bucket.
+    fn check(&self, events: &[i64], now: i64) -> i64 {
+        let used = events.iter().filter(|t| now - *t < self.window).count();
+        if used as f64 >= self.limit as f64 * WARN_RATIO {
+            log::warn!(
+                r#"{{"op":"rate limit","bucket":"{}","used":{},"limit":{}}}"#,
+                self.name,
+                used,
+                self.limit
+            );
+        }
+        if used < self.limit {
+            return 0;
+        }
+        let mut recent: Vec<i64> = events
+            .iter()
+            .copied()
+            .filter(|t| now - t < self.window)
+            .collect();
+        recent.sort_unstable();
+        recent[used - self.limit] + self.window - now
+    }
+}
+
+fn certificate_key(identifiers: &[String]) -> String {
+    let mut sorted: Vec<String> = identifiers.iter().map(|i| i.to_lowercase()).collect();
+    sorted.sort();
+    sorted.dedup();
+    sorted.join(",")
+}
+
+/// The registered domains the `identifiers` are under, without the duplicates.
+fn registered_domains<'i>(identifiers: impl Iterator<Item = &'i str>) -> Vec<String> {
+    let mut domains: Vec<String> = identifiers
+        .filter_map(|i| psl::registered_domain(i.trim_start_matches("*.")))
+        .collect();
+    domains.sort();
+    domains.dedup();
+    domains
+}
+
+fn to_duration(secs: i64) -> Option<Duration> {
+    if secs > 0 {
+        Some(Duration::from_secs(secs as u64))
+    } else {
+        None
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,92 @@
+//! the buckets filling up and freeing their slots, and which of them an order
+//! for a new certificate or a renewal is held back by.
+use super::*;
+
+const NOW: i64 = 1_600_000_000;
+const HOUR: i64 = 3600;
+
+fn identifiers(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+#[test]
+fn bucket_below_its_limit_is_free() {
+    let events = vec![NOW - HOUR; DUPLICATE_CERTIFICATES.limit - 1];
+    assert_eq!(DUPLICATE_CERTIFICATES.check(&events, NOW), 0);
+    assert_eq!(DUPLICATE_CERTIFICATES.check(&[], NOW), 0);
+}
+
+#[test]
+fn full_bucket_waits_for_the_oldest_event() {
+    let events: Vec<i64> = (1..=5).map(|hours| NOW - hours * HOUR).collect();
+    assert_eq!(
+        DUPLICATE_CERTIFICATES.check(&events, NOW),
+        DUPLICATE_CERTIFICATES.window - 5 * HOUR
+    );
+}
+
+#[test]
+fn overfull_bucket_waits_until_it_is_below_its_limit() {
+    // recorded out of order, two over the limit
+    let events = vec![
+        NOW - 3 * HOUR,
+        NOW - HOUR,
+        NOW - 7 * HOUR,
+        NOW - 2 * HOUR,
+        NOW - 5 * HOUR,
+        NOW - 4 * HOUR,
+        NOW - 6 * HOUR,
+    ];
+    assert_eq!(
+        DUPLICATE_CERTIFICATES.check(&events, NOW),
+        DUPLICATE_CERTIFICATES.window - 5 * HOUR
+    );
+}
+
+#[test]
+fn events_out_of_the_window_free_their_slots() {
+    let mut events = vec![NOW - HOUR; DUPLICATE_CERTIFICATES.limit - 1];
+    events.push(NOW - DUPLICATE_CERTIFICATES.window);
+    assert_eq!(DUPLICATE_CERTIFICATES.check(&events, NOW), 0);
+}
+
+#[test]
+fn only_lets_encrypt_limits_are_checked() {
+    let names = identifiers(&["example.com", "www.example.com"]);
+    let mut limits = RateLimits::default();
+    for _ in 0..DUPLICATE_CERTIFICATES.limit {
+        limits.record_certificate(&names);
+    }
+    assert!(limits.check(Some(Ca::Buypass), &names).is_none());
+    assert!(limits.check(None, &names).is_none());
+    let wait = limits.check(Some(Ca::LetsEncrypt), &names).unwrap();
+    assert!(wait.as_secs() as i64 <= DUPLICATE_CERTIFICATES.window);
+    // the order of the identifiers doesn't matter, nor their case
+    let reordered = identifiers(&["WWW.example.com", "Example.com"]);
+    assert!(limits.check(Some(Ca::LetsEncrypt), &reordered).is_some());
+    assert!(limits
+        .check(Some(Ca::LetsEncrypt), &identifiers(&["example.com"]))
+        .is_none());
+}
+
+#[test]
+fn rate_limited_response_holds_back_any_ca() {
+    let mut limits = RateLimits::default();
+    limits.record_rate_limited(Some(Duration::from_secs(600)));
+    let wait = limits.check(None, &identifiers(&["example.com"])).unwrap();
+    assert!(wait.as_secs() > 0 && wait.as_secs() <= 600);
+}
+
+#[test]
+fn renewals_are_exempt_from_the_registered_domain_limit() {
+    let mut limits = RateLimits::default();
+    for i in 0..CERTIFICATES_PER_DOMAIN.limit {
+        limits.record_certificate(&identifiers(&[&format!("host{}.example.com", i)]));
+    }
+    let renewal = identifiers(&["host0.example.com"]);
+    assert!(limits.check(Some(Ca::LetsEncrypt), &renewal).is_none());
+    let new = identifiers(&["*.example.com"]);
+    assert!(limits.check(Some(Ca::LetsEncrypt), &new).is_some());
+    let elsewhere = identifiers(&["example.org"]);
+    assert!(limits.check(Some(Ca::LetsEncrypt), &elsewhere).is_none());
+}
@@ -0,0 +1,58 @@
+//! [revocation reasons](https://tools.ietf.org/html/rfc5280#section-5.3.1), sent as
+//! their codes with [revokeCert](https://tools.ietf.org/html/rfc8555#section-7.6).
+//! CAs may accept only some of them, e.g. Let's Encrypt refuses `cACompromise`.
+use anyhow::anyhow;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    Unspecified,
+    KeyCompromise,
+    CaCompromise,
+    AffiliationChanged,
+    Superseded,
+    CessationOfOperation,
+    CertificateHold,
+    RemoveFromCrl,
+    PrivilegeWithdrawn,
+    AaCompromise,
+}
+
+impl Reason {
+    /// The CRLReason code, 7 is not used.
+    pub fn code(self) -> u8 {
+        match self {
+            Reason::Unspecified => 0,
+            Reason::KeyCompromise => 1,
+            Reason::CaCompromise => 2,
+            Reason::AffiliationChanged => 3,
+            Reason::Superseded => 4,
+            Reason::CessationOfOperation => 5,
+            Reason::CertificateHold => 6,
+            Reason::RemoveFromCrl => 8,
+            Reason::PrivilegeWithdrawn => 9,
+            Reason::AaCompromise => 10,
+        }
+    }
+}
+
+impl FromStr for Reason {
+    type Err = anyhow::Error;
+
+    /// Takes the RFC5280 names, e.g. `keyCompromise`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "unspecified" => Ok(Reason::Unspecified),
+            "keyCompromise" => Ok(Reason::KeyCompromise),
+            "cACompromise" => Ok(Reason::CaCompromise),
+            "affiliationChanged" => Ok(Reason::AffiliationChanged),
+            "superseded" => Ok(Reason::Superseded),
+            "cessationOfOperation" => Ok(Reason::CessationOfOperation),
+            "certificateHold" => Ok(Reason::CertificateHold),
+            "removeFromCRL" => Ok(Reason::RemoveFromCrl),
+            "privilegeWithdrawn" => Ok(Reason::PrivilegeWithdrawn),
+            "aACompromise" => Ok(Reason::AaCompromise),
+            _ => Err(anyhow!("unknown revocation reason: {}", name)),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! issuance latency per phase. Every run is timed, and the timings are added up
+//! per directory in the store, so `certifika stats` can tell which part of
+//! the renewals is slow with which CA.
+use super::capabilities::cache_name;
+use super::AcmeError;
+use crate::storage::{ObjectKind, Store};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    Nonce,
+    NewOrder,
+    /// from triggering a challenge to its authorization being final
+    Challenge,
+    /// from sending the CSR to the order being valid
+    Finalize,
+    Download,
+}
+
+impl Phase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Nonce => "nonce",
+            Phase::NewOrder => "new-order",
+            Phase::Challenge => "challenge",
+            Phase::Finalize => "finalize",
+            Phase::Download => "download",
+        }
+    }
+}
+
+/// The timings of the current run.
+#[derive(Debug, Default)]
+pub struct Timings(Vec<(Phase, Duration)>);
+
+impl Timings {
+    pub fn record(&mut self, phase: Phase, started: Instant) {
+        self.0.push((phase, started.elapsed()));
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PhaseStats {
+    pub count: u64,
+    pub total_millis: u64,
+    pub max_millis: u64,
+}
+
+impl PhaseStats {
+    pub fn average_millis(&self) -> u64 {
+        self.total_millis
+            .checked_div(self.count)
+            .unwrap_or_default()
+    }
+}
+
+/// The timings of all the runs against a directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stats {
+    pub runs: u64,
+    pub phases: BTreeMap<String, PhaseStats>,
+}
+
+impl Stats {
+    /// Loads the stats of the `directory_url`, empty ones if there are none yet.
+    pub fn load(store: &dyn Store, directory_url: &str) -> Result<Self, AcmeError> {
+        match store.read(ObjectKind::Stats, &cache_name(directory_url)) {
+            Ok(stored) => serde_json::from_slice(&stored).map_err(AcmeError::JsonDecode),
+            Err(_) => Ok(Stats::default()),
+        }
+    }
+
+    /// Adds the `timings` of a run to the stats of the `directory_url`.
+    pub fn add(store: &dyn Store, directory_url: &str, timings: &Timings) -> Result<(), AcmeError> {
+        let mut stats = Stats::load(store, directory_url)?;
+        stats.runs += 1;
+        for (phase, duration) in &timings.0 {
+            let millis = duration.as_millis() as u64;
+            let entry = stats.phases.entry(phase.name().to_string()).or_default();
+            entry.count += 1;
+            entry.total_millis += millis;
+            entry.max_millis = entry.max_millis.max(millis);
+            log::info!(
+                r#"{{"op":"timing","phase":"{}","millis":{}}}"#,
+                phase.name(),
+                millis
+            );
+        }
+        let payload = serde_json::to_string(&stats).map_err(AcmeError::JsonDecode)?;
+        store
+            .write(
+                ObjectKind::Stats,
+                &cache_name(directory_url),
+                payload.as_bytes(),
+            )
+            .map_err(AcmeError::Store)
+    }
+}
@@ -0,0 +1,91 @@
+//! statuses of the ACME objects and their [transitions](https://tools.ietf.org/html/rfc8555#section-7.1.6).
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// ```text
+///  pending --------------+
+///     |                  |
+///     | all authz valid  | any authz invalid
+///     V                  |
+///   ready ---------------+
+///     |                  |
+///     | finalize         |
+///     V                  |
+/// processing ------------+
+///     |                  |
+///     | cert issued      | error
+///     V                  V
+///   valid             invalid
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    Pending,
+    Ready,
+    Processing,
+    Valid,
+    Invalid,
+}
+
+impl OrderStatus {
+    fn rank(self) -> u8 {
+        match self {
+            OrderStatus::Pending => 0,
+            OrderStatus::Ready => 1,
+            OrderStatus::Processing => 2,
+            OrderStatus::Valid | OrderStatus::Invalid => 3,
+        }
+    }
+
+    /// `valid` and `invalid` are final, the order won't change anymore.
+    pub fn is_final(self) -> bool {
+        matches!(self, OrderStatus::Valid | OrderStatus::Invalid)
+    }
+
+    /// Checks whether the order can get from this status to the `next` one. Orders only
+    /// move forward, but a poll may observe several transitions at once (e.g. `ready`
+    /// straight to `valid`, when the CA issues the certificate right on finalization).
+    pub fn can_become(self, next: OrderStatus) -> bool {
+        self == next || (!self.is_final() && next.rank() > self.rank())
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Ready => "ready",
+            OrderStatus::Processing => "processing",
+            OrderStatus::Valid => "valid",
+            OrderStatus::Invalid => "invalid",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Statuses of an [authorization](https://tools.ietf.org/html/rfc8555#section-7.1.4),
+/// only `pending` ones may still change after the challenge is triggered.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthzStatus {
+    Pending,
+    Valid,
+    Invalid,
+    Deactivated,
+    Expired,
+    Revoked,
+}
+
+impl fmt::Display for AuthzStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AuthzStatus::Pending => "pending",
+            AuthzStatus::Valid => "valid",
+            AuthzStatus::Invalid => "invalid",
+            AuthzStatus::Deactivated => "deactivated",
+            AuthzStatus::Expired => "expired",
+            AuthzStatus::Revoked => "revoked",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,56 @@
+//! The strict mode (`--strict`): the responses of the CA are checked against the MUSTs of
+//! [RFC8555](https://tools.ietf.org/html/rfc8555), and any violation fails the request.
+//! Real CAs don't need it, it's for those developing their own ACME servers.
+use super::{http_status_ok, AcmeProblem, Request};
+
+const JSON: &str = "application/json";
+const PROBLEM: &str = "application/problem+json";
+const PEM_CHAIN: &str = "application/pem-certificate-chain";
+
+/// Checks the headers of the `response` to the `request`, returning the violations.
+pub fn check_headers(request: &Request<'_>, response: &ureq::Response) -> Vec<String> {
+    let mut violations = Vec::new();
+    let status = response.status();
+    // all our requests are POSTs (including POST-as-GET)
+    if http_status_ok(status) && response.header("Replay-Nonce").is_none() {
+        violations.push("no Replay-Nonce in a successful response (section 6.5)".to_string());
+    }
+    if status == 201 && response.header("Location").is_none() {
+        violations.push("no Location in a 201 Created response (section 7.3, 7.4)".to_string());
+    }
+    let content_type = response
+        .header("Content-Type")
+        .and_then(|t| t.split(';').next())
+        .map(str::trim)
+        .unwrap_or_default();
+    let allowed: &[&str] = match request {
+        _ if !http_status_ok(status) => &[PROBLEM],
+        Request::Get(_) => &[JSON, PEM_CHAIN],
+        Request::Post(..) => &[JSON],
+    };
+    if !allowed.contains(&content_type) {
+        violations.push(format!(
+            "Content-Type {:?} of a {} response, expected {} (section 6.7, 7.4.2)",
+            content_type,
+            status,
+            allowed.join(" or ")
+        ));
+    }
+    violations
+}
+
+/// Checks the `body` of a response with the `status`: error responses
+/// have to carry a problem document with a type.
+pub fn check_body(status: u16, body: &str) -> Vec<String> {
+    if http_status_ok(status) {
+        return Vec::new();
+    }
+    match serde_json::from_str::<AcmeProblem>(body) {
+        Ok(problem) if !problem._type.is_empty() => Vec::new(),
+        Ok(_) => vec!["a problem document without a type (section 6.7)".to_string()],
+        Err(e) => vec![format!(
+            "the body of a {} response is not a problem document: {} (section 6.7)",
+            status, e
+        )],
+    }
+}
@@ -0,0 +1,138 @@
+//! account keys kept in the [Transit](https://developer.hashicorp.com/vault/docs/secrets/transit)
+//! engine of Vault: the JWS are signed by Vault, the private key never leaves it.
+//!
+//! What's stored as the account key is a reference to the transit key,
+//! `vault-transit:<mount>/<name>`. Vault is reached with the env vars of the Vault store
+//! (`VAULT_ADDR`, `VAULT_NAMESPACE`, and the auth method, see `storage::VaultAuth`),
+//! the engine is mounted at `CERTIFIKA_VAULT_TRANSIT_MOUNT`, `transit` by default.
+use super::{jws, AcmeError};
+use crate::storage::{VaultAuth, VaultClient};
+use anyhow::anyhow;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::env;
+
+const REFERENCE_PREFIX: &str = "vault-transit:";
+
+pub struct TransitKey {
+    client: VaultClient,
+    mount: String,
+    name: String,
+    /// the uncompressed P-256 point
+    public_key: Vec<u8>,
+}
+
+/// Whether the stored account key is a reference to a transit key.
+pub fn is_reference(stored: &[u8]) -> bool {
+    stored.starts_with(REFERENCE_PREFIX.as_bytes())
+}
+
+impl TransitKey {
+    /// Creates an ECDSA P-256 key in the transit engine, returns it along with
+    /// the reference to store.
+    pub fn generate() -> Result<(Self, Vec<u8>), AcmeError> {
+        let mount =
+            env::var("CERTIFIKA_VAULT_TRANSIT_MOUNT").unwrap_or_else(|_| "transit".to_string());
+        let mut id = [0u8; 8];
+        SystemRandom::new()
+            .fill(&mut id)
+            .map_err(AcmeError::KeyGen)?;
+        let name = format!(
+            "certifika-account-{}",
+            id.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+        );
+        let client = connect()?;
+        client
+            .call(
+                "POST",
+                &client.url(&format!("{}/keys/{}", mount, name)),
+                Some(ureq::json!({ "type": "ecdsa-p256", "exportable": false })),
+            )
+            .map_err(AcmeError::Store)?;
+        log::info!(
+            r#"{{"op":"transit key created","mount":"{}","name":"{}"}}"#,
+            mount,
+            name
+        );
+        let reference = format!("{}{}/{}", REFERENCE_PREFIX, mount, name).into_bytes();
+        Ok((TransitKey::open(client, mount, name)?, reference))
+    }
+
+    /// The transit key of the stored `reference`.
+    pub fn load(reference: &[u8]) -> Result<Self, AcmeError> {
+        let reference = std::str::from_utf8(reference).map_err(AcmeError::Utf8)?;
+        let path = reference.trim_start_matches(REFERENCE_PREFIX).trim();
+        let (mount, name) = match path.rfind('/') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => return Err(anyhow!("invalid transit key reference {}", reference).into()),
+        };
+        TransitKey::open(connect()?, mount.to_string(), name.to_string())
+    }
+
+    /// Fetches the public key of the latest version of the key.
+    fn open(client: VaultClient, mount: String, name: String) -> Result<Self, AcmeError> {
+        let json: serde_json::Value = client
+            .call(
+                "GET",
+                &client.url(&format!("{}/keys/{}", mount, name)),
+                None,
+            )
+            .map_err(AcmeError::Store)?
+            .into_json()
+            .map_err(AcmeError::JsonEncode)?;
+        let data = &json["data"];
+        if data["type"].as_str() != Some("ecdsa-p256") {
+            return Err(anyhow!("the transit key {} is not an ecdsa-p256 key", name).into());
+        }
+        let version = data["latest_version"].as_u64().unwrap_or(1).to_string();
+        let pem = data["keys"][&version]["public_key"]
+            .as_str()
+            .ok_or_else(|| anyhow!("the transit key {} has no public key", name))?;
+        // the point is at the end of the SubjectPublicKeyInfo
+        let spki = crate::cert::pem_body(pem)?;
+        let public_key = match spki.len().checked_sub(65) {
+            Some(start) if spki[start] == 0x04 => spki[start..].to_vec(),
+            _ => return Err(anyhow!("unexpected public key of the transit key {}", name).into()),
+        };
+        Ok(TransitKey {
+            client,
+            mount,
+            name,
+            public_key,
+        })
+    }
+
+    pub fn public_key(&self) -> &[u8] {
+        &self.public_key
+    }
+
+    /// Has Vault sign the `data`, the signature is `r || s`, as JWS wants it.
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>, AcmeError> {
+        let url = self
+            .client
+            .url(&format!("{}/sign/{}/sha2-256", self.mount, self.name));
+        let json: serde_json::Value = self
+            .client
+            .call(
+                "POST",
+                &url,
+                Some(ureq::json!({
+                    "input": base64::encode(data),
+                    "marshaling_algorithm": "jws",
+                })),
+            )
+            .map_err(AcmeError::Store)?
+            .into_json()
+            .map_err(AcmeError::JsonEncode)?;
+        // vault:v<version>:<signature>
+        let signature = json["data"]["signature"]
+            .as_str()
+            .and_then(|s| s.rsplit(':').next())
+            .ok_or_else(|| anyhow!("transit returned no signature"))?;
+        Ok(jws::b64_decode(signature)?)
+    }
+}
+
+fn connect() -> Result<VaultClient, AcmeError> {
+    let auth = VaultAuth::from_settings(|name| env::var(name).ok());
+    VaultClient::init(env::var("VAULT_NAMESPACE").ok(), auth).map_err(AcmeError::Store)
+}
@@ -0,0 +1,109 @@
+#![deny(clippy::mem_forget)]
+//! backoff policy for retries (503/429 responses) and polling (orders, authorizations).
+//!
+//! `Retry-After` sent by the server is always honored, as long as it's not longer
+//! than the `max` delay, otherwise the delays grow exponentially from `initial`.
+//! Retries are limited by `attempts`, polling goes on until `timeout`.
+//! The policy is configured with comma separated `key=value` pairs, delays are in seconds:
+//! ```text
+//! CERTIFIKA_BACKOFF="initial=2,max=60,attempts=6,timeout=300"
+//! ```
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub attempts: u32,
+    pub timeout: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(2),
+            max: Duration::from_secs(60),
+            attempts: 6,
+            timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+impl Backoff {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut backoff = Backoff::default();
+        for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let mut kv = pair.splitn(2, '=');
+            let (key, value) = match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) => (k.trim(), v.trim().parse::<u64>()?),
+                _ => return Err(anyhow!("invalid backoff setting: {}", pair)),
+            };
+            match key {
+                "initial" => backoff.initial = Duration::from_secs(value),
+                "max" => backoff.max = Duration::from_secs(value),
+                "attempts" => backoff.attempts = value as u32,
+                "timeout" => backoff.timeout = Duration::from_secs(value),
+                _ => return Err(anyhow!("unknown backoff setting: {}", key)),
+            }
+        }
+        Ok(backoff)
+    }
+
+    /// Returns how long to wait before the retry number `attempt` (counting from 0),
+    /// or `None` when the attempts are exhausted, or the server asks to come back
+    /// later than we are willing to wait.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if attempt >= self.attempts {
+            return None;
+        }
+        match retry_after {
+            Some(delay) if delay > self.max => None,
+            Some(delay) => Some(delay),
+            None => Some(self.exponential(attempt)),
+        }
+    }
+
+    /// Returns how long to wait before the poll number `attempt`, or `None` once
+    /// the `deadline` has passed. The delay never reaches past the deadline.
+    pub fn poll_delay(
+        &self,
+        attempt: u32,
+        retry_after: Option<Duration>,
+        deadline: Instant,
+    ) -> Option<Duration> {
+        let left = deadline.checked_duration_since(Instant::now())?;
+        if left == Duration::from_secs(0) {
+            return None;
+        }
+        let delay = retry_after.unwrap_or_else(|| self.exponential(attempt));
+        Some(delay.min(left))
+    }
+
+    /// Returns the deadline for polling that starts now.
+    pub fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout
+    }
+
+    fn exponential(&self, attempt: u32) -> Duration {
+        2u32.checked_pow(attempt)
+            .and_then(|factor| self.initial.checked_mul(factor))
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+}
+
+/// Parses the value of a `Retry-After` header, which is either
+/// a number of seconds, or an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        date.signed_duration_since(Utc::now())
+            .to_std()
+            .unwrap_or_else(|_| Duration::from_secs(0)),
+    )
+}
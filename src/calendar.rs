@@ -0,0 +1,133 @@
+#![deny(clippy::mem_forget)]
+//! maintenance calendars, restricting when certificates may be renewed (and thus
+//! when deploy hooks reloading production services may fire).
+//!
+//! A calendar is a `;` separated list of windows, each of them being weekdays and
+//! a time range in UTC:
+//! ```text
+//! CERTIFIKA_RENEWAL_WINDOWS="Mon-Fri 02:00-04:00;Sat,Sun 22:00-06:00"
+//! ```
+//! A time range that ends before it starts spans midnight. `*` stands for every day.
+//! An empty calendar allows renewals at any time.
+use anyhow::{anyhow, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+mod tests;
+
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+#[derive(Debug, Clone, Default)]
+pub struct Calendar {
+    windows: Vec<Window>,
+}
+
+#[derive(Debug, Clone)]
+struct Window {
+    days: [bool; 7],
+    /// minutes since midnight
+    start: u32,
+    end: u32,
+}
+
+impl Calendar {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let windows = spec
+            .split(';')
+            .map(str::trim)
+            .filter(|w| !w.is_empty())
+            .map(Window::parse)
+            .collect::<Result<Vec<Window>>>()?;
+        Ok(Calendar { windows })
+    }
+
+    /// Checks whether the `time` falls into one of the windows.
+    pub fn allows(&self, time: SystemTime) -> bool {
+        if self.windows.is_empty() {
+            return true;
+        }
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        // 1970-01-01 was a Thursday
+        let day = ((secs / 86400 + 3) % 7) as usize;
+        let minute = ((secs % 86400) / 60) as u32;
+        self.windows.iter().any(|w| w.contains(day, minute))
+    }
+}
+
+impl Window {
+    fn parse(spec: &str) -> Result<Self> {
+        let mut parts = spec.split_whitespace();
+        let (days, hours) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(d), Some(h), None) => (d, h),
+            _ => return Err(anyhow!("invalid maintenance window: {}", spec)),
+        };
+        let mut window = Window {
+            days: [false; 7],
+            start: 0,
+            end: 0,
+        };
+        for range in days.split(',') {
+            if range == "*" {
+                window.days = [true; 7];
+                continue;
+            }
+            let mut ends = range.splitn(2, '-');
+            let first = weekday(ends.next().unwrap_or_default())?;
+            let last = match ends.next() {
+                Some(d) => weekday(d)?,
+                None => first,
+            };
+            let mut day = first;
+            loop {
+                window.days[day] = true;
+                if day == last {
+                    break;
+                }
+                day = (day + 1) % 7;
+            }
+        }
+        let mut times = hours.splitn(2, '-');
+        match (times.next(), times.next()) {
+            (Some(start), Some(end)) => {
+                window.start = minutes(start)?;
+                window.end = minutes(end)?;
+            }
+            _ => return Err(anyhow!("invalid maintenance window: {}", spec)),
+        }
+        Ok(window)
+    }
+
+    fn contains(&self, day: usize, minute: u32) -> bool {
+        if self.start < self.end {
+            self.days[day] && minute >= self.start && minute < self.end
+        } else {
+            // the early hours belong to the window opened the day before
+            (self.days[day] && minute >= self.start)
+                || (self.days[(day + 6) % 7] && minute < self.end)
+        }
+    }
+}
+
+fn weekday(name: &str) -> Result<usize> {
+    let name = name.to_lowercase();
+    WEEKDAYS
+        .iter()
+        .position(|d| name.starts_with(d))
+        .ok_or_else(|| anyhow!("invalid weekday: {}", name))
+}
+
+fn minutes(time: &str) -> Result<u32> {
+    let mut hm = time.splitn(2, ':');
+    let hours: u32 = hm.next().unwrap_or_default().parse()?;
+    let minutes: u32 = match hm.next() {
+        Some(m) => m.parse()?,
+        None => 0,
+    };
+    if hours > 24 || minutes > 59 || hours * 60 + minutes > 24 * 60 {
+        return Err(anyhow!("invalid time: {}", time));
+    }
+    Ok(hours * 60 + minutes)
+}
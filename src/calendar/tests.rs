@@ -0,0 +1,93 @@
+//! the parsing of the windows, and which times they contain, wrapping around the
+//! week and midnight.
+use super::*;
+use std::time::Duration;
+
+/// The time on a `day` (0 is Monday) of the first week of 1970, in UTC.
+fn at(day: u64, hour: u64, minute: u64) -> SystemTime {
+    // 1970-01-05 was a Monday
+    UNIX_EPOCH + Duration::from_secs((4 + day) * 86400 + hour * 3600 + minute * 60)
+}
+
+#[test]
+fn invalid_windows_are_rejected() {
+    for spec in &[
+        "Mon",
+        "Mon 02:00",
+        "Mon 02:00-04:00 UTC",
+        "Xyz 02:00-04:00",
+        "Mon 25:00-26:00",
+        "Mon 02:60-04:00",
+        "Mon 24:01-02:00",
+        "Mon two-four",
+    ] {
+        assert!(Calendar::parse(spec).is_err(), "{}", spec);
+    }
+}
+
+#[test]
+fn empty_calendar_allows_any_time() {
+    let calendar = Calendar::parse(" ; ").unwrap();
+    assert!(calendar.allows(at(2, 13, 37)));
+}
+
+#[test]
+fn window_is_limited_to_its_days_and_hours() {
+    let calendar = Calendar::parse("Mon-Fri 02:00-04:00;Sun 10:00-11:00").unwrap();
+    assert!(calendar.allows(at(0, 2, 0)));
+    assert!(calendar.allows(at(4, 3, 59)));
+    assert!(!calendar.allows(at(0, 4, 0)));
+    assert!(!calendar.allows(at(0, 1, 59)));
+    assert!(!calendar.allows(at(5, 3, 0)));
+    assert!(calendar.allows(at(6, 10, 30)));
+    assert!(!calendar.allows(at(6, 3, 0)));
+}
+
+#[test]
+fn weekday_range_wraps_around_the_week() {
+    let calendar = Calendar::parse("Fri-Mon 10:00-11:00").unwrap();
+    for day in &[4, 5, 6, 0] {
+        assert!(calendar.allows(at(*day, 10, 0)), "day {}", day);
+    }
+    for day in &[1, 2, 3] {
+        assert!(!calendar.allows(at(*day, 10, 0)), "day {}", day);
+    }
+}
+
+#[test]
+fn window_spanning_midnight_carries_over_to_the_next_day() {
+    let calendar = Calendar::parse("Sat 22:00-06:00").unwrap();
+    assert!(calendar.allows(at(5, 22, 0)));
+    assert!(calendar.allows(at(5, 23, 59)));
+    assert!(calendar.allows(at(6, 0, 0)));
+    assert!(calendar.allows(at(6, 5, 59)));
+    assert!(!calendar.allows(at(6, 6, 0)));
+    assert!(!calendar.allows(at(6, 22, 0)));
+    // the early hours of Saturday belong to Friday's window, which there is none of
+    assert!(!calendar.allows(at(5, 5, 0)));
+    assert!(!calendar.allows(at(5, 21, 59)));
+}
+
+#[test]
+fn window_spanning_midnight_carries_over_to_monday() {
+    let calendar = Calendar::parse("Sun 23:00-01:00").unwrap();
+    assert!(calendar.allows(at(0, 0, 30)));
+    assert!(!calendar.allows(at(0, 23, 30)));
+}
+
+#[test]
+fn window_may_end_at_midnight() {
+    let calendar = Calendar::parse("Mon 22:00-24:00").unwrap();
+    assert!(calendar.allows(at(0, 23, 59)));
+    assert!(!calendar.allows(at(1, 0, 0)));
+    assert!(!calendar.allows(at(0, 21, 59)));
+}
+
+#[test]
+fn every_day_with_hours_only() {
+    let calendar = Calendar::parse("* 3-5").unwrap();
+    for day in 0..7 {
+        assert!(calendar.allows(at(day, 4, 0)), "day {}", day);
+        assert!(!calendar.allows(at(day, 5, 0)), "day {}", day);
+    }
+}
@@ -0,0 +1,258 @@
+#![deny(clippy::mem_forget)]
+//! helpers to inspect issued certificates.
+use anyhow::{anyhow, Result};
+use ring::digest;
+use serde::Serialize;
+use x509_parser::extensions::{GeneralName, ParsedExtension};
+use x509_parser::parse_x509_certificate;
+use x509_parser::pem::parse_x509_pem;
+
+/// certificates living this long or less are renewed halfway through their lifetime,
+/// the rest once two thirds of it are over
+const SHORT_LIVED_SECS: i64 = 10 * 86400;
+
+/// Returns notAfter of the leaf (i.e. the first) certificate of the PEM encoded `chain`,
+/// as seconds since the UNIX epoch.
+pub fn not_after(chain: &str) -> Result<i64> {
+    let (_, pem) = parse_x509_pem(chain.as_bytes()).map_err(|e| anyhow!("PEM decode: {:?}", e))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|e| anyhow!("X.509 decode: {:?}", e))?;
+    Ok(cert.validity().not_after.timestamp())
+}
+
+/// Returns the serial number of the leaf certificate of the `chain`, in hex.
+pub fn serial(chain: &str) -> Result<String> {
+    let (_, pem) = parse_x509_pem(chain.as_bytes()).map_err(|e| anyhow!("PEM decode: {:?}", e))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|e| anyhow!("X.509 decode: {:?}", e))?;
+    Ok(cert.tbs_certificate.serial.to_str_radix(16))
+}
+
+/// What's worth knowing about the leaf certificate of a chain.
+#[derive(Debug, Serialize)]
+pub struct Info {
+    /// the DNS names of the subjectAltName extension
+    pub domains: Vec<String>,
+    pub issuer: String,
+    /// in hex
+    pub serial: String,
+    /// seconds since the UNIX epoch
+    pub not_after: i64,
+}
+
+/// Inspects the leaf certificate of the `chain`.
+pub fn info(chain: &str) -> Result<Info> {
+    let (_, pem) = parse_x509_pem(chain.as_bytes()).map_err(|e| anyhow!("PEM decode: {:?}", e))?;
+    info_der(&pem.contents)
+}
+
+/// Same as `info()`, but for a DER encoded certificate.
+pub fn info_der(der: &[u8]) -> Result<Info> {
+    let details = details_der(der)?;
+    Ok(Info {
+        domains: details.domains,
+        issuer: details.issuer,
+        serial: details.serial,
+        not_after: details.not_after,
+    })
+}
+
+/// Everything `certifika cert show` tells about the leaf certificate of a chain.
+#[derive(Debug, Serialize)]
+pub struct Details {
+    pub subject: String,
+    /// the DNS names of the subjectAltName extension
+    pub domains: Vec<String>,
+    pub issuer: String,
+    /// in hex
+    pub serial: String,
+    /// seconds since the UNIX epoch
+    pub not_before: i64,
+    pub not_after: i64,
+    /// e.g. `ecdsa-p256` or `rsa-2048`, named like the `--key-type` values
+    pub key_type: String,
+    /// the digests of the DER encoding, colon separated hex
+    pub sha256: String,
+    pub sha1: String,
+}
+
+/// Inspects the leaf certificate of the `chain` in detail.
+pub fn details(chain: &str) -> Result<Details> {
+    let (_, pem) = parse_x509_pem(chain.as_bytes()).map_err(|e| anyhow!("PEM decode: {:?}", e))?;
+    details_der(&pem.contents)
+}
+
+/// Same as `details()`, but for a DER encoded certificate.
+pub fn details_der(der: &[u8]) -> Result<Details> {
+    let (_, cert) = parse_x509_certificate(der).map_err(|e| anyhow!("X.509 decode: {:?}", e))?;
+    let domains = match cert.tbs_certificate.subject_alternative_name() {
+        Some((_, san)) => san
+            .general_names
+            .iter()
+            .filter_map(|name| match name {
+                GeneralName::DNSName(name) => Some(name.to_string()),
+                _ => None,
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    let spki = &cert.tbs_certificate.subject_pki;
+    let curve = spki
+        .algorithm
+        .parameters
+        .as_ref()
+        .and_then(|p| p.as_oid().ok())
+        .map(|oid| oid.to_id_string());
+    let key_type = match (
+        spki.algorithm.algorithm.to_id_string().as_str(),
+        curve.as_deref(),
+    ) {
+        ("1.2.840.113549.1.1.1", _) => match rsa_modulus_bits(spki.subject_public_key.data) {
+            Some(bits) => format!("rsa-{}", bits),
+            None => "rsa".to_string(),
+        },
+        ("1.2.840.10045.2.1", Some("1.2.840.10045.3.1.7")) => "ecdsa-p256".to_string(),
+        ("1.2.840.10045.2.1", Some("1.3.132.0.34")) => "ecdsa-p384".to_string(),
+        ("1.2.840.10045.2.1", Some("1.3.132.0.35")) => "ecdsa-p521".to_string(),
+        ("1.3.101.112", _) => "ed25519".to_string(),
+        (oid, _) => oid.to_string(),
+    };
+    Ok(Details {
+        subject: cert.subject().to_string(),
+        domains,
+        issuer: cert.issuer().to_string(),
+        serial: cert.tbs_certificate.serial.to_str_radix(16),
+        not_before: cert.validity().not_before.timestamp(),
+        not_after: cert.validity().not_after.timestamp(),
+        key_type,
+        sha256: fingerprint(&digest::SHA256, der),
+        sha1: fingerprint(&digest::SHA1_FOR_LEGACY_USE_ONLY, der),
+    })
+}
+
+/// The `algorithm` digest of the `der`, as colon separated hex, the way openssl shows it.
+fn fingerprint(algorithm: &'static digest::Algorithm, der: &[u8]) -> String {
+    digest::digest(algorithm, der)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// The size of the modulus of a DER encoded RSAPublicKey (RFC8017), a SEQUENCE
+/// of the modulus and the exponent INTEGERs.
+fn rsa_modulus_bits(der: &[u8]) -> Option<usize> {
+    let (tag, _, rest) = der_header(der)?;
+    if tag != 0x30 {
+        return None;
+    }
+    let (tag, len, rest) = der_header(rest)?;
+    if tag != 0x02 {
+        return None;
+    }
+    let modulus = rest.get(..len)?;
+    // the leading zero keeping the INTEGER positive is not part of the size
+    let first = modulus.iter().position(|b| *b != 0)?;
+    Some((modulus.len() - first) * 8 - modulus[first].leading_zeros() as usize)
+}
+
+/// Splits a DER TLV into its tag, length and what follows the header.
+fn der_header(der: &[u8]) -> Option<(u8, usize, &[u8])> {
+    let tag = *der.first()?;
+    let first = *der.get(1)?;
+    if first < 0x80 {
+        return Some((tag, first as usize, &der[2..]));
+    }
+    let octets = (first & 0x7f) as usize;
+    let bytes = der.get(2..2 + octets)?;
+    let len = bytes.iter().fold(0usize, |len, b| len << 8 | *b as usize);
+    Some((tag, len, &der[2 + octets..]))
+}
+
+/// Returns when the leaf certificate of the `chain` is due for renewal, as seconds
+/// since the UNIX epoch. Short-lived certificates get a tighter window, there is
+/// little time to retry a failed renewal otherwise.
+pub fn renew_at(chain: &str) -> Result<i64> {
+    let (_, pem) = parse_x509_pem(chain.as_bytes()).map_err(|e| anyhow!("PEM decode: {:?}", e))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|e| anyhow!("X.509 decode: {:?}", e))?;
+    let not_before = cert.validity().not_before.timestamp();
+    let lifetime = cert.validity().not_after.timestamp() - not_before;
+    if lifetime <= SHORT_LIVED_SECS {
+        Ok(not_before + lifetime / 2)
+    } else {
+        Ok(not_before + lifetime * 2 / 3)
+    }
+}
+
+/// The identifier of the leaf certificate of the `chain` for
+/// [ARI](https://datatracker.ietf.org/doc/draft-ietf-acme-ari/): the key identifier
+/// of its authority key identifier and its serial number, base64url encoded, dot separated.
+pub fn ari_id(chain: &str) -> Result<String> {
+    let (_, pem) = parse_x509_pem(chain.as_bytes()).map_err(|e| anyhow!("PEM decode: {:?}", e))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|e| anyhow!("X.509 decode: {:?}", e))?;
+    let key_id = cert
+        .tbs_certificate
+        .extensions()
+        .values()
+        .find_map(|ext| match ext.parsed_extension() {
+            ParsedExtension::AuthorityKeyIdentifier(aki) => {
+                aki.key_identifier.as_ref().map(|id| id.0)
+            }
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("the certificate has no authority key identifier"))?;
+    // the DER INTEGER content, with the leading zero of the serials with the high bit set
+    let mut serial = cert.tbs_certificate.serial.to_bytes_be();
+    if serial[0] & 0x80 != 0 {
+        serial.insert(0, 0);
+    }
+    Ok(format!(
+        "{}.{}",
+        base64::encode_config(key_id, base64::URL_SAFE_NO_PAD),
+        base64::encode_config(serial, base64::URL_SAFE_NO_PAD)
+    ))
+}
+
+/// Splits the PEM encoded `chain` into DER encoded certificates, leaf first.
+pub fn chain_der(chain: &str) -> Result<Vec<Vec<u8>>> {
+    let mut rest = chain.as_bytes();
+    let mut certs = Vec::new();
+    while rest.iter().any(|b| !b.is_ascii_whitespace()) {
+        let (remaining, pem) = parse_x509_pem(rest).map_err(|e| anyhow!("PEM decode: {:?}", e))?;
+        certs.push(pem.contents);
+        rest = remaining;
+    }
+    Ok(certs)
+}
+
+/// PEM encodes the DER encoded `certs`.
+pub fn pem(certs: &[Vec<u8>]) -> String {
+    let mut pem = String::new();
+    for der in certs {
+        pem.push_str("-----BEGIN CERTIFICATE-----\n");
+        let encoded = base64::encode(der);
+        for line in encoded.as_bytes().chunks(64) {
+            pem.push_str(&String::from_utf8_lossy(line));
+            pem.push('\n');
+        }
+        pem.push_str("-----END CERTIFICATE-----\n");
+    }
+    pem
+}
+
+/// Decodes the base64 body of a single PEM block.
+pub fn pem_body(pem: &str) -> Result<Vec<u8>> {
+    let body: String = pem
+        .lines()
+        .filter(|l| !l.starts_with("-----"))
+        .map(str::trim)
+        .collect();
+    Ok(base64::decode(body)?)
+}
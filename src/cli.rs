@@ -0,0 +1,677 @@
+#![deny(clippy::mem_forget)]
+//! the command line interface. Run `certifika help` (or `certifika help <command>`)
+//! for the details, e.g.:
+//! ```text
+//! certifika account register some@email.com --agree-tos
+//! certifika order some@email.com -d example.com -d '*.example.com'
+//! certifika order some@email.com --domains example.com,www.example.com
+//! certifika renew example.com
+//! certifika renew --all --days 30
+//! certifika completions bash > /etc/bash_completion.d/certifika
+//! ```
+use crate::{acme, request, storage, APP_NAME, APP_VERSION};
+use clap::{App, AppSettings, Arg, ArgGroup, SubCommand};
+use std::fmt::Display;
+use std::str::FromStr;
+
+pub fn app() -> App<'static, 'static> {
+    App::new(APP_NAME)
+        .version(APP_VERSION)
+        .about("ACME client, gets certificates from Let's Encrypt and other ACME CAs")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .args(&[
+            Arg::with_name("config")
+                .long("config")
+                .global(true)
+                .takes_value(true)
+                .help("The config file, overrides CERTIFIKA_CONFIG and ~/.config/certifika/config.toml"),
+            Arg::with_name("staging")
+                .long("staging")
+                .global(true)
+                .conflicts_with("production")
+                .help("Uses the staging directory of the CA"),
+            Arg::with_name("production")
+                .long("production")
+                .global(true)
+                .help("Uses the production directory, even if CERTIFIKA_STAGING is set"),
+            Arg::with_name("ca")
+                .long("ca")
+                .global(true)
+                .takes_value(true)
+                .validator(parses::<acme::Ca>)
+                .help("The CA preset, overrides CERTIFIKA_CA"),
+            Arg::with_name("directory-url")
+                .long("directory-url")
+                .global(true)
+                .takes_value(true)
+                .help("The directory URL of the CA, overrides the CA preset"),
+            Arg::with_name("strict")
+                .long("strict")
+                .global(true)
+                .help("Fails on the responses of the CA violating RFC8555"),
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .global(true)
+                .conflicts_with_all(&["production", "directory-url"])
+                .help("Uses the staging directory, and neither stores nor deploys certificates"),
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .global(true)
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help("Prints the result as a single JSON document, the logs go to stderr then"),
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .global(true)
+                .conflicts_with("verbose")
+                .help("Prints only the errors and the result, overrides CERTIFIKA_LOG_LEVEL"),
+            Arg::with_name("verbose")
+                .long("verbose")
+                .short("v")
+                .global(true)
+                .multiple(true)
+                .help("Logs the progress, -vv the details too, overrides CERTIFIKA_LOG_LEVEL"),
+        ])
+        .subcommand(account())
+        .subcommand(SubCommand::with_name("accounts").about("Lists the stored accounts"))
+        .subcommand(cert())
+        .subcommand(
+            SubCommand::with_name("certs")
+                .about("Lists the stored certificates")
+                .args(&[
+                    Arg::with_name("domain")
+                        .long("domain")
+                        .takes_value(true)
+                        .help("Lists only the certificates for the domain or its subdomains"),
+                    Arg::with_name("sort")
+                        .long("sort")
+                        .takes_value(true)
+                        .possible_values(&["name", "expiry"])
+                        .default_value("name")
+                        .help("Sorts by the name, or by the expiry date"),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Checks the stored account against the CA")
+                .arg(email()),
+        )
+        .subcommand(
+            order()
+                .setting(AppSettings::SubcommandsNegateReqs)
+                .setting(AppSettings::ArgsNegateSubcommands)
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Places an order and writes the challenges to solve out of band")
+                        .arg(email())
+                        .args(&request_args())
+                        .arg(
+                            Arg::with_name("out")
+                                .long("out")
+                                .takes_value(true)
+                                .help("The bundle file, stdout by default"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("resume")
+                        .about("Picks up an exported order and gets the certificate")
+                        .arg(email())
+                        .arg(
+                            Arg::with_name("bundle")
+                                .required(true)
+                                .help("The bundle file written by `order export`"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("renew")
+                .about("Orders a stored certificate again, if it's due for renewal")
+                .arg(
+                    Arg::with_name("name")
+                        .required_unless("all")
+                        .conflicts_with("all")
+                        .help("The name of the certificate, i.e. its first domain"),
+                )
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("Renews all the stored certificates that are due"),
+                )
+                .arg(
+                    Arg::with_name("days")
+                        .long("days")
+                        .takes_value(true)
+                        .validator(parses::<u32>)
+                        .help("Renews the certificates expiring within that many days"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .visible_alias("force-renewal")
+                        .help(
+                            "Renews even if it's not due, or outside of the renewal windows, \
+                             e.g. after a key compromise or a change of the domains",
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("daemon")
+                .about("Stays resident and renews the stored certificates when they are due")
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .takes_value(true)
+                        .default_value("3600")
+                        .validator(parses::<u64>)
+                        .help("Seconds between the renewal runs"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("systemd-install")
+                .about("Writes the systemd units running certifika with the current settings")
+                .args(&[
+                    Arg::with_name("daemon")
+                        .long("daemon")
+                        .help("The service of the daemon, rather than the renew service and timer"),
+                    Arg::with_name("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .requires("daemon")
+                        .help("Adds the socket of the daemon's status endpoint, e.g. 127.0.0.1:8555"),
+                    Arg::with_name("dir")
+                        .long("dir")
+                        .takes_value(true)
+                        .default_value("/etc/systemd/system")
+                        .help("Where to write the units"),
+                    Arg::with_name("print")
+                        .long("print")
+                        .help("Prints the units instead of writing them"),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("revoke")
+                .about("Revokes a stored certificate")
+                .arg(email())
+                .arg(
+                    Arg::with_name("name")
+                        .required(true)
+                        .help("The name of the certificate, i.e. its first domain"),
+                )
+                .arg(reason()),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Writes a stored certificate and its key into files")
+                .args(&[
+                    Arg::with_name("name")
+                        .required(true)
+                        .help("The name of the certificate, i.e. its first domain"),
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["pem", "pkcs12"])
+                        .default_value("pem")
+                        .help("Separate PEM files, or a PKCS#12 archive"),
+                    Arg::with_name("out")
+                        .long("out")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help("The directory to write the files into"),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about(
+                    "Writes the whole store into an archive encrypted with CERTIFIKA_BACKUP_PASSPHRASE",
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .help("The archive to write"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Writes the objects of a backup archive into the store, replacing the ones there")
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .help("The archive written by certifika backup"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export-all")
+                .about("Writes the accounts and the certificates into a tarball, to move them to another machine")
+                .args(&[
+                    Arg::with_name("file")
+                        .required(true)
+                        .help("The tarball to write"),
+                    Arg::with_name("encrypt")
+                        .long("encrypt")
+                        .help("Encrypts the tarball with CERTIFIKA_EXPORT_PASSWORD"),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("import-all")
+                .about("Writes the accounts and the certificates of a tarball into the store")
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .help("The tarball written by certifika export-all, an encrypted one takes CERTIFIKA_EXPORT_PASSWORD"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate-layout")
+                .about("Moves the stored objects over to the layout of CERTIFIKA_STORE_LAYOUT")
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .default_value(storage::DEFAULT_LAYOUT)
+                        .help("The layout the objects were written with"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Checks when a certificate expires, with the exit codes of Nagios plugins")
+                .args(&[
+                    Arg::with_name("domain")
+                        .required(true)
+                        .help("The name of the stored certificate, or the host with --remote"),
+                    Arg::with_name("warn-days")
+                        .long("warn-days")
+                        .takes_value(true)
+                        .default_value("21")
+                        .validator(parses::<u32>)
+                        .help("Warns when the certificate expires within that many days"),
+                    Arg::with_name("crit-days")
+                        .long("crit-days")
+                        .takes_value(true)
+                        .default_value("7")
+                        .validator(parses::<u32>)
+                        .help("Critical when the certificate expires within that many days"),
+                    Arg::with_name("remote")
+                        .long("remote")
+                        .help("Checks the certificate the host serves, rather than the stored one"),
+                    Arg::with_name("port")
+                        .long("port")
+                        .takes_value(true)
+                        .default_value("443")
+                        .validator(parses::<u16>)
+                        .help("The port to connect to with --remote"),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Checks that the host serves the stored certificate")
+                .args(&[
+                    Arg::with_name("domain")
+                        .required(true)
+                        .help("The host to connect to"),
+                    Arg::with_name("name")
+                        .long("name")
+                        .takes_value(true)
+                        .help("The name of the stored certificate, the domain by default"),
+                    Arg::with_name("port")
+                        .long("port")
+                        .takes_value(true)
+                        .default_value("443")
+                        .validator(parses::<u16>)
+                        .help("The port to connect to"),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Takes over the accounts of other ACME clients")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("certbot")
+                        .about("Imports the accounts of certbot")
+                        .arg(
+                            Arg::with_name("dir")
+                                .default_value("/etc/letsencrypt")
+                                .help("The config dir of certbot"),
+                        )
+                        .arg(import_email()),
+                )
+                .subcommand(
+                    SubCommand::with_name("acme-sh")
+                        .about("Imports the accounts of acme.sh")
+                        .arg(
+                            Arg::with_name("dir")
+                                .help("The home of acme.sh, ~/.acme.sh by default"),
+                        )
+                        .arg(import_email()),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("directory")
+                .about("Shows what the directory of the CA offers")
+                .args(&[
+                    Arg::with_name("profiles")
+                        .long("profiles")
+                        .help("The certificate profiles"),
+                    Arg::with_name("meta")
+                        .long("meta")
+                        .help("The meta object of the directory"),
+                    Arg::with_name("capabilities")
+                        .long("capabilities")
+                        .help("The probed capabilities of the CA"),
+                    Arg::with_name("refresh")
+                        .long("refresh")
+                        .requires("capabilities")
+                        .help("Probes the capabilities again rather than using the cached ones"),
+                ])
+                .group(
+                    ArgGroup::with_name("show")
+                        .args(&["profiles", "meta", "capabilities"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Shows how long the phases of issuance take with the CA"),
+        )
+        .subcommand(
+            SubCommand::with_name("self-update")
+                .about("Updates certifika to the latest release")
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Reinstalls the latest release even if it's the running one"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Prints the completion script for the shell")
+                .arg(
+                    Arg::with_name("shell")
+                        .required(true)
+                        .possible_values(&["bash", "zsh", "fish"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("complete-names")
+                .setting(AppSettings::Hidden)
+                .arg(
+                    Arg::with_name("kind")
+                        .required(true)
+                        .possible_values(&["accounts", "certs"]),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("debug")
+                .about("Developer utilities")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("decode-jws")
+                        .about("Decodes a recorded JWS and verifies it against the account key")
+                        .arg(email())
+                        .arg(
+                            Arg::with_name("file")
+                                .required(true)
+                                .help("The JWS file, - for stdin"),
+                        ),
+                ),
+        )
+}
+
+fn account() -> App<'static, 'static> {
+    SubCommand::with_name("account")
+        .about("Account management")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("register")
+                .about("Registers a new account with the CA")
+                .arg(email())
+                .args(&[
+                    Arg::with_name("contacts")
+                        .long("contacts")
+                        .takes_value(true)
+                        .help("Additional contact addresses, comma separated"),
+                    Arg::with_name("account-key-type")
+                        .long("account-key-type")
+                        .takes_value(true)
+                        .validator(parses::<acme::AccountKeyType>)
+                        .help("ecdsa-p256 (the default), rsa-2048, rsa-4096, ed25519 or vault-transit"),
+                    Arg::with_name("eab-kid")
+                        .long("eab-kid")
+                        .takes_value(true)
+                        .requires("eab-hmac-key")
+                        .help("The key identifier of External Account Binding"),
+                    Arg::with_name("eab-hmac-key")
+                        .long("eab-hmac-key")
+                        .takes_value(true)
+                        .requires("eab-kid")
+                        .help("The MAC key of External Account Binding"),
+                    agree_tos(),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("load")
+                .about("Loads the stored account and shows what the CA knows about it")
+                .arg(email())
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints the account as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("find")
+                .about("Recovers the account of the stored key from the CA")
+                .arg(email()),
+        )
+        .subcommand(
+            SubCommand::with_name("agree-tos")
+                .about("Agrees to the updated terms of service of the CA")
+                .arg(email()),
+        )
+        .subcommand(
+            SubCommand::with_name("delete")
+                .about("Removes the account from the store")
+                .arg(email())
+                .args(&[
+                    Arg::with_name("deactivate")
+                        .long("deactivate")
+                        .help("Deactivates the account at the CA first, for good"),
+                    Arg::with_name("purge-certs")
+                        .long("purge-certs")
+                        .help("Removes the certificates ordered with the account too"),
+                ]),
+        )
+}
+
+fn cert() -> App<'static, 'static> {
+    SubCommand::with_name("cert")
+        .about("Certificate management")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("show")
+                .about("Shows the details of a stored certificate")
+                .args(&[
+                    Arg::with_name("name")
+                        .required(true)
+                        .help("The name of the certificate, i.e. its first domain"),
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Prints the details as JSON"),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("delete")
+                .about("Removes the certificate from the store, it's not renewed anymore")
+                .args(&[
+                    Arg::with_name("name")
+                        .required(true)
+                        .help("The name of the certificate, i.e. its first domain"),
+                    Arg::with_name("revoke").long("revoke").help(
+                        "Revokes the certificate first, with the account it was ordered with",
+                    ),
+                    reason().requires("revoke"),
+                ]),
+        )
+}
+
+fn order() -> App<'static, 'static> {
+    SubCommand::with_name("order")
+        .about("Orders a certificate")
+        .arg(email())
+        .args(&request_args())
+        .args(&finalize_args())
+        // with the memory store the account is registered for the run
+        .arg(agree_tos())
+}
+
+fn email() -> Arg<'static, 'static> {
+    Arg::with_name("email")
+        .required(true)
+        .help("The email address of the account")
+}
+
+fn import_email() -> Arg<'static, 'static> {
+    Arg::with_name("email")
+        .long("email")
+        .takes_value(true)
+        .help("The email address to store the account under, its contact by default")
+}
+
+fn reason() -> Arg<'static, 'static> {
+    Arg::with_name("reason")
+        .long("reason")
+        .takes_value(true)
+        .possible_values(&[
+            "unspecified",
+            "keyCompromise",
+            "cACompromise",
+            "affiliationChanged",
+            "superseded",
+            "cessationOfOperation",
+            "certificateHold",
+            "removeFromCRL",
+            "privilegeWithdrawn",
+            "aACompromise",
+        ])
+        .help("The revocation reason (RFC5280), CAs may accept only some")
+}
+
+fn agree_tos() -> Arg<'static, 'static> {
+    Arg::with_name("agree-tos")
+        .long("agree-tos")
+        .help("Agrees to the terms of service of the CA without asking")
+}
+
+/// What makes up a `CertRequest`, see `cert_request()` in main.
+fn request_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("domain")
+            .short("d")
+            .long("domain")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .required_unless("domains")
+            .help("A domain to get the certificate for, the first one names it"),
+        Arg::with_name("domains")
+            .long("domains")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .help("Domains to get the certificate for, comma separated"),
+        Arg::with_name("key-type")
+            .long("key-type")
+            .takes_value(true)
+            .validator(parses::<request::KeyType>)
+            .help("ecdsa-p256 (the default), ecdsa-p384, rsa-2048 or rsa-4096"),
+        Arg::with_name("challenge")
+            .long("challenge")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .validator(parses::<request::ChallengeType>)
+            .help("dns-01 or http-01, in the order of preference, CERTIFIKA_CHALLENGES otherwise"),
+        Arg::with_name("profile")
+            .long("profile")
+            .takes_value(true)
+            .help("The certificate profile of the CA"),
+        Arg::with_name("deploy")
+            .long("deploy")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("A deploy target, the configured ones otherwise"),
+        Arg::with_name("pre-validation-hook")
+            .long("pre-validation-hook")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("A shell command to run before the challenges of an order are validated, see CERTIFIKA_CHALLENGES"),
+        Arg::with_name("post-validation-hook")
+            .long("post-validation-hook")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("A shell command to run after the challenges of an order are validated, even if that failed"),
+        Arg::with_name("hook")
+            .long("hook")
+            .alias("deploy-hook")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("A shell command to run once the certificate is deployed"),
+        Arg::with_name("reuse-key")
+            .long("reuse-key")
+            .help("Renews with the key of the previous certificate"),
+        Arg::with_name("must-staple")
+            .long("must-staple")
+            .help("Asks for the OCSP Must-Staple extension"),
+        Arg::with_name("not-before")
+            .long("not-before")
+            .takes_value(true)
+            .validator(rfc3339)
+            .help("The start of the validity window (RFC3339)"),
+        Arg::with_name("not-after")
+            .long("not-after")
+            .takes_value(true)
+            .validator(rfc3339)
+            .help("The end of the validity window (RFC3339)"),
+        Arg::with_name("short-lived")
+            .long("short-lived")
+            .conflicts_with("not-after")
+            .help("Asks for a certificate living a few days"),
+    ]
+}
+
+/// How the order is finalized, a fresh key is generated by default.
+fn finalize_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("csr")
+            .long("csr")
+            .takes_value(true)
+            .conflicts_with_all(&["cert-key", "reuse-key", "key-type", "must-staple"])
+            .help("A CSR (PEM or DER) to finalize with, its domains have to match"),
+        Arg::with_name("cert-key")
+            .long("cert-key")
+            .takes_value(true)
+            .conflicts_with_all(&["reuse-key", "key-type"])
+            .help("An existing certificate key (PEM) to use instead of a fresh one"),
+    ]
+}
+
+fn parses<T>(value: String) -> Result<(), String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value.parse::<T>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn rfc3339(value: String) -> Result<(), String> {
+    chrono::DateTime::parse_from_rfc3339(&value)
+        .map(|_| ())
+        .map_err(|e| format!("not an RFC3339 timestamp: {}", e))
+}
@@ -0,0 +1,71 @@
+#![deny(clippy::mem_forget)]
+//! shell completion scripts. clap generates the static part (commands, options,
+//! possible values), the names of the stored accounts and certificates are completed
+//! at runtime with `certifika complete-names accounts|certs`.
+use crate::{cli, APP_NAME};
+use anyhow::{anyhow, Result};
+use clap::Shell;
+
+/// the bash function of clap is wrapped, so that the positional names get completed
+const BASH_NAMES: &str = r#"
+_certifika_names() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" kind=""
+    case "${COMP_WORDS[1]} ${COMP_CWORD}" in
+        "renew 2"|"export 2"|"check 2"|"revoke 3") kind=certs ;;
+        "doctor 2"|"order 2"|"revoke 2"|"account 3") kind=accounts ;;
+    esac
+    if [[ -n "$kind" && "$cur" != -* ]]; then
+        COMPREPLY=( $(compgen -W "$(certifika complete-names $kind 2>/dev/null)" -- "$cur") )
+    else
+        _certifika
+    fi
+}
+
+complete -F _certifika_names -o bashdefault -o default certifika
+"#;
+
+/// replaces the call of `_certifika` closing the zsh script of clap
+const ZSH_NAMES: &str = r#"_certifika_names() {
+    local kind
+    case "$words[2] $CURRENT" in
+        ("renew 3"|"export 3"|"check 3"|"revoke 4") kind=certs ;;
+        ("doctor 3"|"order 3"|"revoke 3"|"account 4") kind=accounts ;;
+    esac
+    if [[ -n $kind && $PREFIX != -* ]]; then
+        compadd -- ${(f)"$(certifika complete-names $kind 2>/dev/null)"}
+    else
+        _certifika "$@"
+    fi
+}
+
+_certifika_names "$@"
+"#;
+
+const FISH_NAMES: &str = r#"
+complete -c certifika -n "__fish_seen_subcommand_from renew export check revoke" -f -a "(certifika complete-names certs 2>/dev/null)"
+complete -c certifika -n "__fish_seen_subcommand_from doctor order load find agree-tos revoke" -f -a "(certifika complete-names accounts 2>/dev/null)"
+"#;
+
+/// Returns the completion script for the `shell`: bash, zsh or fish.
+pub fn script(shell: &str) -> Result<String> {
+    let target = match shell {
+        "bash" => Shell::Bash,
+        "zsh" => Shell::Zsh,
+        "fish" => Shell::Fish,
+        _ => return Err(anyhow!("unsupported shell: {}", shell)),
+    };
+    let mut generated = Vec::new();
+    cli::app().gen_completions_to(APP_NAME, target, &mut generated);
+    let generated = String::from_utf8(generated)?;
+    Ok(match target {
+        Shell::Zsh => {
+            let call = "_certifika \"$@\"";
+            match generated.rfind(call) {
+                Some(at) => format!("{}{}", &generated[..at], ZSH_NAMES),
+                None => generated,
+            }
+        }
+        Shell::Fish => generated + FISH_NAMES,
+        _ => generated + BASH_NAMES,
+    })
+}
@@ -1,20 +1,165 @@
 #![deny(clippy::mem_forget)]
+//! the settings, layered: `~/.config/certifika/config.toml` (or the file of `--config`
+//! or `CERTIFIKA_CONFIG`), overridden by the `CERTIFIKA_*` env vars, which in turn
+//! are overridden by the command line flags. E.g.:
+//! ```toml
+//! store-type = "file"
+//! ca = "letsencrypt"
+//! account-key-type = "ecdsa-p256"
+//! contacts = ["mailto:ops@example.com"]
+//! challenges = "dns-01,http-01"
+//! pre-validation-hooks = ["systemctl stop nginx"]
+//! post-validation-hooks = ["systemctl start nginx"]
+//! hooks = ["systemctl reload nginx"]
+//!
+//! [zone-overrides]
+//! "example.com" = "Z1D633PJN98FT9"
+//! ```
 use ::log::LevelFilter;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::env;
+use std::path::PathBuf;
 
 pub struct Config {
     pub store: Box<dyn crate::storage::Store>,
+    /// the store keeps nothing past the run (`memory`), the certificates are printed
+    pub ephemeral: bool,
     pub log_level: LevelFilter,
+    pub log_target: crate::log::Target,
+    pub zones: crate::dns::ZoneResolver,
+    pub ca: crate::acme::Ca,
+    /// explicitly configured directory URL, takes precedence over the CA preset
+    pub directory_url: Option<String>,
+    pub staging: bool,
+    pub eab: Option<crate::acme::Eab>,
+    /// additional contact addresses to register accounts with
+    pub contacts: Vec<String>,
+    pub account_key_type: crate::acme::AccountKeyType,
+    pub challenge_prefs: Vec<crate::request::ChallengeType>,
+    pub domain_challenge_prefs:
+        std::collections::BTreeMap<String, Vec<crate::request::ChallengeType>>,
+    /// how the http-01 challenges are served, see `acme::Http01`
+    pub http01: crate::acme::Http01,
+    pub renewal_windows: crate::calendar::Calendar,
+    pub backoff: crate::backoff::Backoff,
+    pub rate_limit: crate::acme::Throttle,
+    pub nonce_max_age: std::time::Duration,
+    /// how many challenges of an order are validated at once
+    pub concurrency: usize,
+    pub order_timeout: std::time::Duration,
+    /// how long to wait for the store lock another process holds, see `storage::lock`
+    pub lock_timeout: std::time::Duration,
+    pub deploy: Vec<Box<dyn crate::deploy::Deploy>>,
+    /// the hooks of the requests that don't name their own, see `request::HookStage`
+    pub pre_validation_hooks: Vec<String>,
+    pub post_validation_hooks: Vec<String>,
+    pub hooks: Vec<String>,
+}
+
+/// The config file, every setting is optional. The keys are the names of the env vars,
+/// without the `CERTIFIKA_` prefix, in kebab case.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct File {
+    store_dir: Option<String>,
+    store_type: Option<String>,
+    store_cache_ttl: Option<u64>,
+    store_passphrase: Option<String>,
+    store_key_file: Option<String>,
+    store_layout: Option<String>,
+    azure_vault_url: Option<String>,
+    vault_mount: Option<String>,
+    vault_prefix: Option<String>,
+    vault_kv_version: Option<u8>,
+    vault_namespace: Option<String>,
+    vault_approle_mount: Option<String>,
+    vault_role_id: Option<String>,
+    vault_secret_id: Option<String>,
+    vault_kubernetes_mount: Option<String>,
+    vault_kubernetes_role: Option<String>,
+    vault_kubernetes_token_path: Option<String>,
+    // known even without the redis store, the config files stay valid either way
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    redis_url: Option<String>,
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    redis_prefix: Option<String>,
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    redis_ttl: Option<u64>,
+    log_level: Option<String>,
+    log_target: Option<String>,
+    zone_overrides: Option<BTreeMap<String, String>>,
+    ca: Option<String>,
+    directory_url: Option<String>,
+    staging: Option<bool>,
+    eab_kid: Option<String>,
+    eab_hmac_key: Option<String>,
+    contacts: Option<Vec<String>>,
+    account_key_type: Option<String>,
+    challenges: Option<String>,
+    domain_challenges: Option<String>,
+    webroot: Option<String>,
+    http01_listen: Option<String>,
+    renewal_windows: Option<String>,
+    backoff: Option<String>,
+    rate_limit: Option<String>,
+    nonce_max_age: Option<u64>,
+    concurrency: Option<usize>,
+    order_timeout: Option<u64>,
+    lock_timeout: Option<u64>,
+    deploy: Option<Vec<String>>,
+    pre_validation_hooks: Option<Vec<String>>,
+    post_validation_hooks: Option<Vec<String>>,
+    hooks: Option<Vec<String>>,
+}
+
+impl File {
+    /// Reads the config file at `path`, or at the default location, where it may
+    /// be missing.
+    fn read(path: Option<&str>) -> Result<Self> {
+        let (path, required) = match path
+            .map(str::to_string)
+            .or_else(|| env::var("CERTIFIKA_CONFIG").ok())
+        {
+            Some(path) => (PathBuf::from(path), true),
+            None => match env::var_os("HOME") {
+                Some(home) => (
+                    PathBuf::from(home).join(".config/certifika/config.toml"),
+                    false,
+                ),
+                None => return Ok(File::default()),
+            },
+        };
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(File::default())
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("can't read the config file {}", path.display()))
+            }
+        };
+        toml::from_str(&content).with_context(|| format!("invalid config file {}", path.display()))
+    }
+}
+
+/// The env var `name`, or else the `fallback` of the config file.
+fn var(name: &str, fallback: Option<String>) -> Option<String> {
+    env::var(name).ok().or(fallback)
 }
 
 impl Config {
-    pub fn parse() -> Result<Self> {
+    /// Reads the settings from the config file at `path` (the default one if `None`)
+    /// and the env vars.
+    pub fn parse(path: Option<&str>) -> Result<Self> {
+        let file = File::read(path)?;
         let home_dir = env::var("HOME").unwrap();
-        let base_dir =
-            env::var("CERTIFIKA_STORE_DIR").unwrap_or(format!("{}/.config/certifika", home_dir));
-        let log_level = match env::var("CERTIFIKA_LOG_LEVEL")
-            .unwrap_or_else(|_| "WARN".to_string())
+        let base_dir = var("CERTIFIKA_STORE_DIR", file.store_dir)
+            .unwrap_or(format!("{}/.config/certifika", home_dir));
+        let log_level = match var("CERTIFIKA_LOG_LEVEL", file.log_level)
+            .unwrap_or_else(|| "WARN".to_string())
             .as_str()
         {
             "DEBUG" => LevelFilter::Debug,
@@ -23,14 +168,240 @@ impl Config {
             "ERROR" => LevelFilter::Error,
             _ => LevelFilter::Info,
         };
-        let store: Box<dyn crate::storage::Store> = match env::var("CERTIFIKA_STORE_TYPE")
-            .unwrap_or_else(|_| "file".to_string())
-            .as_str()
-        {
-            "file" => Box::new(crate::storage::FileStore::init(&base_dir)?),
-            "vault" => Box::new(crate::storage::VaultStore::init("certifika")?),
+        let log_target =
+            crate::log::Target::parse(var("CERTIFIKA_LOG_TARGET", file.log_target).as_deref())?;
+        let cache_ttl = file.store_cache_ttl.map(|ttl| ttl.to_string());
+        // the content of the key file is the passphrase, the trailing newline aside
+        let passphrase = match var("CERTIFIKA_STORE_KEY_FILE", file.store_key_file) {
+            Some(path) => {
+                let mut key = std::fs::read(&path)
+                    .with_context(|| format!("can't read the store key file {}", path))?;
+                while key.last() == Some(&b'\n') || key.last() == Some(&b'\r') {
+                    key.pop();
+                }
+                Some(key)
+            }
+            None => {
+                var("CERTIFIKA_STORE_PASSPHRASE", file.store_passphrase).map(String::into_bytes)
+            }
+        };
+        let file_store = || -> Result<crate::storage::FileStore> {
+            let store = crate::storage::FileStore::init(&base_dir)?;
+            Ok(match &passphrase {
+                Some(passphrase) => store.encrypted(passphrase.clone()),
+                None => store,
+            })
+        };
+        let vault_auth_settings = [
+            ("VAULT_ROLE_ID", file.vault_role_id),
+            ("VAULT_SECRET_ID", file.vault_secret_id),
+            ("CERTIFIKA_VAULT_APPROLE_MOUNT", file.vault_approle_mount),
+            (
+                "CERTIFIKA_VAULT_KUBERNETES_ROLE",
+                file.vault_kubernetes_role,
+            ),
+            (
+                "CERTIFIKA_VAULT_KUBERNETES_MOUNT",
+                file.vault_kubernetes_mount,
+            ),
+            (
+                "CERTIFIKA_VAULT_KUBERNETES_TOKEN_PATH",
+                file.vault_kubernetes_token_path,
+            ),
+        ];
+        let defaults = crate::storage::VaultOptions::default();
+        let vault_options = crate::storage::VaultOptions {
+            mount: var("CERTIFIKA_VAULT_MOUNT", file.vault_mount).unwrap_or(defaults.mount),
+            prefix: var("CERTIFIKA_VAULT_PREFIX", file.vault_prefix).unwrap_or(defaults.prefix),
+            kv_version: match var(
+                "CERTIFIKA_VAULT_KV_VERSION",
+                file.vault_kv_version.map(|v| v.to_string()),
+            )
+            .as_deref()
+            {
+                Some("1") => Some(1),
+                Some("2") => Some(2),
+                Some(version) => return Err(anyhow!("unknown Vault KV version {}", version)),
+                None => None,
+            },
+            namespace: var("VAULT_NAMESPACE", file.vault_namespace),
+            auth: crate::storage::VaultAuth::from_settings(|name| {
+                let fallback = vault_auth_settings
+                    .iter()
+                    .find(|(setting, _)| *setting == name)
+                    .and_then(|(_, value)| value.clone());
+                var(name, fallback)
+            }),
+        };
+        if let Some(template) = var("CERTIFIKA_STORE_LAYOUT", file.store_layout) {
+            crate::storage::set_layout(crate::storage::Layout::parse(&template)?);
+        }
+        let store_type =
+            var("CERTIFIKA_STORE_TYPE", file.store_type).unwrap_or_else(|| "file".to_string());
+        let ephemeral = store_type == "memory";
+        let store: Box<dyn crate::storage::Store> = match store_type.as_str() {
+            "file" => Box::new(file_store()?),
+            "memory" => Box::new(crate::storage::MemStore::new()),
+            "vault" => Box::new(crate::storage::VaultStore::init(vault_options)?),
+            "azure" => Box::new(crate::storage::KeyVaultStore::init(
+                &var("CERTIFIKA_AZURE_VAULT_URL", file.azure_vault_url)
+                    .ok_or_else(|| anyhow!("CERTIFIKA_AZURE_VAULT_URL is not set"))?,
+                "certifika",
+            )?),
+            #[cfg(feature = "sled")]
+            "sled" => Box::new(crate::storage::DbStore::init(&format!("{}/db", base_dir))?),
+            #[cfg(not(feature = "sled"))]
+            "sled" => return Err(anyhow!("certifika is built without the sled store")),
+            #[cfg(feature = "redis")]
+            "redis" => Box::new(crate::storage::RedisStore::init(
+                &var("CERTIFIKA_REDIS_URL", file.redis_url)
+                    .unwrap_or_else(|| "redis://127.0.0.1/".to_string()),
+                &var("CERTIFIKA_REDIS_PREFIX", file.redis_prefix)
+                    .unwrap_or_else(|| "certifika".to_string()),
+                match var("CERTIFIKA_REDIS_TTL", file.redis_ttl.map(|t| t.to_string())) {
+                    Some(ttl) => Some(std::time::Duration::from_secs(ttl.parse()?)),
+                    None => None,
+                },
+            )?),
+            #[cfg(not(feature = "redis"))]
+            "redis" => return Err(anyhow!("certifika is built without the redis store")),
+            // the store dir caches Vault
+            "tiered" => Box::new(crate::storage::TieredStore::init(
+                file_store()?,
+                Box::new(crate::storage::VaultStore::init(vault_options)?),
+                std::time::Duration::from_secs(
+                    var("CERTIFIKA_STORE_CACHE_TTL", cache_ttl)
+                        .unwrap_or_else(|| "300".to_string())
+                        .parse()?,
+                ),
+            )),
             _ => return Err(anyhow!("unknown storage type")),
         };
-        Ok(Config { log_level, store })
+        let zone_overrides = file.zone_overrides.map(|zones| {
+            zones
+                .iter()
+                .map(|(domain, zone)| format!("{}={}", domain, zone))
+                .collect::<Vec<_>>()
+                .join(",")
+        });
+        let zones = crate::dns::ZoneResolver::parse(
+            &var("CERTIFIKA_ZONE_OVERRIDES", zone_overrides).unwrap_or_default(),
+        )?;
+        let ca = var("CERTIFIKA_CA", file.ca)
+            .unwrap_or_else(|| "letsencrypt".to_string())
+            .parse()?;
+        let directory_url = var("CERTIFIKA_DIRECTORY_URL", file.directory_url);
+        let staging = matches!(
+            var("CERTIFIKA_STAGING", file.staging.map(|s| s.to_string())).as_deref(),
+            Some("1") | Some("true") | Some("yes")
+        );
+        let eab = match (
+            var("CERTIFIKA_EAB_KID", file.eab_kid),
+            var("CERTIFIKA_EAB_HMAC_KEY", file.eab_hmac_key),
+        ) {
+            (Some(kid), Some(hmac_key)) => Some(crate::acme::Eab { kid, hmac_key }),
+            _ => None,
+        };
+        let contacts = split_list(
+            &var("CERTIFIKA_CONTACTS", file.contacts.map(|c| c.join(","))).unwrap_or_default(),
+        );
+        let account_key_type = var("CERTIFIKA_ACCOUNT_KEY_TYPE", file.account_key_type)
+            .unwrap_or_else(|| "ecdsa-p256".to_string())
+            .parse()?;
+        let challenge_prefs = crate::request::parse_challenge_prefs(
+            &var("CERTIFIKA_CHALLENGES", file.challenges).unwrap_or_default(),
+        )?;
+        let domain_challenge_prefs = crate::request::parse_domain_challenge_prefs(
+            &var("CERTIFIKA_DOMAIN_CHALLENGES", file.domain_challenges).unwrap_or_default(),
+        )?;
+        // a webroot means there is a web server on port 80 already
+        let http01 = match var("CERTIFIKA_WEBROOT", file.webroot) {
+            Some(webroot) => crate::acme::Http01::Webroot(PathBuf::from(webroot)),
+            None => crate::acme::Http01::Standalone(
+                var("CERTIFIKA_HTTP01_LISTEN", file.http01_listen)
+                    .unwrap_or_else(|| "0.0.0.0:80".to_string()),
+            ),
+        };
+        let renewal_windows = crate::calendar::Calendar::parse(
+            &var("CERTIFIKA_RENEWAL_WINDOWS", file.renewal_windows).unwrap_or_default(),
+        )?;
+        let backoff = crate::backoff::Backoff::parse(
+            &var("CERTIFIKA_BACKOFF", file.backoff).unwrap_or_default(),
+        )?;
+        let rate_limit = var("CERTIFIKA_RATE_LIMIT", file.rate_limit)
+            .unwrap_or_else(|| "warn".to_string())
+            .parse()?;
+        let nonce_max_age = std::time::Duration::from_secs(
+            var(
+                "CERTIFIKA_NONCE_MAX_AGE",
+                file.nonce_max_age.map(|secs| secs.to_string()),
+            )
+            .unwrap_or_else(|| "300".to_string())
+            .parse()?,
+        );
+        let concurrency = var(
+            "CERTIFIKA_CONCURRENCY",
+            file.concurrency.map(|n| n.to_string()),
+        )
+        .unwrap_or_else(|| "10".to_string())
+        .parse()?;
+        let order_timeout = std::time::Duration::from_secs(
+            var(
+                "CERTIFIKA_ORDER_TIMEOUT",
+                file.order_timeout.map(|secs| secs.to_string()),
+            )
+            .unwrap_or_else(|| "600".to_string())
+            .parse()?,
+        );
+        let lock_timeout = std::time::Duration::from_secs(
+            var(
+                "CERTIFIKA_LOCK_TIMEOUT",
+                file.lock_timeout.map(|secs| secs.to_string()),
+            )
+            .unwrap_or_else(|| "300".to_string())
+            .parse()?,
+        );
+        let deploy = crate::deploy::targets(
+            &var("CERTIFIKA_DEPLOY", file.deploy.map(|d| d.join(","))).unwrap_or_default(),
+        )?;
+        let pre_validation_hooks = file.pre_validation_hooks.unwrap_or_default();
+        let post_validation_hooks = file.post_validation_hooks.unwrap_or_default();
+        let hooks = file.hooks.unwrap_or_default();
+        Ok(Config {
+            log_level,
+            log_target,
+            store,
+            ephemeral,
+            zones,
+            ca,
+            directory_url,
+            staging,
+            eab,
+            contacts,
+            account_key_type,
+            challenge_prefs,
+            domain_challenge_prefs,
+            http01,
+            renewal_windows,
+            backoff,
+            rate_limit,
+            nonce_max_age,
+            concurrency,
+            order_timeout,
+            lock_timeout,
+            deploy,
+            pre_validation_hooks,
+            post_validation_hooks,
+            hooks,
+        })
     }
 }
+
+/// Splits a comma separated list, ignoring the blanks.
+pub fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
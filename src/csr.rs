@@ -0,0 +1,111 @@
+#![deny(clippy::mem_forget)]
+//! certificate signing requests ([RFC2986](https://tools.ietf.org/html/rfc2986)) to finalize orders with.
+use crate::request::KeyType;
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+
+/// A DER encoded CSR, with the PEM encoded private key of the certificate.
+pub struct Csr {
+    pub der: Vec<u8>,
+    pub key_pem: String,
+}
+
+/// TLS Feature extension ([RFC7633](https://tools.ietf.org/html/rfc7633)) OID
+const TLS_FEATURE: &[u64] = &[1, 3, 6, 1, 5, 5, 7, 1, 24];
+/// `SEQUENCE { INTEGER 5 }`, i.e. status_request, the OCSP Must-Staple
+const STATUS_REQUEST: &[u8] = &[0x30, 0x03, 0x02, 0x01, 0x05];
+
+/// Builds a CSR for a fresh `key_type` key, with all the `identifiers` as
+/// Subject Alternative Names. The first one is the subject's CN too, if it fits.
+/// With `must_staple`, the certificate is requested with the TLS Feature extension.
+pub fn build(identifiers: &[String], key_type: KeyType, must_staple: bool) -> Result<Csr> {
+    let mut params = params(identifiers, must_staple)?;
+    params.alg = key_type.algorithm();
+    if let Some(bits) = key_type.rsa_bits() {
+        let pkcs8 = crate::acme::generate_rsa_pkcs8(bits)?;
+        params.key_pair = Some(rcgen::KeyPair::from_der(&pkcs8)?);
+    }
+    sign(params)
+}
+
+/// Same as `build()`, but for an existing PEM encoded (PKCS#8) private key.
+pub fn build_for_key(identifiers: &[String], key_pem: &str, must_staple: bool) -> Result<Csr> {
+    let key_pair =
+        rcgen::KeyPair::from_pem(key_pem).map_err(|e| anyhow!("certificate key: {}", e))?;
+    let mut params = params(identifiers, must_staple)?;
+    params.alg = key_pair
+        .compatible_algs()
+        .next()
+        .ok_or_else(|| anyhow!("unsupported certificate key algorithm"))?;
+    params.key_pair = Some(key_pair);
+    sign(params)
+}
+
+/// The raw public key of the PEM encoded private key.
+pub fn public_key(key_pem: &str) -> Result<Vec<u8>> {
+    let key_pair =
+        rcgen::KeyPair::from_pem(key_pem).map_err(|e| anyhow!("certificate key: {}", e))?;
+    Ok(key_pair.public_key_raw().to_vec())
+}
+
+fn params(identifiers: &[String], must_staple: bool) -> Result<rcgen::CertificateParams> {
+    let names = san_names(identifiers)?;
+    let mut params = rcgen::CertificateParams::new(names.clone());
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    // CNs are limited to 64 characters, SANs are not
+    if names[0].len() <= 64 {
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, names[0].to_owned());
+    }
+    if must_staple {
+        params
+            .custom_extensions
+            .push(rcgen::CustomExtension::from_oid_content(
+                TLS_FEATURE,
+                STATUS_REQUEST.to_vec(),
+            ));
+    }
+    Ok(params)
+}
+
+fn sign(params: rcgen::CertificateParams) -> Result<Csr> {
+    let cert = rcgen::Certificate::from_params(params)?;
+    Ok(Csr {
+        der: cert.serialize_request_der()?,
+        key_pem: cert.serialize_private_key_pem(),
+    })
+}
+
+/// Normalizes the identifiers to lowercase names without the trailing dot,
+/// and drops the duplicates, which CAs reject.
+fn san_names(identifiers: &[String]) -> Result<Vec<String>> {
+    let mut names: Vec<String> = Vec::new();
+    for identifier in identifiers {
+        let name = identifier.trim_end_matches('.').to_lowercase();
+        if name.is_empty() {
+            return Err(anyhow!("empty identifier in {:?}", identifiers));
+        }
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    if names.is_empty() {
+        return Err(anyhow!("a CSR needs at least one identifier"));
+    }
+    Ok(names)
+}
+
+/// Reads a CSR made elsewhere, either DER or PEM encoded.
+pub fn read(path: &str) -> Result<Vec<u8>> {
+    let csr = fs::read(path).with_context(|| format!("can't read the CSR {}", path))?;
+    if !csr.starts_with(b"-----BEGIN") {
+        return Ok(csr);
+    }
+    let body: String = String::from_utf8(csr)?
+        .lines()
+        .filter(|l| !l.starts_with("-----"))
+        .map(str::trim)
+        .collect();
+    Ok(base64::decode(body)?)
+}
@@ -0,0 +1,61 @@
+#![deny(clippy::mem_forget)]
+//! deploy targets, installing renewed certificates where the services can pick them up.
+//!
+//! Targets are configured with a comma separated list of their names:
+//! ```text
+//! CERTIFIKA_DEPLOY="windows"
+//! CERTIFIKA_DEPLOY="keychain"
+//! CERTIFIKA_DEPLOY="reload-nginx,reload-postfix"
+//! ```
+//! Requests can name their own targets (`deploy_targets`), see `reload` for the ones
+//! of the common servers.
+//! Every target gets the certificate in turn, the first failure stops the deploy.
+use crate::acme::IssuedCertificate;
+use anyhow::{anyhow, Result};
+
+#[cfg(target_os = "macos")]
+mod macos;
+mod reload;
+#[cfg(windows)]
+mod windows;
+
+pub trait Deploy {
+    /// Installs the freshly `issued` certificate, `name` being its primary domain.
+    fn deploy(&self, name: &str, issued: &IssuedCertificate) -> Result<()>;
+}
+
+/// Builds the deploy targets from the `spec`, see the module docs.
+pub fn targets(spec: &str) -> Result<Vec<Box<dyn Deploy>>> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(target)
+        .collect()
+}
+
+/// Builds the deploy targets listed by their `names`.
+pub fn from_names(names: &[String]) -> Result<Vec<Box<dyn Deploy>>> {
+    names.iter().map(|n| target(n.trim())).collect()
+}
+
+fn target(name: &str) -> Result<Box<dyn Deploy>> {
+    match name {
+        #[cfg(windows)]
+        "windows" => Ok(Box::new(windows::CertStore::from_env()?)),
+        #[cfg(not(windows))]
+        "windows" => Err(anyhow!(
+            "the windows deploy target is only available on Windows"
+        )),
+        #[cfg(target_os = "macos")]
+        "keychain" => Ok(Box::new(macos::Keychain::from_env()?)),
+        #[cfg(not(target_os = "macos"))]
+        "keychain" => Err(anyhow!(
+            "the keychain deploy target is only available on macOS"
+        )),
+        "reload-nginx" => Ok(Box::new(reload::Reload::from_env(reload::Server::Nginx))),
+        "reload-apache" => Ok(Box::new(reload::Reload::from_env(reload::Server::Apache))),
+        "reload-haproxy" => Ok(Box::new(reload::Reload::from_env(reload::Server::Haproxy))),
+        "reload-postfix" => Ok(Box::new(reload::Reload::from_env(reload::Server::Postfix))),
+        _ => Err(anyhow!("unknown deploy target: {}", name)),
+    }
+}
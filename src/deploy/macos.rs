@@ -0,0 +1,145 @@
+//! installs certificates and their keys into a macOS keychain with the `security` tool.
+//!
+//! The keychain is the system one by default, or `CERTIFIKA_KEYCHAIN`. Applications
+//! allowed to use the key without a prompt are listed in `CERTIFIKA_KEYCHAIN_APPS`,
+//! and with `CERTIFIKA_KEYCHAIN_TRUST` the leaf certificate is marked as trusted for
+//! the listed policies (`ssl`, `smime`, `eap`, `IPSec`, ...):
+//! ```text
+//! CERTIFIKA_KEYCHAIN_APPS="/usr/sbin/httpd,/usr/local/bin/nginx"
+//! CERTIFIKA_KEYCHAIN_TRUST="ssl,eap"
+//! ```
+use super::Deploy;
+use crate::acme::IssuedCertificate;
+use anyhow::{anyhow, Result};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const SYSTEM_KEYCHAIN: &str = "/Library/Keychains/System.keychain";
+
+pub struct Keychain {
+    keychain: String,
+    apps: Vec<String>,
+    trust: Vec<String>,
+}
+
+impl Keychain {
+    pub fn from_env() -> Result<Self> {
+        Ok(Keychain {
+            keychain: env::var("CERTIFIKA_KEYCHAIN")
+                .unwrap_or_else(|_| SYSTEM_KEYCHAIN.to_string()),
+            apps: list("CERTIFIKA_KEYCHAIN_APPS"),
+            trust: list("CERTIFIKA_KEYCHAIN_TRUST"),
+        })
+    }
+
+    fn import(&self, key: &Path, chain: &Path) -> Result<()> {
+        let mut import_key = Command::new("security");
+        import_key
+            .arg("import")
+            .arg(key)
+            .args(&["-k", &self.keychain, "-t", "priv"]);
+        for app in &self.apps {
+            import_key.args(&["-T", app]);
+        }
+        security(&mut import_key)?;
+        security(Command::new("security").arg("import").arg(chain).args(&[
+            "-k",
+            &self.keychain,
+            "-t",
+            "cert",
+            "-f",
+            "pemseq",
+        ]))
+    }
+
+    fn mark_trusted(&self, leaf: &Path) -> Result<()> {
+        if self.trust.is_empty() {
+            return Ok(());
+        }
+        let mut trust = Command::new("security");
+        trust.args(&[
+            "add-trusted-cert",
+            "-r",
+            "trustAsRoot",
+            "-k",
+            &self.keychain,
+        ]);
+        if self.keychain == SYSTEM_KEYCHAIN {
+            // admin trust settings, rather than the ones of the user running certifika
+            trust.arg("-d");
+        }
+        for policy in &self.trust {
+            trust.args(&["-p", policy]);
+        }
+        security(trust.arg(leaf))
+    }
+}
+
+impl Deploy for Keychain {
+    fn deploy(&self, name: &str, issued: &IssuedCertificate) -> Result<()> {
+        let key = TempFile::create(&format!("{}.key", name), issued.key()?)?;
+        let chain = TempFile::create(&format!("{}.crt", name), &issued.chain_pem)?;
+        self.import(&key.0, &chain.0)?;
+        const END: &str = "-----END CERTIFICATE-----";
+        let leaf_pem = match issued.chain_pem.find(END) {
+            Some(end) => &issued.chain_pem[..end + END.len()],
+            None => &issued.chain_pem,
+        };
+        let leaf = TempFile::create(&format!("{}.leaf.crt", name), leaf_pem)?;
+        self.mark_trusted(&leaf.0)?;
+        log::info!(
+            r#"{{"op":"keychain import","keychain":"{}","name":"{}","trusted_for":{:?}}}"#,
+            self.keychain,
+            name,
+            self.trust
+        );
+        Ok(())
+    }
+}
+
+/// A file readable only by the owner, removed on drop.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn create(name: &str, content: &str) -> Result<Self> {
+        let path = env::temp_dir().join(format!("certifika-{}-{}", std::process::id(), name));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        file.write_all(content.as_bytes())?;
+        Ok(TempFile(path))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn security(command: &mut Command) -> Result<()> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "security failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+fn list(var: &str) -> Vec<String> {
+    env::var(var)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|i| !i.is_empty())
+        .map(str::to_string)
+        .collect()
+}
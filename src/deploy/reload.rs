@@ -0,0 +1,109 @@
+//! ready-made deploys for the common servers: the certificate files are exported
+//! (see the `export` module) to the conventional directory of the server, which is
+//! then told to reload.
+//!
+//! | target           | directory            | reload                     |
+//! |------------------|----------------------|----------------------------|
+//! | `reload-nginx`   | `/etc/nginx/certs`   | `systemctl reload nginx`   |
+//! | `reload-apache`  | `/etc/apache2/certs` | `systemctl reload apache2` |
+//! | `reload-haproxy` | `/etc/haproxy/certs` | `systemctl reload haproxy` |
+//! | `reload-postfix` | `/etc/postfix/certs` | `postfix reload`           |
+//!
+//! HAProxy wants the certificate and the key in one file, so it also gets
+//! `example.com.pem`. Both can be overridden per server, e.g.:
+//! ```text
+//! CERTIFIKA_NGINX_DIR="/etc/ssl/nginx"
+//! CERTIFIKA_NGINX_RELOAD="nginx -s reload"
+//! ```
+use super::Deploy;
+use crate::acme::IssuedCertificate;
+use crate::export;
+use anyhow::{anyhow, Result};
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Server {
+    Nginx,
+    Apache,
+    Haproxy,
+    Postfix,
+}
+
+impl Server {
+    fn name(self) -> &'static str {
+        match self {
+            Server::Nginx => "nginx",
+            Server::Apache => "apache",
+            Server::Haproxy => "haproxy",
+            Server::Postfix => "postfix",
+        }
+    }
+
+    fn default_dir(self) -> &'static str {
+        match self {
+            Server::Nginx => "/etc/nginx/certs",
+            Server::Apache => "/etc/apache2/certs",
+            Server::Haproxy => "/etc/haproxy/certs",
+            Server::Postfix => "/etc/postfix/certs",
+        }
+    }
+
+    fn default_reload(self) -> &'static str {
+        match self {
+            Server::Nginx => "systemctl reload nginx",
+            Server::Apache => "systemctl reload apache2",
+            Server::Haproxy => "systemctl reload haproxy",
+            Server::Postfix => "postfix reload",
+        }
+    }
+}
+
+pub struct Reload {
+    server: Server,
+    dir: PathBuf,
+    reload: String,
+}
+
+impl Reload {
+    pub fn from_env(server: Server) -> Self {
+        let var = |suffix: &str| {
+            env::var(format!(
+                "CERTIFIKA_{}_{}",
+                server.name().to_uppercase(),
+                suffix
+            ))
+        };
+        Reload {
+            server,
+            dir: PathBuf::from(var("DIR").unwrap_or_else(|_| server.default_dir().to_string())),
+            reload: var("RELOAD").unwrap_or_else(|_| server.default_reload().to_string()),
+        }
+    }
+}
+
+impl Deploy for Reload {
+    fn deploy(&self, name: &str, issued: &IssuedCertificate) -> Result<()> {
+        export::export(issued, name, &self.dir, export::Format::Pem, "")?;
+        if self.server == Server::Haproxy {
+            let key = issued
+                .key_pem
+                .as_ref()
+                .ok_or_else(|| anyhow!("HAProxy needs the key, and it's not known"))?;
+            let combined = format!("{}\n{}", issued.chain_pem.trim_end(), key);
+            export::write(&self.dir.join(format!("{}.pem", name)), combined.as_bytes())?;
+        }
+        let status = Command::new("sh").args(&["-c", &self.reload]).status()?;
+        if !status.success() {
+            return Err(anyhow!("{} failed with {}", self.reload, status));
+        }
+        log::info!(
+            r#"{{"op":"deploy","target":"reload-{}","domain":"{}","dir":{:?}}}"#,
+            self.server.name(),
+            name,
+            self.dir
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+//! imports certificates into the Windows certificate store and binds them to IIS sites.
+//!
+//! The leaf certificate goes to the local machine store (`MY` by default, or
+//! `CERTIFIKA_WINDOWS_STORE`), its private key is persisted as a machine key with the
+//! Microsoft Software Key Storage Provider, and the intermediates go to the `CA` store.
+//! IIS sites are bound with `netsh http` SNI bindings, listed as `host:port` pairs:
+//! ```text
+//! CERTIFIKA_IIS_BINDINGS="example.com:443,www.example.com:443"
+//! ```
+use super::Deploy;
+use crate::acme::IssuedCertificate;
+use anyhow::{anyhow, Result};
+use std::env;
+use std::ffi::c_void;
+use std::process::Command;
+use std::ptr;
+use windows::core::{HSTRING, PCWSTR, PWSTR};
+use windows::Win32::Security::Cryptography::{
+    BCryptBuffer, BCryptBufferDesc, CertAddEncodedCertificateToStore, CertCloseStore,
+    CertFreeCertificateContext, CertOpenStore, CertSetCertificateContextProperty, NCryptFreeObject,
+    NCryptImportKey, NCryptOpenStorageProvider, CERT_CONTEXT, CERT_KEY_PROV_INFO_PROP_ID,
+    CERT_OPEN_STORE_FLAGS, CERT_STORE_ADD_REPLACE_EXISTING, CERT_STORE_PROV_SYSTEM_W,
+    CERT_SYSTEM_STORE_LOCAL_MACHINE_ID, CERT_SYSTEM_STORE_LOCATION_SHIFT, CRYPT_KEY_FLAGS,
+    CRYPT_KEY_PROV_INFO, HCERTSTORE, MS_KEY_STORAGE_PROVIDER, NCRYPTBUFFER_PKCS_KEY_NAME,
+    NCRYPT_FLAGS, NCRYPT_KEY_HANDLE, NCRYPT_MACHINE_KEY_FLAG, NCRYPT_OVERWRITE_KEY_FLAG,
+    NCRYPT_PKCS8_PRIVATE_KEY_BLOB, NCRYPT_PROV_HANDLE, PKCS_7_ASN_ENCODING, X509_ASN_ENCODING,
+};
+
+/// The application ID IIS registers its SSL bindings with.
+const IIS_APP_ID: &str = "{4dc3e181-e14b-4a21-b022-59fc669b0914}";
+
+pub struct CertStore {
+    store: String,
+    bindings: Vec<String>,
+}
+
+impl CertStore {
+    pub fn from_env() -> Result<Self> {
+        let bindings = env::var("CERTIFIKA_IIS_BINDINGS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|b| !b.is_empty())
+            .map(str::to_string)
+            .collect::<Vec<String>>();
+        if let Some(binding) = bindings.iter().find(|b| !b.contains(':')) {
+            return Err(anyhow!(
+                "invalid IIS binding, expected host:port: {}",
+                binding
+            ));
+        }
+        Ok(CertStore {
+            store: env::var("CERTIFIKA_WINDOWS_STORE").unwrap_or_else(|_| "MY".to_string()),
+            bindings,
+        })
+    }
+
+    /// Imports the leaf certificate along with its private key, returns the thumbprint.
+    fn import(&self, name: &str, issued: &IssuedCertificate) -> Result<String> {
+        let chain = crate::cert::chain_der(&issued.chain_pem)?;
+        let (leaf, intermediates) = chain
+            .split_first()
+            .ok_or_else(|| anyhow!("empty certificate chain"))?;
+        let key_name = format!("certifika-{}-{}", name, issued.expires);
+        import_key(&key_name, &crate::cert::pem_body(issued.key()?)?)?;
+
+        let store = SystemStore::open(&self.store)?;
+        let context = store.add(leaf)?;
+        let mut container: Vec<u16> = key_name.encode_utf16().chain(Some(0)).collect();
+        let mut provider: Vec<u16> = unsafe { MS_KEY_STORAGE_PROVIDER.as_wide() }
+            .iter()
+            .copied()
+            .chain(Some(0))
+            .collect();
+        let key_info = CRYPT_KEY_PROV_INFO {
+            pwszContainerName: PWSTR(container.as_mut_ptr()),
+            pwszProvName: PWSTR(provider.as_mut_ptr()),
+            dwProvType: 0,
+            dwFlags: CRYPT_KEY_FLAGS(NCRYPT_MACHINE_KEY_FLAG.0),
+            cProvParam: 0,
+            rgProvParam: ptr::null_mut(),
+            dwKeySpec: 0,
+        };
+        let linked = unsafe {
+            CertSetCertificateContextProperty(
+                context,
+                CERT_KEY_PROV_INFO_PROP_ID,
+                0,
+                Some(&key_info as *const CRYPT_KEY_PROV_INFO as *const c_void),
+            )
+        };
+        unsafe { CertFreeCertificateContext(Some(context)) };
+        linked
+            .ok()
+            .map_err(|e| anyhow!("linking the private key: {}", e))?;
+
+        let ca_store = SystemStore::open("CA")?;
+        for intermediate in intermediates {
+            let context = ca_store.add(intermediate)?;
+            unsafe { CertFreeCertificateContext(Some(context)) };
+        }
+        Ok(thumbprint(leaf))
+    }
+
+    fn bind(&self, thumbprint: &str) -> Result<()> {
+        for binding in &self.bindings {
+            let hostnameport = format!("hostnameport={}", binding);
+            // there is no update for an existing binding, and deleting
+            // a missing one fails, which is fine.
+            let _ = Command::new("netsh")
+                .args(&["http", "delete", "sslcert", &hostnameport])
+                .output();
+            let output = Command::new("netsh")
+                .args(&["http", "add", "sslcert", &hostnameport])
+                .arg(format!("certhash={}", thumbprint))
+                .arg(format!("certstorename={}", self.store))
+                .arg(format!("appid={}", IIS_APP_ID))
+                .output()?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "netsh binding {} failed: {}",
+                    binding,
+                    String::from_utf8_lossy(&output.stdout).trim()
+                ));
+            }
+            log::info!(
+                r#"{{"op":"iis binding","binding":"{}","thumbprint":"{}"}}"#,
+                binding,
+                thumbprint
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Deploy for CertStore {
+    fn deploy(&self, name: &str, issued: &IssuedCertificate) -> Result<()> {
+        let thumbprint = self.import(name, issued)?;
+        log::info!(
+            r#"{{"op":"windows store import","store":"{}","thumbprint":"{}"}}"#,
+            self.store,
+            thumbprint
+        );
+        self.bind(&thumbprint)
+    }
+}
+
+/// A local machine system store, closed on drop.
+struct SystemStore(HCERTSTORE);
+
+impl SystemStore {
+    fn open(name: &str) -> Result<Self> {
+        let name = HSTRING::from(name);
+        let handle = unsafe {
+            CertOpenStore(
+                CERT_STORE_PROV_SYSTEM_W,
+                X509_ASN_ENCODING | PKCS_7_ASN_ENCODING,
+                None,
+                CERT_OPEN_STORE_FLAGS(
+                    CERT_SYSTEM_STORE_LOCAL_MACHINE_ID << CERT_SYSTEM_STORE_LOCATION_SHIFT,
+                ),
+                Some(name.as_ptr() as *const c_void),
+            )
+        }
+        .map_err(|e| anyhow!("opening the certificate store: {}", e))?;
+        Ok(SystemStore(handle))
+    }
+
+    /// Adds a DER encoded certificate, replacing an existing one. The returned
+    /// context has to be freed by the caller.
+    fn add(&self, der: &[u8]) -> Result<*mut CERT_CONTEXT> {
+        let mut context: *mut CERT_CONTEXT = ptr::null_mut();
+        unsafe {
+            CertAddEncodedCertificateToStore(
+                self.0,
+                X509_ASN_ENCODING | PKCS_7_ASN_ENCODING,
+                der,
+                CERT_STORE_ADD_REPLACE_EXISTING,
+                Some(&mut context),
+            )
+        }
+        .ok()
+        .map_err(|e| anyhow!("adding the certificate to the store: {}", e))?;
+        Ok(context)
+    }
+}
+
+impl Drop for SystemStore {
+    fn drop(&mut self) {
+        unsafe { CertCloseStore(self.0, 0) };
+    }
+}
+
+/// Persists the PKCS#8 encoded key as a machine key named `key_name`.
+fn import_key(key_name: &str, pkcs8: &[u8]) -> Result<()> {
+    let mut provider = NCRYPT_PROV_HANDLE::default();
+    unsafe { NCryptOpenStorageProvider(&mut provider, MS_KEY_STORAGE_PROVIDER, 0) }
+        .map_err(|e| anyhow!("opening the key storage provider: {}", e))?;
+    let mut name: Vec<u16> = key_name.encode_utf16().chain(Some(0)).collect();
+    let mut buffer = BCryptBuffer {
+        cbBuffer: (name.len() * 2) as u32,
+        BufferType: NCRYPTBUFFER_PKCS_KEY_NAME,
+        pvBuffer: name.as_mut_ptr() as *mut c_void,
+    };
+    let parameters = BCryptBufferDesc {
+        ulVersion: 0,
+        cBuffers: 1,
+        pBuffers: &mut buffer,
+    };
+    let mut key = NCRYPT_KEY_HANDLE::default();
+    let imported = unsafe {
+        NCryptImportKey(
+            provider,
+            NCRYPT_KEY_HANDLE::default(),
+            NCRYPT_PKCS8_PRIVATE_KEY_BLOB,
+            Some(&parameters),
+            &mut key,
+            pkcs8,
+            NCRYPT_FLAGS(NCRYPT_MACHINE_KEY_FLAG.0 | NCRYPT_OVERWRITE_KEY_FLAG.0),
+        )
+    };
+    unsafe {
+        if !key.is_invalid() {
+            let _ = NCryptFreeObject(key);
+        }
+        let _ = NCryptFreeObject(provider);
+    }
+    imported.map_err(|e| anyhow!("importing the private key: {}", e))
+}
+
+/// SHA-1 of the DER encoded certificate, the way Windows tools display it.
+fn thumbprint(der: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, der)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect()
+}
@@ -0,0 +1,78 @@
+#![deny(clippy::mem_forget)]
+//! helpers for the [dns-01](https://tools.ietf.org/html/rfc8555#section-8.4) challenge.
+//!
+//! The zone holding `_acme-challenge.foo.bar.example.com` can't always be derived from
+//! the name itself (delegated subzones, provider specific zone IDs), so explicit
+//! mappings can be configured:
+//! ```text
+//! CERTIFIKA_ZONE_OVERRIDES="example.com=Z1D633PJN98FT9,dev.example.org=example-dev"
+//! ```
+//! Identifiers without an override fall back to their registered domain,
+//! as computed with the public suffix list rules. The validation hooks publishing
+//! the records get the zone of each in `CERTIFIKA_CHALLENGES`, see
+//! `CertRequest::run_validation_hooks()`.
+use crate::psl;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+/// Maps identifiers to the DNS provider zones they belong to.
+#[derive(Debug, Default)]
+pub struct ZoneResolver {
+    overrides: HashMap<String, String>,
+}
+
+impl ZoneResolver {
+    pub fn new(overrides: HashMap<String, String>) -> Self {
+        ZoneResolver {
+            overrides: overrides
+                .into_iter()
+                .map(|(domain, zone)| (normalize(&domain), zone))
+                .collect(),
+        }
+    }
+
+    /// Parses comma separated `domain=zone` pairs.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut overrides = HashMap::new();
+        for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let mut kv = pair.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(domain), Some(zone)) if !domain.is_empty() && !zone.is_empty() => {
+                    overrides.insert(domain.trim().to_string(), zone.trim().to_string());
+                }
+                _ => return Err(anyhow!("invalid zone override: {}", pair)),
+            }
+        }
+        Ok(ZoneResolver::new(overrides))
+    }
+
+    /// Returns the zone for the `domain`. The most specific override wins,
+    /// i.e. for `a.dev.example.com` an override for `dev.example.com` is preferred
+    /// over the one for `example.com`.
+    pub fn zone_for(&self, domain: &str) -> Option<String> {
+        let domain = normalize(domain);
+        let mut name = domain.as_str();
+        loop {
+            if let Some(zone) = self.overrides.get(name) {
+                return Some(zone.to_owned());
+            }
+            match name.find('.') {
+                Some(i) => name = &name[i + 1..],
+                None => break,
+            }
+        }
+        psl::registered_domain(&domain)
+    }
+}
+
+/// Returns the name of the TXT record to provision for the `domain`.
+pub fn challenge_record(domain: &str) -> String {
+    format!("_acme-challenge.{}", normalize(domain))
+}
+
+fn normalize(domain: &str) -> String {
+    domain
+        .trim_start_matches("*.")
+        .trim_end_matches('.')
+        .to_lowercase()
+}
@@ -0,0 +1,152 @@
+#![deny(clippy::mem_forget)]
+//! exports of stored certificates into files, for the deployments that pull what they
+//! need rather than being deployed to. The store stays the single source of truth.
+//!
+//! `pem` writes the usual set of files:
+//! ```text
+//! example.com.crt        the leaf certificate
+//! example.com.chain.pem  the intermediates
+//! example.com.full.pem   the leaf with the intermediates
+//! example.com.key        the private key
+//! ```
+//! `pkcs12` writes them all into `example.com.p12`.
+use crate::acme::IssuedCertificate;
+use anyhow::{anyhow, Result};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Pem,
+    Pkcs12,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "pem" => Ok(Format::Pem),
+            "pkcs12" | "p12" => Ok(Format::Pkcs12),
+            _ => Err(anyhow!("unknown export format: {}", name)),
+        }
+    }
+}
+
+/// Writes the `issued` certificate `name` into the `dir` in the `format`, the PKCS#12
+/// file is protected with the `password`. Returns the paths of the written files.
+pub fn export(
+    issued: &IssuedCertificate,
+    name: &str,
+    dir: &Path,
+    format: Format,
+    password: &str,
+) -> Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    export_with(issued, name, dir, format, password, write)
+}
+
+/// Like `export()`, into a dir of `temp_dir()`: none of the files may exist yet.
+pub fn export_new(
+    issued: &IssuedCertificate,
+    name: &str,
+    dir: &Path,
+    format: Format,
+    password: &str,
+) -> Result<Vec<PathBuf>> {
+    export_with(issued, name, dir, format, password, write_new)
+}
+
+fn export_with(
+    issued: &IssuedCertificate,
+    name: &str,
+    dir: &Path,
+    format: Format,
+    password: &str,
+    write: fn(&Path, &[u8]) -> Result<()>,
+) -> Result<Vec<PathBuf>> {
+    let certs = crate::cert::chain_der(&issued.chain_pem)?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates in the chain of {}", name));
+    }
+    match format {
+        Format::Pem => {
+            let path = |suffix: &str| dir.join(format!("{}.{}", name, suffix));
+            let mut files = vec![
+                (path("crt"), crate::cert::pem(&certs[..1])),
+                (path("chain.pem"), crate::cert::pem(&certs[1..])),
+                (path("full.pem"), issued.chain_pem.to_owned()),
+            ];
+            // there is no key for the certificates issued for a supplied CSR
+            if let Some(key_pem) = &issued.key_pem {
+                files.push((path("key"), key_pem.to_owned()));
+            }
+            for (path, content) in &files {
+                write(path, content.as_bytes())?;
+            }
+            Ok(files.into_iter().map(|(path, _)| path).collect())
+        }
+        Format::Pkcs12 => {
+            let key_der = crate::cert::pem_body(issued.key()?)?;
+            let pfx = p12::PFX::new(
+                &certs[0],
+                &key_der,
+                certs.get(1).map(Vec::as_slice),
+                password,
+                name,
+            )
+            .ok_or_else(|| anyhow!("can't build the PKCS#12 archive of {}", name))?;
+            let path = dir.join(format!("{}.p12", name));
+            write(&path, &pfx.to_der())?;
+            Ok(vec![path])
+        }
+    }
+}
+
+/// Creates a fresh directory for the certificate `name` in the temp dir, accessible
+/// by the owner only, to export it into for a while. Its name is random and it must
+/// not exist yet, so no other local user can have it prepared to get at the key.
+pub fn temp_dir(name: &str) -> Result<PathBuf> {
+    let mut random = [0u8; 16];
+    SystemRandom::new()
+        .fill(&mut random)
+        .map_err(|_| anyhow!("can't name the temp dir, no randomness"))?;
+    let suffix: String = random.iter().map(|b| format!("{:02x}", b)).collect();
+    let dir = std::env::temp_dir().join(format!("certifika-{}-{}", name.replace('*', "_"), suffix));
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(&dir)?;
+    Ok(dir)
+}
+
+/// Writes the file readable by the owner only, there are private keys in most of them.
+pub fn write(path: &Path, content: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    open(options, path)?.write_all(content)?;
+    Ok(())
+}
+
+/// Like `write()`, but the file must not exist yet, a symlink in its place included.
+fn write_new(path: &Path, content: &[u8]) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    open(options, path)?.write_all(content)?;
+    Ok(())
+}
+
+fn open(mut options: OpenOptions, path: &Path) -> Result<fs::File> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    Ok(options.open(path)?)
+}
@@ -0,0 +1,80 @@
+#![deny(clippy::mem_forget)]
+//! the HTTP agent shared by all the requests, so connections to the CA (and their TLS
+//! handshakes) are reused across the requests of an order, instead of set up for each.
+//!
+//! ureq doesn't tell whether a request went over a pooled connection, but it resolves
+//! the host only when it opens a new one, so the resolver counts the new connections.
+//! Comparing the time of requests over new and reused connections shows what
+//! the connection setup (mostly the TLS handshake) costs.
+use crate::metrics::{self, Counter};
+use once_cell::sync::Lazy;
+use std::cell::Cell;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Instant;
+
+static AGENT: Lazy<ureq::Agent> = Lazy::new(|| {
+    ureq::AgentBuilder::new()
+        .resolver(|netloc: &str| -> io::Result<Vec<SocketAddr>> {
+            CONNECTIONS.with(|c| c.set(c.get() + 1));
+            netloc.to_socket_addrs().map(Iterator::collect)
+        })
+        .build()
+});
+
+thread_local! {
+    /// connections opened by this thread, requests are synchronous,
+    /// so a request's connection is opened on the thread sending it
+    static CONNECTIONS: Cell<u64> = Cell::new(0);
+}
+
+pub fn agent() -> &'static ureq::Agent {
+    &AGENT
+}
+
+/// Sends a request with `send`, recording whether it took a new connection and how long it took.
+pub fn timed<T>(url: &str, send: impl FnOnce() -> T) -> T {
+    let before = CONNECTIONS.with(Cell::get);
+    let started = Instant::now();
+    let result = send();
+    let millis = started.elapsed().as_millis() as u64;
+    let reused = CONNECTIONS.with(Cell::get) == before;
+    if reused {
+        metrics::increment(Counter::ConnectionsReused);
+        metrics::increment_by(Counter::ReusedConnectionMillis, millis);
+    } else {
+        metrics::increment(Counter::ConnectionsCreated);
+        metrics::increment_by(Counter::NewConnectionMillis, millis);
+    }
+    log::debug!(
+        r#"{{"op":"http","url":"{}","connection":"{}","ms":{}}}"#,
+        url,
+        if reused { "reused" } else { "new" },
+        millis
+    );
+    result
+}
+
+/// Logs the connection reuse statistics, with the estimated cost of a new connection.
+pub fn log_stats() {
+    let registry = metrics::registry();
+    let created = registry.get(Counter::ConnectionsCreated);
+    let reused = registry.get(Counter::ConnectionsReused);
+    let average = |millis: Counter, count: u64| {
+        if count == 0 {
+            0
+        } else {
+            registry.get(millis) / count
+        }
+    };
+    let new_ms = average(Counter::NewConnectionMillis, created);
+    let reused_ms = average(Counter::ReusedConnectionMillis, reused);
+    log::debug!(
+        r#"{{"op":"http pool","created":{},"reused":{},"new_ms":{},"reused_ms":{},"setup_ms":{}}}"#,
+        created,
+        reused,
+        new_ms,
+        reused_ms,
+        new_ms.saturating_sub(reused_ms)
+    );
+}
@@ -0,0 +1,159 @@
+#![deny(clippy::mem_forget)]
+//! imports of the accounts of other ACME clients, so their users can switch
+//! to certifika without registering again.
+use crate::acme::{pkcs8_from_jwk, pkcs8_from_pkcs1, pkcs8_from_sec1};
+use crate::cert::pem_body;
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::Path;
+
+/// An account found in the files of another client.
+pub struct Imported {
+    /// the first `mailto:` contact of the account, if the client keeps them
+    pub email: Option<String>,
+    pub directory_url: String,
+    pub kid: String,
+    /// the account key, PKCS#8 encoded
+    pub pkcs8: Vec<u8>,
+}
+
+/// Finds the accounts in the certbot config dir, usually `/etc/letsencrypt`. They are kept
+/// in `accounts/<directory host>/<directory path>/<account id>/`, with the account
+/// object in `regr.json`, and the key in `private_key.json` (a JWK).
+pub fn certbot(config_dir: &Path) -> Result<Vec<Imported>> {
+    let accounts = config_dir.join("accounts");
+    let mut dirs = Vec::new();
+    find_dirs(&accounts, "regr.json", &mut dirs)?;
+    if dirs.is_empty() {
+        return Err(anyhow!("no certbot accounts in {}", accounts.display()));
+    }
+    let mut imported = Vec::new();
+    for dir in dirs {
+        let read_json = |file: &str| -> Result<serde_json::Value> {
+            let path = dir.join(file);
+            let content =
+                fs::read(&path).with_context(|| format!("can't read {}", path.display()))?;
+            Ok(serde_json::from_slice(&content)?)
+        };
+        let regr = read_json("regr.json")?;
+        let kid = regr["uri"]
+            .as_str()
+            .ok_or_else(|| anyhow!("no account URL in {}", dir.display()))?;
+        // the directory URL is spelled out by the path, minus the account id
+        let server = dir
+            .parent()
+            .and_then(|d| d.strip_prefix(&accounts).ok())
+            .ok_or_else(|| anyhow!("unexpected certbot account path {}", dir.display()))?;
+        let server: Vec<String> = server
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        imported.push(Imported {
+            email: mailto(&regr["body"]["contact"]),
+            directory_url: format!("https://{}", server.join("/")),
+            kid: kid.to_string(),
+            pkcs8: pkcs8_from_jwk(&read_json("private_key.json")?)?,
+        });
+    }
+    Ok(imported)
+}
+
+/// Finds the accounts in the acme.sh home, usually `~/.acme.sh`. They are kept
+/// in `ca/<directory host>/<directory path>/`, with the key in `account.key` (PEM),
+/// the account URL in `ca.conf`, and the account object in `account.json`. The email
+/// the accounts are registered with is in the `account.conf` of the home.
+pub fn acme_sh(home: &Path) -> Result<Vec<Imported>> {
+    let ca = home.join("ca");
+    let mut dirs = Vec::new();
+    find_dirs(&ca, "account.key", &mut dirs)?;
+    if dirs.is_empty() {
+        return Err(anyhow!("no acme.sh accounts in {}", ca.display()));
+    }
+    let account_email = fs::read_to_string(home.join("account.conf"))
+        .ok()
+        .and_then(|conf| shell_var(&conf, "ACCOUNT_EMAIL"));
+    let mut imported = Vec::new();
+    for dir in dirs {
+        let ca_conf = fs::read_to_string(dir.join("ca.conf")).unwrap_or_default();
+        let kid = shell_var(&ca_conf, "ACCOUNT_URL")
+            .ok_or_else(|| anyhow!("no ACCOUNT_URL in {}/ca.conf", dir.display()))?;
+        let server = dir
+            .strip_prefix(&ca)
+            .map_err(|_| anyhow!("unexpected acme.sh account path {}", dir.display()))?;
+        let server: Vec<String> = server
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect();
+        let contact = fs::read(dir.join("account.json"))
+            .ok()
+            .and_then(|json| serde_json::from_slice::<serde_json::Value>(&json).ok())
+            .and_then(|account| mailto(&account["contact"]));
+        let key = fs::read_to_string(dir.join("account.key"))?;
+        imported.push(Imported {
+            email: contact.or_else(|| account_email.clone()),
+            directory_url: format!("https://{}", server.join("/")),
+            kid,
+            pkcs8: pem_key(&key).with_context(|| format!("{}/account.key", dir.display()))?,
+        });
+    }
+    Ok(imported)
+}
+
+/// The first `mailto:` address of the `contact` array of an account object.
+fn mailto(contact: &serde_json::Value) -> Option<String> {
+    contact
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|c| c.as_str())
+        .find_map(|c| c.strip_prefix("mailto:"))
+        .map(str::to_string)
+}
+
+/// The value of the `name` variable in a shell config file, e.g. `ACCOUNT_URL='...'`.
+fn shell_var(conf: &str, name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+    conf.lines()
+        .filter_map(|l| l.trim().strip_prefix(prefix.as_str()))
+        .map(|v| v.trim().trim_matches(|c| c == '\'' || c == '"').to_string())
+        .find(|v| !v.is_empty())
+}
+
+/// Converts a PEM encoded private key to PKCS#8: OpenSSL writes SEC1 EC keys and PKCS#1
+/// RSA keys (possibly after an `EC PARAMETERS` block), PKCS#8 is taken as it is.
+fn pem_key(pem: &str) -> Result<Vec<u8>> {
+    if let Some(block) = pem_block(pem, "EC PRIVATE KEY") {
+        return Ok(pkcs8_from_sec1(&pem_body(block)?)?);
+    }
+    if let Some(block) = pem_block(pem, "RSA PRIVATE KEY") {
+        return Ok(pkcs8_from_pkcs1(&pem_body(block)?)?);
+    }
+    match pem_block(pem, "PRIVATE KEY") {
+        Some(block) => pem_body(block),
+        None => Err(anyhow!("no private key in the PEM")),
+    }
+}
+
+/// The PEM block with the `label`, if there is one.
+fn pem_block<'p>(pem: &'p str, label: &str) -> Option<&'p str> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let start = pem.find(&begin)?;
+    let stop = pem[start..].find(&end)? + start + end.len();
+    Some(&pem[start..stop])
+}
+
+/// Collects the directories under the `dir` that have the `file`.
+fn find_dirs(dir: &Path, file: &str, found: &mut Vec<std::path::PathBuf>) -> Result<()> {
+    if dir.join(file).is_file() {
+        found.push(dir.to_path_buf());
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir).with_context(|| format!("can't read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_dirs(&path, file, found)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+#![deny(clippy::mem_forget)]
+//! what a stored certificate was issued for: the account, and the request. It's kept
+//! next to the certificate, so `certifika renew <name>` needs nothing but the name.
+use crate::request::CertRequest;
+use crate::storage::{ObjectKind, Store};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Lineage {
+    /// the email address of the account the certificate was ordered with
+    pub email: String,
+    pub staging: bool,
+    pub request: CertRequest,
+    /// the order the current certificate was issued for
+    pub order_url: String,
+}
+
+impl Lineage {
+    pub fn load(store: &dyn Store, name: &str) -> Result<Self> {
+        let stored = store
+            .read(ObjectKind::Lineage, name)
+            .with_context(|| format!("no stored certificate {}", name))?;
+        Ok(serde_json::from_slice(&stored)?)
+    }
+
+    pub fn save(&self, store: &dyn Store) -> Result<()> {
+        let payload = serde_json::to_vec(self)?;
+        store.write(ObjectKind::Lineage, self.request.name()?, &payload)?;
+        Ok(())
+    }
+
+    /// Removes the certificate `name`: its chain, key, order and lineage, so it's not
+    /// renewed anymore.
+    pub fn delete(store: &dyn Store, name: &str) -> Result<()> {
+        store.delete(ObjectKind::Certificate, name)?;
+        store.delete(ObjectKind::CertKey, name)?;
+        store.delete(ObjectKind::Chain, name)?;
+        store.delete(ObjectKind::Order, name)?;
+        store.delete(ObjectKind::Lineage, name)?;
+        Ok(())
+    }
+}
@@ -1,10 +1,53 @@
 #![deny(clippy::mem_forget)]
+//! JSON logs on stdout (or stderr), or, when running under systemd, native journald entries.
+//!
+//! Journal entries carry the JSON as `MESSAGE`, and its top level fields as
+//! `CERTIFIKA_*` fields, so they can be matched on:
+//! ```text
+//! journalctl -u certifika CERTIFIKA_DOMAIN=example.com CERTIFIKA_OP=order
+//! ```
+//! The target is picked with `CERTIFIKA_LOG_TARGET` (`stdout`, `stderr` or `journald`),
+//! by default journald is used when stdout is connected to the journal.
+//! With `--output json` the logs on stdout move to stderr, out of the way of the JSON.
 use crate::APP_NAME;
 use log::{Level, LevelFilter, Metadata, Record};
+#[cfg(unix)]
+use once_cell::sync::OnceCell;
+use std::env;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
 
 static LOGGER: Logger = Logger;
+static STDERR: AtomicBool = AtomicBool::new(false);
+#[cfg(unix)]
+static JOURNAL: OnceCell<UnixDatagram> = OnceCell::new();
 struct Logger;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Stdout,
+    Stderr,
+    Journald,
+}
+
+impl Target {
+    pub fn parse(name: Option<&str>) -> anyhow::Result<Self> {
+        match name {
+            Some("stdout") => Ok(Target::Stdout),
+            Some("stderr") => Ok(Target::Stderr),
+            Some("journald") => Ok(Target::Journald),
+            Some(other) => Err(anyhow::anyhow!("unknown log target: {}", other)),
+            // systemd sets JOURNAL_STREAM for the services logging to the journal
+            None if env::var_os("JOURNAL_STREAM").is_some() => Ok(Target::Journald),
+            None => Ok(Target::Stdout),
+        }
+    }
+}
+
 impl log::Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
         metadata.level() <= Level::Debug
@@ -12,22 +55,78 @@ impl log::Log for Logger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) && record.target().starts_with(APP_NAME) {
-            println!(
-                r#"{{"level":"{}","message":{}}}"#,
-                record.level(),
-                record
-                    .args()
-                    .to_string()
-                    .replace("\n", "")
-                    .replace("\t", "")
-                    .replace(" ", "")
-            );
+            let message = record
+                .args()
+                .to_string()
+                .replace("\n", "")
+                .replace("\t", "")
+                .replace(" ", "");
+            #[cfg(unix)]
+            {
+                if let Some(journal) = JOURNAL.get() {
+                    let _ = journal.send_to(
+                        journal_entry(record.level(), &message).as_bytes(),
+                        JOURNALD_SOCKET,
+                    );
+                    return;
+                }
+            }
+            if STDERR.load(Ordering::Relaxed) {
+                eprintln!(r#"{{"level":"{}","message":{}}}"#, record.level(), message);
+            } else {
+                println!(r#"{{"level":"{}","message":{}}}"#, record.level(), message);
+            }
         }
     }
     fn flush(&self) {}
 }
 
-pub fn init(log_level: LevelFilter) {
+pub fn init(log_level: LevelFilter, target: Target) {
+    #[cfg(not(unix))]
+    let _ = target;
+    #[cfg(unix)]
+    {
+        if target == Target::Journald {
+            if let Ok(socket) = UnixDatagram::unbound() {
+                let _ = JOURNAL.set(socket);
+            }
+        }
+    }
+    STDERR.store(target == Target::Stderr, Ordering::Relaxed);
     log::set_logger(&LOGGER).unwrap();
     log::set_max_level(log_level);
 }
+
+/// Formats the entry in the journald [native protocol](https://systemd.io/JOURNAL_NATIVE_PROTOCOL/).
+/// Newlines are stripped from the messages, so the simple `KEY=value` form is enough.
+#[cfg(unix)]
+fn journal_entry(level: Level, message: &str) -> String {
+    let priority = match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    };
+    let mut entry = format!(
+        "PRIORITY={}\nSYSLOG_IDENTIFIER={}\nMESSAGE={}\n",
+        priority, APP_NAME, message
+    );
+    if let Ok(serde_json::Value::Object(fields)) = serde_json::from_str(message) {
+        for (key, value) in fields {
+            let value = match value {
+                serde_json::Value::String(s) => s,
+                serde_json::Value::Object(_) | serde_json::Value::Array(_) => continue,
+                other => other.to_string(),
+            };
+            let key: String = key
+                .chars()
+                .map(|c| match c.to_ascii_uppercase() {
+                    c @ 'A'..='Z' | c @ '0'..='9' => c,
+                    _ => '_',
+                })
+                .collect();
+            entry.push_str(&format!("CERTIFIKA_{}={}\n", key, value));
+        }
+    }
+    entry
+}
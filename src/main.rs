@@ -1,27 +1,1635 @@
 #![deny(clippy::mem_forget)]
 use anyhow::{anyhow, Context, Result};
-use std::env;
+use clap::ArgMatches;
+use serde::Serialize;
+use serde_json::json;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::time::SystemTime;
 mod acme;
+mod backoff;
+mod calendar;
+mod cert;
+mod cli;
+mod completions;
 mod config;
+mod csr;
+mod deploy;
+mod dns;
+mod export;
+mod http;
+mod import;
+mod lineage;
 mod log;
+mod metrics;
+mod psl;
+mod request;
 mod storage;
+mod systemd;
+mod tls;
+mod update;
 
 pub const APP_NAME: &str = "certifika";
-pub const APP_VERSION: &str = "0.1.0";
+pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn main() -> Result<()> {
-    let config = config::Config::parse()?;
-    crate::log::init(config.log_level);
+    let matches = cli::app().get_matches();
+    let config = config::Config::parse(leaf(&matches).value_of("config"))?;
+    let log_target = match config.log_target {
+        crate::log::Target::Stdout if json_output(leaf(&matches)) => crate::log::Target::Stderr,
+        target => target,
+    };
+    let log_level = log_level(leaf(&matches)).unwrap_or(config.log_level);
+    crate::log::init(log_level, log_target);
+
+    // the commands writing to the store hold its lock, the daemon takes it for each run
+    let _lock = match matches.subcommand_name() {
+        Some("account")
+        | Some("cert")
+        | Some("order")
+        | Some("renew")
+        | Some("revoke")
+        | Some("import")
+        | Some("restore")
+        | Some("import-all")
+        | Some("migrate-layout") => {
+            Some(storage::lock(config.store.as_ref(), config.lock_timeout)?)
+        }
+        _ => None,
+    };
+    match matches.subcommand() {
+        ("account", Some(m)) => account(&config, m),
+        ("accounts", Some(m)) => accounts(&config, m),
+        ("cert", Some(m)) => cert(&config, m),
+        ("certs", Some(m)) => certs(&config, m),
+        ("export", Some(m)) => export(&config, m),
+        ("backup", Some(m)) => backup(&config, m),
+        ("restore", Some(m)) => restore(&config, m),
+        ("export-all", Some(m)) => export_all(&config, m),
+        ("import-all", Some(m)) => import_all(&config, m),
+        ("migrate-layout", Some(m)) => migrate_layout(&config, m),
+        ("check", Some(m)) => check(&config, m),
+        ("verify", Some(m)) => verify(&config, m),
+        ("import", Some(m)) => import(&config, m),
+        ("doctor", Some(m)) => doctor(&config, m),
+        ("order", Some(m)) => order(&config, m),
+        ("renew", Some(m)) => renew(&config, m),
+        ("daemon", Some(m)) => daemon(&config, m),
+        ("systemd-install", Some(m)) => systemd_install(&config, m),
+        ("revoke", Some(m)) => revoke(&config, m),
+        ("directory", Some(m)) => directory(&config, m),
+        ("stats", Some(m)) => stats(&config, m),
+        ("self-update", Some(m)) => {
+            let updated = update::self_update(m.is_present("force"))?;
+            if json_output(m) {
+                return print_json(&json!({ "updated": updated }));
+            }
+            match updated {
+                Some(version) => println!("updated to {}", version),
+                None => println!("already up to date"),
+            }
+            Ok(())
+        }
+        ("debug", Some(m)) => debug(&config, m),
+        ("completions", Some(m)) => {
+            print!(
+                "{}",
+                completions::script(m.value_of("shell").unwrap_or_default())?
+            );
+            Ok(())
+        }
+        ("complete-names", Some(m)) => complete_names(&config, m),
+        _ => Err(anyhow!("Unknown command!")),
+    }
+}
+
+/// The matches of the innermost subcommand, the global args are found there.
+fn leaf<'a, 'b>(m: &'b ArgMatches<'a>) -> &'b ArgMatches<'a> {
+    match m.subcommand() {
+        (_, Some(m)) => leaf(m),
+        _ => m,
+    }
+}
+
+/// With `--output json` a command prints a single JSON document to stdout instead
+/// of the text, so it can be scripted. The logs go to stderr then.
+fn json_output(m: &ArgMatches) -> bool {
+    m.value_of("output") == Some("json")
+}
+
+/// `-q` leaves only the errors in the logs, `-v` adds the progress and `-vv` the details,
+/// all of them override `CERTIFIKA_LOG_LEVEL`.
+fn log_level(m: &ArgMatches) -> Option<::log::LevelFilter> {
+    if quiet(m) {
+        return Some(::log::LevelFilter::Error);
+    }
+    match m.occurrences_of("verbose") {
+        0 => None,
+        1 => Some(::log::LevelFilter::Info),
+        _ => Some(::log::LevelFilter::Debug),
+    }
+}
+
+/// With `-q` the commands print only their final result.
+fn quiet(m: &ArgMatches) -> bool {
+    m.is_present("quiet")
+}
+
+/// Prints the `document` for `--output json`.
+fn print_json<T: Serialize>(document: &T) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(document)?);
+    Ok(())
+}
+
+/// `--staging` and `--production` take precedence over `CERTIFIKA_STAGING`,
+/// `--dry-run` always means staging.
+fn staging(config: &config::Config, m: &ArgMatches) -> bool {
+    if m.is_present("staging") || dry_run(m) {
+        true
+    } else if m.is_present("production") {
+        false
+    } else {
+        config.staging
+    }
+}
+
+/// With `--dry-run` certificates are issued by the staging CA, but neither stored
+/// nor deployed, so the configuration can be validated without touching
+/// the production rate limits.
+fn dry_run(m: &ArgMatches) -> bool {
+    m.is_present("dry-run")
+}
+
+/// The email address of the account the command works with.
+fn email(m: &ArgMatches) -> String {
+    m.value_of("email").unwrap_or_default().to_string()
+}
+
+/// Loads the account named on the command line, see `account_for()`.
+/// The memory store starts out empty, so a throwaway account is registered there first.
+fn load_account<'s>(config: &'s config::Config, m: &ArgMatches) -> Result<acme::Account<'s>> {
+    if config.ephemeral {
+        let directory_url = directory_url(config, m)?;
+        let terms = agreed_terms(&directory_url, m.is_present("agree-tos"), quiet(m))?;
+        acme::Account::new(
+            email(m),
+            &config.contacts,
+            config.account_key_type,
+            &*config.store,
+            &directory_url,
+            config.eab.clone(),
+            staging(config, m),
+            terms.as_deref(),
+        )?;
+    }
+    account_for(config, m, email(m), staging(config, m))
+}
+
+/// Loads the account and applies the configured policies to it.
+fn account_for<'s>(
+    config: &'s config::Config,
+    m: &ArgMatches,
+    email: String,
+    staging: bool,
+) -> Result<acme::Account<'s>> {
+    let mut account = acme::Account::load(email, &*config.store, staging)?;
+    account.set_backoff(config.backoff);
+    account.set_throttle(config.rate_limit);
+    account.set_http01(config.http01.clone());
+    account.set_concurrency(config.concurrency);
+    account.set_order_timeout(config.order_timeout);
+    account.set_nonce_max_age(config.nonce_max_age);
+    account.set_strict(m.is_present("strict"));
+    Ok(account)
+}
+
+/// Orders the certificate for the request on the command line, and issues it.
+/// Either in one go, or out of band with `order export` and `order resume`:
+/// the former places the order and writes the bundle with the challenges to solve
+/// (to stdout without `--out`), the latter picks it up and gets the certificate.
+fn order(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    match m.subcommand() {
+        ("export", Some(m)) => {
+            let mut account = load_account(config, m)?;
+            let request = cert_request(config, m)?;
+            let bundle =
+                serde_json::to_string_pretty(&account.export_order(&request, &config.zones)?)?;
+            match m.value_of("out") {
+                Some(path) => fs::write(path, bundle)?,
+                None => println!("{}", bundle),
+            }
+            Ok(())
+        }
+        ("resume", Some(m)) => {
+            let mut account = load_account(config, m)?;
+            let path = m.value_of("bundle").unwrap_or_default();
+            let bundle: acme::Bundle = serde_json::from_str(&fs::read_to_string(path)?)?;
+            let order = account.resume_order(&bundle)?;
+            issue(config, m, &mut account, &bundle.request, order)
+        }
+        _ => {
+            if !config.renewal_windows.allows(SystemTime::now()) {
+                return Err(anyhow!("outside of the allowed renewal windows"));
+            }
+            let mut account = load_account(config, m)?;
+            let request = cert_request(config, m)?;
+            let order = account.order(&request, &config.zones)?;
+            issue(config, m, &mut account, &request, order)
+        }
+    }
+}
+
+/// `certifika renew <name>` orders the stored certificate again, with the account
+/// and the request it was issued for (see `lineage::Lineage`), but only when it's due
+/// for renewal and within the renewal windows. `--force` skips both checks.
+/// The certificate is due once `cert::renew_at` has passed, or with `--days`,
+/// when it expires within that many days.
+/// `certifika renew --all` goes through all the stored certificates, and sums
+/// them up: it fails if any of them failed to renew, for cron to notice.
+/// When there is nothing to do, outside of the renewal windows included, it says
+/// nothing and succeeds, so cron doesn't mail about it.
+fn renew(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let force = m.is_present("force");
+    if !force && !config.renewal_windows.allows(SystemTime::now()) {
+        if m.is_present("all") {
+            ::log::info!(
+                r#"{{"op":"renewal run skipped","reason":"outside of the renewal windows"}}"#
+            );
+            return Ok(());
+        }
+        return Err(anyhow!(
+            "outside of the allowed renewal windows, use --force to renew anyway"
+        ));
+    }
+    let days = match m.value_of("days") {
+        Some(days) => Some(days.parse::<i64>()?),
+        None => None,
+    };
+    if !m.is_present("all") {
+        let name = m.value_of("name").unwrap_or_default();
+        match renew_one(config, m, name, force, days)? {
+            Some(renewal) if json_output(m) => print_json(&renewal)?,
+            Some(renewal) => renewal.print(),
+            None if json_output(m) => print_json(&json!({ "name": name, "due": false }))?,
+            None => {
+                println!("the certificate is not due for renewal yet, use --force to renew anyway")
+            }
+        }
+        return Ok(());
+    }
+    let summary = renew_all(config, m, force, days)?;
+    if json_output(m) {
+        print_json(&summary)?;
+    } else if !summary.renewed.is_empty() || !summary.failed.is_empty() {
+        let renewed: Vec<&str> = summary.renewed.iter().map(|r| r.name.as_str()).collect();
+        let failed: Vec<&str> = summary.failed.iter().map(|f| f.name.as_str()).collect();
+        println!("renewed {}: {}", renewed.len(), renewed.join(", "));
+        println!(
+            "skipped {}: {}",
+            summary.skipped.len(),
+            summary.skipped.join(", ")
+        );
+        println!("failed {}: {}", failed.len(), failed.join(", "));
+    }
+    if !summary.failed.is_empty() {
+        return Err(anyhow!(
+            "{} certificates failed to renew",
+            summary.failed.len()
+        ));
+    }
+    Ok(())
+}
+
+/// How a run over all the stored certificates went, see `renew_all()`.
+#[derive(Serialize)]
+struct RenewalSummary {
+    renewed: Vec<Renewal>,
+    skipped: Vec<String>,
+    failed: Vec<RenewalFailure>,
+}
+
+#[derive(Serialize)]
+struct RenewalFailure {
+    name: String,
+    error: String,
+}
+
+/// Renews the stored certificates that are due, see `renew_one()`. A failure to renew
+/// one of them is logged, and the rest are renewed anyway.
+fn renew_all(
+    config: &config::Config,
+    m: &ArgMatches,
+    force: bool,
+    days: Option<i64>,
+) -> Result<RenewalSummary> {
+    let mut summary = RenewalSummary {
+        renewed: Vec::new(),
+        skipped: Vec::new(),
+        failed: Vec::new(),
+    };
+    for name in config.store.list(storage::ObjectKind::Lineage)? {
+        match renew_one(config, m, &name, force, days) {
+            Ok(Some(renewal)) => {
+                systemd::notify("WATCHDOG=1");
+                if !json_output(m) && !quiet(m) {
+                    renewal.print();
+                }
+                summary.renewed.push(renewal);
+            }
+            Ok(None) => {
+                systemd::notify("WATCHDOG=1");
+                summary.skipped.push(name);
+            }
+            Err(e) => {
+                ::log::error!(
+                    r#"{{"op":"renewal failed","name":"{}","error":"{}"}}"#,
+                    name,
+                    e
+                );
+                summary.failed.push(RenewalFailure {
+                    name,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// `certifika daemon` stays resident and renews the stored certificates on its own,
+/// a built-in alternative to cron: every `--interval` seconds (an hour by default)
+/// it goes through them as `renew --all` does, within the renewal windows.
+/// The results are logged, failures don't stop it.
+/// Under systemd it reports readiness and pings the watchdog (see the `systemd` module),
+/// and serves the outcome of the last run as JSON on the socket systemd passes it.
+fn daemon(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let interval =
+        std::time::Duration::from_secs(m.value_of("interval").unwrap_or_default().parse()?);
+    let status = std::sync::Arc::new(std::sync::Mutex::new(json!({ "runs": 0 })));
+    if let Some(listener) = systemd::listener() {
+        let status = status.clone();
+        std::thread::spawn(move || serve_status(listener, &status));
+    }
+    ::log::info!(
+        r#"{{"op":"daemon started","interval":{}}}"#,
+        interval.as_secs()
+    );
+    systemd::notify("READY=1");
+    let mut runs = 0;
+    loop {
+        let outcome = if config.renewal_windows.allows(SystemTime::now()) {
+            let summary = storage::lock(config.store.as_ref(), config.lock_timeout)
+                .map_err(anyhow::Error::from)
+                .and_then(|_lock| renew_all(config, m, false, None));
+            match summary {
+                Ok(summary) => {
+                    ::log::info!(
+                        r#"{{"op":"renewal run","renewed":{},"skipped":{},"failed":{}}}"#,
+                        summary.renewed.len(),
+                        summary.skipped.len(),
+                        summary.failed.len()
+                    );
+                    serde_json::to_value(&summary)?
+                }
+                Err(e) => {
+                    ::log::error!(r#"{{"op":"renewal run failed","error":"{}"}}"#, e);
+                    json!({ "error": e.to_string() })
+                }
+            }
+        } else {
+            ::log::info!(
+                r#"{{"op":"renewal run skipped","reason":"outside of the renewal windows"}}"#
+            );
+            json!({ "skipped": "outside of the renewal windows" })
+        };
+        runs += 1;
+        if let Ok(mut status) = status.lock() {
+            *status = json!({
+                "runs": runs,
+                "at": timestamp(chrono::Utc::now().timestamp()),
+                "last": outcome,
+            });
+        }
+        idle(interval);
+    }
+}
+
+/// Sleeps for the `interval`, pinging the systemd watchdog in the meantime.
+fn idle(interval: std::time::Duration) {
+    let until = std::time::Instant::now() + interval;
+    let step = systemd::watchdog_interval().map(|watchdog| watchdog / 2);
+    loop {
+        let left = until.saturating_duration_since(std::time::Instant::now());
+        if left == std::time::Duration::from_secs(0) {
+            return;
+        }
+        std::thread::sleep(step.map_or(left, |step| step.min(left)));
+        systemd::notify("WATCHDOG=1");
+    }
+}
+
+/// Answers every connection on the `listener` with the `status` of the daemon,
+/// as a minimal HTTP response, whatever the request.
+fn serve_status(listener: std::net::TcpListener, status: &std::sync::Mutex<serde_json::Value>) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let _ = stream.set_read_timeout(Some(std::time::Duration::from_secs(5)));
+        let _ = stream.read(&mut [0; 1024]);
+        let body = match status.lock() {
+            Ok(status) => status.to_string(),
+            Err(_) => continue,
+        };
+        let _ = write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+    }
+}
+
+/// `certifika systemd-install` writes the systemd units running certifika with
+/// the current settings, see `systemd::units()`: the `renew --all` service and its timer,
+/// or with `--daemon`, the `certifika daemon` service (and with `--listen`, the socket
+/// of its status endpoint). `--print` prints them instead.
+fn systemd_install(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    // the watchdog is pinged between the certificates and in the waits of a renewal,
+    // but not while a run waits for the store lock, up to the lock timeout
+    let watchdog = config.order_timeout.max(config.lock_timeout) * 2;
+    let units = systemd::units(
+        m.is_present("daemon"),
+        m.value_of("listen"),
+        m.value_of("config"),
+        watchdog,
+    )?;
+    let dir = std::path::Path::new(m.value_of("dir").unwrap_or_default());
+    for (name, unit) in units {
+        if m.is_present("print") {
+            println!("# {}\n{}", name, unit);
+            continue;
+        }
+        let path = dir.join(&name);
+        fs::write(&path, unit).with_context(|| format!("can't write {}", path.display()))?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// A renewed certificate, along with the serial and the expiry date of the one
+/// it replaces.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Renewal {
+    name: String,
+    serial: String,
+    expires: String,
+    previous_serial: Option<String>,
+    previous_expires: Option<String>,
+    key_changed: bool,
+    dry_run: bool,
+}
 
-    let command = env::args().nth(1).context("command not provided")?;
-    let email = env::args().nth(2).context("account email not provided")?;
-    let mut account = match command.as_str() {
-        "load" => acme::Account::load(email, &*config.store)?,
-        "reg" => acme::Account::new(email, &*config.store)?,
-        _ => return Err(anyhow!("Unknown command!")),
+impl Renewal {
+    fn print(&self) {
+        if self.dry_run {
+            println!(
+                "DRY RUN: {} renewed by the staging CA, expires {}, not stored nor deployed",
+                self.name, self.expires
+            );
+            return;
+        }
+        println!("{} renewed", self.name);
+        if let (Some(serial), Some(expires)) = (&self.previous_serial, &self.previous_expires) {
+            println!("serial: {} -> {}", serial, self.serial);
+            println!("expires: {} -> {}", expires, self.expires);
+        }
+        println!(
+            "key: {}",
+            if self.key_changed { "new" } else { "unchanged" }
+        );
+    }
+}
+
+/// Renews the certificate `name` if it's due (or if `force`d), see `renew()`, and
+/// tells how it went, `None` if it's not due. It's also due once the window
+/// the CA suggests with ARI has started. The challenges are solved only if the CA no longer has
+/// valid authorizations. The stored certificate and key are replaced once the new
+/// ones are issued, a failed renewal leaves them as they were.
+fn renew_one(
+    config: &config::Config,
+    m: &ArgMatches,
+    name: &str,
+    force: bool,
+    days: Option<i64>,
+) -> Result<Option<Renewal>> {
+    let lineage = lineage::Lineage::load(&*config.store, name)?;
+    let staging = lineage.staging || dry_run(m);
+    let mut account = account_for(config, m, lineage.email.clone(), staging)?;
+    let previous = config
+        .store
+        .read(storage::ObjectKind::Certificate, name)
+        .ok()
+        .and_then(|chain| String::from_utf8(chain).ok());
+    let due = renewal_due(&*config.store, name, days)?
+        || previous
+            .as_deref()
+            .map_or(false, |chain| ari_due(&account, chain));
+    if !due && !force {
+        return Ok(None);
+    }
+    if !due {
+        // the same set of domains counts against the duplicate certificate limit of the CA
+        ::log::warn!(
+            r#"{{"op":"forced renewal","name":"{}","warning":"duplicate certificate rate limit"}}"#,
+            name
+        );
+    }
+    let previous_key = config.store.read(storage::ObjectKind::CertKey, name).ok();
+    let order = account.order(&lineage.request, &config.zones)?;
+    let issued = finalize(m, &mut account, &lineage.request, order, false)?;
+    let renewal = Renewal {
+        name: name.to_string(),
+        serial: cert::serial(&issued.chain_pem)?,
+        expires: timestamp(issued.expires),
+        previous_serial: match &previous {
+            Some(previous) => Some(cert::serial(previous)?),
+            None => None,
+        },
+        previous_expires: match &previous {
+            Some(previous) => Some(timestamp(cert::not_after(previous)?)),
+            None => None,
+        },
+        key_changed: previous_key.as_deref() != issued.key_pem.as_ref().map(String::as_bytes),
+        dry_run: dry_run(m),
+    };
+    if renewal.dry_run {
+        return Ok(Some(renewal));
+    }
+    account.save_certificate(name, &issued)?;
+    let lineage = lineage::Lineage {
+        order_url: issued.order_url.to_owned(),
+        ..lineage
     };
-    let domains: Vec<String> = ["deviantguru".to_string()].to_vec();
-    account.order(domains)?;
-    account.info();
+    lineage.save(&*config.store)?;
+    deliver(config, &lineage.request, &issued)?;
+    Ok(Some(renewal))
+}
+
+/// Formats seconds since the UNIX epoch as RFC3339.
+fn timestamp(secs: i64) -> String {
+    chrono::DateTime::<chrono::Utc>::from_utc(
+        chrono::NaiveDateTime::from_timestamp(secs, 0),
+        chrono::Utc,
+    )
+    .to_rfc3339()
+}
+
+/// Revokes the stored certificate named on the command line, `--reason` takes
+/// the RFC5280 reason names, e.g. `keyCompromise` or `superseded`.
+fn revoke(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let name = m.value_of("name").unwrap_or_default();
+    let chain = config
+        .store
+        .read(storage::ObjectKind::Certificate, name)
+        .with_context(|| format!("no stored certificate {}", name))?;
+    if dry_run(m) {
+        if json_output(m) {
+            return print_json(&json!({ "name": name, "revoked": false, "dryRun": true }));
+        }
+        println!("DRY RUN: certificate {} would be revoked", name);
+        return Ok(());
+    }
+    let mut account = load_account(config, m)?;
+    account.revoke(&String::from_utf8(chain)?, revocation_reason(m)?)?;
+    if json_output(m) {
+        return print_json(&json!({ "name": name, "revoked": true, "dryRun": false }));
+    }
+    println!("certificate {} revoked", name);
     Ok(())
 }
+
+/// The `--reason` to revoke with, if given.
+fn revocation_reason(m: &ArgMatches) -> Result<Option<acme::Reason>> {
+    match m.value_of("reason") {
+        Some(reason) => Ok(Some(reason.parse()?)),
+        None => Ok(None),
+    }
+}
+
+/// Certificate maintenance.
+fn cert(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    match m.subcommand() {
+        ("show", Some(m)) => show_cert(config, m),
+        ("delete", Some(m)) => delete_cert(config, m),
+        _ => Err(anyhow!("Unknown cert subcommand!")),
+    }
+}
+
+/// Shows the leaf of the stored certificate named on the command line: subject, SANs,
+/// issuer, validity, key type, serial and fingerprints.
+fn show_cert(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let name = m.value_of("name").unwrap_or_default();
+    let chain = config
+        .store
+        .read(storage::ObjectKind::Certificate, name)
+        .with_context(|| format!("no stored certificate {}", name))?;
+    let details = cert::details(&String::from_utf8(chain)?)
+        .with_context(|| format!("certificate {}", name))?;
+    if m.is_present("json") || json_output(m) {
+        return print_json(&json!({
+            "name": name,
+            "subject": details.subject,
+            "domains": details.domains,
+            "issuer": details.issuer,
+            "notBefore": timestamp(details.not_before),
+            "notAfter": timestamp(details.not_after),
+            "keyType": details.key_type,
+            "serial": details.serial,
+            "sha256": details.sha256,
+            "sha1": details.sha1,
+        }));
+    }
+    println!("subject: {}", details.subject);
+    println!("domains: {}", details.domains.join(", "));
+    println!("issuer: {}", details.issuer);
+    println!("not before: {}", timestamp(details.not_before));
+    println!("not after: {}", timestamp(details.not_after));
+    println!("key type: {}", details.key_type);
+    println!("serial: {}", details.serial);
+    println!("SHA-256 fingerprint: {}", details.sha256);
+    println!("SHA-1 fingerprint: {}", details.sha1);
+    Ok(())
+}
+
+/// Removes the stored certificate named on the command line, its key and lineage,
+/// so the renewals skip it from now on. With `--revoke` it's revoked first, by the
+/// account it was ordered with.
+fn delete_cert(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let name = m.value_of("name").unwrap_or_default();
+    let chain = config
+        .store
+        .read(storage::ObjectKind::Certificate, name)
+        .with_context(|| format!("no stored certificate {}", name))?;
+    let revoke = m.is_present("revoke");
+    if dry_run(m) {
+        if json_output(m) {
+            return print_json(&json!({ "name": name, "revoked": false, "dryRun": true }));
+        }
+        println!(
+            "DRY RUN: certificate {} would be {}",
+            name,
+            if revoke {
+                "revoked and removed"
+            } else {
+                "removed"
+            }
+        );
+        return Ok(());
+    }
+    if revoke {
+        let lineage = lineage::Lineage::load(&*config.store, name)?;
+        let mut account = account_for(config, m, lineage.email, lineage.staging)?;
+        account.revoke(&String::from_utf8(chain)?, revocation_reason(m)?)?;
+    }
+    lineage::Lineage::delete(&*config.store, name)?;
+    if json_output(m) {
+        return print_json(&json!({ "name": name, "revoked": revoke, "dryRun": false }));
+    }
+    if revoke {
+        println!("certificate {} revoked", name);
+    }
+    println!("certificate {} removed", name);
+    Ok(())
+}
+
+/// Finalizes the `order` and stores the certificate along with its lineage,
+/// then deploys it and runs the hooks.
+fn issue(
+    config: &config::Config,
+    m: &ArgMatches,
+    account: &mut acme::Account<'_>,
+    request: &request::CertRequest,
+    order: acme::Order,
+) -> Result<()> {
+    if dry_run(m) {
+        let issued = finalize(m, account, request, order, false)?;
+        if json_output(m) {
+            return print_json(&issued_json(request, &issued, true)?);
+        }
+        println!(
+            "DRY RUN: {} issued by the staging CA, expires {}, not stored nor deployed",
+            request.name()?,
+            timestamp(issued.expires)
+        );
+        return Ok(());
+    }
+    let issued = finalize(m, account, request, order, true)?;
+    let lineage = lineage::Lineage {
+        email: email(m),
+        staging: staging(config, m),
+        request: request.clone(),
+        order_url: issued.order_url.to_owned(),
+    };
+    lineage.save(&*config.store)?;
+    deliver(config, request, &issued)?;
+    if json_output(m) {
+        let mut issued_json = issued_json(request, &issued, false)?;
+        if config.ephemeral {
+            issued_json["certificate"] = json!(issued.chain_pem);
+            issued_json["key"] = json!(issued.key_pem);
+        }
+        print_json(&issued_json)?;
+    } else if config.ephemeral {
+        // nothing is kept, so this is the only copy
+        print!(
+            "{}{}",
+            issued.key_pem.as_deref().unwrap_or_default(),
+            issued.chain_pem
+        );
+    }
+    Ok(())
+}
+
+/// The issued certificate, as `--output json` shows it.
+fn issued_json(
+    request: &request::CertRequest,
+    issued: &acme::IssuedCertificate,
+    dry_run: bool,
+) -> Result<serde_json::Value> {
+    Ok(json!({
+        "name": request.name()?,
+        "domains": request.identifiers,
+        "serial": cert::serial(&issued.chain_pem)?,
+        "expires": timestamp(issued.expires),
+        "orderUrl": issued.order_url,
+        "dryRun": dry_run,
+    }))
+}
+
+/// Finalizes the `order` with the key the command line asks for: `--csr`, `--cert-key`,
+/// the previous one for the requests with `reuse_key`, or a fresh one.
+fn finalize(
+    m: &ArgMatches,
+    account: &mut acme::Account<'_>,
+    request: &request::CertRequest,
+    order: acme::Order,
+    persist: bool,
+) -> Result<acme::IssuedCertificate> {
+    let issued = match (m.value_of("csr"), m.value_of("cert-key")) {
+        (Some(path), _) => order.finalize_with_csr(account, &csr::read(path)?, persist)?,
+        (None, Some(path)) => {
+            let key_pem = fs::read_to_string(path)
+                .with_context(|| format!("can't read the certificate key {}", path))?;
+            order.finalize_with_own_key(account, &key_pem, persist)?
+        }
+        (None, None) if request.reuse_key => order.finalize_reusing_key(account, persist)?,
+        (None, None) => order.finalize_with_key(account, persist)?,
+    };
+    Ok(issued)
+}
+
+/// Deploys the `issued` certificate and runs the hooks.
+fn deliver(
+    config: &config::Config,
+    request: &request::CertRequest,
+    issued: &acme::IssuedCertificate,
+) -> Result<()> {
+    let name = request.name()?;
+    ::log::info!(
+        r#"{{"op":"certificate issued","domain":"{}","order":"{}","expires":{}}}"#,
+        name,
+        issued.order_url,
+        issued.expires
+    );
+    let requested;
+    let targets = if request.deploy_targets.is_empty() {
+        &config.deploy
+    } else {
+        requested = deploy::from_names(&request.deploy_targets)?;
+        &requested
+    };
+    for target in targets {
+        target.deploy(name, issued)?;
+    }
+    request.run_deploy_hooks(issued)?;
+    http::log_stats();
+    for (counter, value) in metrics::registry().snapshot() {
+        ::log::debug!(
+            r#"{{"op":"metrics","counter":"{}","value":{}}}"#,
+            counter.name(),
+            value
+        );
+    }
+    Ok(())
+}
+
+/// Resolves the directory URL to use: `--directory-url` and `--ca` take precedence
+/// over `CERTIFIKA_DIRECTORY_URL` and `CERTIFIKA_CA`.
+fn directory_url(config: &config::Config, m: &ArgMatches) -> Result<String> {
+    let staging = staging(config, m);
+    Ok(match (m.value_of("directory-url"), m.value_of("ca")) {
+        (Some(url), _) => url.to_string(),
+        (None, Some(name)) => name
+            .parse::<acme::Ca>()?
+            .directory_url(staging)?
+            .to_string(),
+        (None, None) => match &config.directory_url {
+            Some(url) if !dry_run(m) => url.to_owned(),
+            _ => config.ca.directory_url(staging)?.to_string(),
+        },
+    })
+}
+
+/// Shows the terms of service of the CA, and returns their URL once they are agreed
+/// to, either with `--agree-tos`, or interactively. `--agree-tos` with `-q` agrees
+/// without showing them.
+fn agreed_terms(directory_url: &str, agree: bool, quiet: bool) -> Result<Option<String>> {
+    let terms = match acme::directory_meta(directory_url)?.terms_of_service {
+        Some(terms) => terms,
+        None => return Ok(None),
+    };
+    if !(agree && quiet) {
+        eprintln!("Terms of service: {}", terms);
+    }
+    if agree {
+        return Ok(Some(terms));
+    }
+    eprint!("Do you agree to the terms of service? [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(Some(terms)),
+        _ => Ok(None),
+    }
+}
+
+/// Returns the values of all occurrences of the repeatable `arg`.
+fn values(m: &ArgMatches, arg: &str) -> Vec<String> {
+    m.values_of(arg)
+        .map(|values| values.map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Builds the certificate request from the command line, see `cli::request_args()`.
+/// The domains of `--domain` come first, then the ones of `--domains`, duplicates
+/// are dropped. The challenge preferences default to `CERTIFIKA_CHALLENGES`.
+fn cert_request(config: &config::Config, m: &ArgMatches) -> Result<request::CertRequest> {
+    let mut identifiers: Vec<String> = Vec::new();
+    for domain in values(m, "domain").into_iter().chain(values(m, "domains")) {
+        let domain = domain.trim().to_string();
+        if !domain.is_empty() && !identifiers.contains(&domain) {
+            identifiers.push(domain);
+        }
+    }
+    let request = request::CertRequest {
+        identifiers,
+        key_type: match m.value_of("key-type") {
+            Some(key_type) => key_type.parse()?,
+            None => request::KeyType::default(),
+        },
+        challenge_prefs: match values(m, "challenge") {
+            types if types.is_empty() => config.challenge_prefs.clone(),
+            types => types
+                .iter()
+                .map(|c| c.parse())
+                .collect::<Result<Vec<request::ChallengeType>>>()?,
+        },
+        domain_challenge_prefs: config.domain_challenge_prefs.clone(),
+        profile_name: m.value_of("profile").map(str::to_string),
+        deploy_targets: values(m, "deploy"),
+        pre_validation_hooks: match values(m, "pre-validation-hook") {
+            hooks if hooks.is_empty() => config.pre_validation_hooks.clone(),
+            hooks => hooks,
+        },
+        post_validation_hooks: match values(m, "post-validation-hook") {
+            hooks if hooks.is_empty() => config.post_validation_hooks.clone(),
+            hooks => hooks,
+        },
+        hooks: match values(m, "hook") {
+            hooks if hooks.is_empty() => config.hooks.clone(),
+            hooks => hooks,
+        },
+        reuse_key: m.is_present("reuse-key"),
+        must_staple: m.is_present("must-staple"),
+        not_before: m.value_of("not-before").map(str::to_string),
+        not_after: m.value_of("not-after").map(str::to_string),
+        short_lived: m.is_present("short-lived"),
+    };
+    if request.identifiers.is_empty() {
+        return Err(anyhow!("no domains to order, use --domain"));
+    }
+    Ok(request)
+}
+
+/// Whether the stored certificate for the `name` is due for renewal: when it expires
+/// within the `days`, or else once `cert::renew_at` has passed. A missing one always is.
+fn renewal_due(store: &dyn storage::Store, name: &str, days: Option<i64>) -> Result<bool> {
+    let chain = match store.read(storage::ObjectKind::Certificate, name) {
+        Ok(chain) => String::from_utf8(chain)?,
+        Err(_) => return Ok(true),
+    };
+    let now = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs() as i64;
+    match days {
+        Some(days) => Ok(cert::not_after(&chain)? - days * 86400 <= now),
+        None => Ok(cert::renew_at(&chain)? <= now),
+    }
+}
+
+/// Whether the suggested renewal window of the `chain` has started, according to
+/// the CA's [ARI](https://datatracker.ietf.org/doc/draft-ietf-acme-ari/). CAs without
+/// it, and failures to tell, leave it to the local rules.
+fn ari_due(account: &acme::Account<'_>, chain: &str) -> bool {
+    match account.renewal_info(chain) {
+        Ok(Some(window)) => {
+            ::log::debug!(
+                r#"{{"op":"renewal info","start":"{}","end":"{}"}}"#,
+                window.start,
+                window.end
+            );
+            chrono::DateTime::parse_from_rfc3339(&window.start)
+                .map(|start| start.with_timezone(&chrono::Utc) <= chrono::Utc::now())
+                .unwrap_or(false)
+        }
+        Ok(None) => false,
+        Err(e) => {
+            ::log::warn!(r#"{{"op":"renewal info","error":"{}"}}"#, e);
+            false
+        }
+    }
+}
+
+/// Account maintenance: registration, recovery of a lost account record, and agreeing
+/// to the updated terms of service.
+fn account(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    match m.subcommand() {
+        ("register", Some(m)) => {
+            let directory_url = directory_url(config, m)?;
+            let eab = match (m.value_of("eab-kid"), m.value_of("eab-hmac-key")) {
+                (Some(kid), Some(hmac_key)) => Some(acme::Eab {
+                    kid: kid.to_string(),
+                    hmac_key: hmac_key.to_string(),
+                }),
+                _ => config.eab.clone(),
+            };
+            let terms = agreed_terms(&directory_url, m.is_present("agree-tos"), quiet(m))?;
+            let contacts = match m.value_of("contacts") {
+                Some(contacts) => config::split_list(contacts),
+                None => config.contacts.clone(),
+            };
+            let key_type = match m.value_of("account-key-type") {
+                Some(key_type) => key_type.parse()?,
+                None => config.account_key_type,
+            };
+            acme::Account::new(
+                email(m),
+                &contacts,
+                key_type,
+                &*config.store,
+                &directory_url,
+                eab,
+                staging(config, m),
+                terms.as_deref(),
+            )?;
+            if json_output(m) {
+                return print_json(&json!({
+                    "email": email(m),
+                    "staging": staging(config, m),
+                    "directoryUrl": directory_url,
+                    "status": "registered",
+                }));
+            }
+            println!("account registered");
+            Ok(())
+        }
+        ("load", Some(m)) => {
+            let info = load_account(config, m)?.info()?;
+            if m.is_present("json") || json_output(m) {
+                return print_json(&info);
+            }
+            println!("account: {}", info.kid);
+            println!("directory: {}", info.directory_url);
+            println!("status: {}", info.status);
+            println!("contacts: {}", info.contact.join(", "));
+            println!("orders: {}", info.orders.as_deref().unwrap_or("unknown"));
+            println!(
+                "created: {}",
+                info.created_at.as_deref().unwrap_or("unknown")
+            );
+            Ok(())
+        }
+        ("find", Some(m)) => {
+            let directory_url = directory_url(config, m)?;
+            acme::Account::find(email(m), &*config.store, &directory_url, staging(config, m))?;
+            if json_output(m) {
+                return print_json(&json!({
+                    "email": email(m),
+                    "staging": staging(config, m),
+                    "directoryUrl": directory_url,
+                    "status": "recovered",
+                }));
+            }
+            println!("account recovered");
+            Ok(())
+        }
+        ("agree-tos", Some(m)) => {
+            let mut account = load_account(config, m)?;
+            let terms = account.current_terms();
+            match &terms {
+                Some(terms) if !json_output(m) && !quiet(m) => {
+                    println!("Terms of service: {}", terms)
+                }
+                _ => (),
+            }
+            account.agree_tos()?;
+            if json_output(m) {
+                return print_json(&json!({ "email": email(m), "termsOfService": terms }));
+            }
+            println!("terms of service agreed");
+            Ok(())
+        }
+        ("delete", Some(m)) => delete_account(config, m),
+        _ => Err(anyhow!("Unknown account subcommand!")),
+    }
+}
+
+/// Removes the account from the store, deactivating it at the CA first with `--deactivate`.
+/// `--purge-certs` removes the certificates ordered with it as well, they couldn't
+/// be renewed without it anyway.
+fn delete_account(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let staging = staging(config, m);
+    let name = acme::store_name(&email(m), staging);
+    config
+        .store
+        .read(storage::ObjectKind::KeyPair, &name)
+        .with_context(|| format!("no stored account {}", name))?;
+    let mut certs = Vec::new();
+    if m.is_present("purge-certs") {
+        for cert in config.store.list(storage::ObjectKind::Lineage)? {
+            let lineage = lineage::Lineage::load(&*config.store, &cert)?;
+            if lineage.email == email(m) && lineage.staging == staging {
+                certs.push(cert);
+            }
+        }
+    }
+    let deactivate = m.is_present("deactivate");
+    if dry_run(m) {
+        if json_output(m) {
+            return print_json(&json!({
+                "email": email(m),
+                "staging": staging,
+                "deactivated": false,
+                "certs": certs,
+                "dryRun": true,
+            }));
+        }
+        println!(
+            "DRY RUN: account {} would be {}",
+            name,
+            if deactivate {
+                "deactivated and removed"
+            } else {
+                "removed"
+            }
+        );
+        for cert in &certs {
+            println!("DRY RUN: certificate {} would be removed", cert);
+        }
+        return Ok(());
+    }
+    if deactivate {
+        load_account(config, m)?.deactivate()?;
+    }
+    for cert in &certs {
+        lineage::Lineage::delete(&*config.store, cert)?;
+    }
+    acme::delete_account(&*config.store, &name)?;
+    if json_output(m) {
+        return print_json(&json!({
+            "email": email(m),
+            "staging": staging,
+            "deactivated": deactivate,
+            "certs": certs,
+            "dryRun": false,
+        }));
+    }
+    if deactivate {
+        println!("account {} deactivated", name);
+    }
+    for cert in &certs {
+        println!("certificate {} removed", cert);
+    }
+    println!("account {} removed", name);
+    Ok(())
+}
+
+/// Lists the stored accounts: email, environment, directory, kid, and when it was registered.
+fn accounts(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let accounts = acme::accounts(&*config.store)?;
+    if json_output(m) {
+        return print_json(&accounts);
+    }
+    for account in accounts {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            account.email,
+            if account.staging {
+                "staging"
+            } else {
+                "production"
+            },
+            account.directory_url,
+            account.kid,
+            account.created.as_deref().unwrap_or("unknown")
+        );
+    }
+    Ok(())
+}
+
+/// Prints the names of the stored accounts (emails) or certificates, one per line,
+/// for the completion scripts.
+fn complete_names(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let mut names: Vec<String> = match m.value_of("kind") {
+        Some("accounts") => config
+            .store
+            .list(storage::ObjectKind::Account)?
+            .into_iter()
+            .map(|name| name.trim_end_matches(".staging").to_string())
+            .collect(),
+        _ => config.store.list(storage::ObjectKind::Certificate)?,
+    };
+    names.sort();
+    names.dedup();
+    for name in names {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+/// Lists the stored certificates: name, domains, issuer, serial, notAfter and the days
+/// left. `--domain` keeps the ones for the domain (or its subdomains), `--sort expiry`
+/// puts the ones expiring first on top.
+fn certs(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs() as i64;
+    let mut certs = Vec::new();
+    for name in config.store.list(storage::ObjectKind::Certificate)? {
+        let chain = String::from_utf8(config.store.read(storage::ObjectKind::Certificate, &name)?)?;
+        let info = cert::info(&chain).with_context(|| format!("certificate {}", name))?;
+        if let Some(domain) = m.value_of("domain") {
+            let domain = domain.to_lowercase();
+            let suffix = format!(".{}", domain);
+            let matches = info.domains.iter().any(|d| {
+                let d = d.to_lowercase();
+                d == domain || d.ends_with(&suffix)
+            });
+            if !matches {
+                continue;
+            }
+        }
+        certs.push((name, info));
+    }
+    if m.value_of("sort") == Some("expiry") {
+        certs.sort_by_key(|(_, info)| info.not_after);
+    }
+    if json_output(m) {
+        let certs: Vec<serde_json::Value> = certs
+            .iter()
+            .map(|(name, info)| {
+                json!({
+                    "name": name,
+                    "domains": info.domains,
+                    "issuer": info.issuer,
+                    "serial": info.serial,
+                    "notAfter": timestamp(info.not_after),
+                    "daysLeft": (info.not_after - now).div_euclid(86400),
+                })
+            })
+            .collect();
+        return print_json(&certs);
+    }
+    for (name, info) in certs {
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            name,
+            info.domains.join(","),
+            info.issuer,
+            info.serial,
+            timestamp(info.not_after),
+            (info.not_after - now).div_euclid(86400)
+        );
+    }
+    Ok(())
+}
+
+/// `certifika export <name> --format pem|pkcs12 --out <dir>` writes the stored
+/// certificate and its key into files, see the `export` module. The PKCS#12 password
+/// comes from `CERTIFIKA_EXPORT_PASSWORD`, it's empty otherwise.
+fn export(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let name = m.value_of("name").unwrap_or_default();
+    let read = |kind| -> Result<String> {
+        let payload = config
+            .store
+            .read(kind, name)
+            .with_context(|| format!("no stored certificate {}", name))?;
+        Ok(String::from_utf8(payload)?)
+    };
+    let issued = acme::IssuedCertificate {
+        key_pem: read(storage::ObjectKind::CertKey).ok(),
+        chain_pem: read(storage::ObjectKind::Certificate)?,
+        order_url: String::new(),
+        order: None,
+        expires: 0,
+    };
+    let format = m.value_of("format").unwrap_or_default().parse()?;
+    let password = std::env::var("CERTIFIKA_EXPORT_PASSWORD").unwrap_or_default();
+    let dir = std::path::Path::new(m.value_of("out").unwrap_or_default());
+    let paths = export::export(&issued, name, dir, format, &password)?;
+    if json_output(m) {
+        let files: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+        return print_json(&json!({ "name": name, "files": files }));
+    }
+    for path in paths {
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// The passphrase of the backup archives.
+fn backup_passphrase() -> Result<Vec<u8>> {
+    match std::env::var("CERTIFIKA_BACKUP_PASSPHRASE") {
+        Ok(passphrase) if !passphrase.is_empty() => Ok(passphrase.into_bytes()),
+        _ => Err(anyhow!("CERTIFIKA_BACKUP_PASSPHRASE is not set")),
+    }
+}
+
+/// `certifika backup <file>` writes every object of the store into the encrypted archive,
+/// which `certifika restore <file>` writes into the store, of this or any other type.
+fn backup(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let file = m.value_of("file").unwrap_or_default();
+    let (archive, objects) = storage::snapshot(config.store.as_ref(), &backup_passphrase()?)?;
+    export::write(std::path::Path::new(file), &archive)
+        .with_context(|| format!("can't write the backup {}", file))?;
+    if json_output(m) {
+        return print_json(&json!({ "file": file, "objects": objects }));
+    }
+    println!("{} objects backed up into {}", objects, file);
+    Ok(())
+}
+
+fn restore(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let file = m.value_of("file").unwrap_or_default();
+    let archive = std::fs::read(file).with_context(|| format!("can't read the backup {}", file))?;
+    let objects = storage::restore(config.store.as_ref(), &backup_passphrase()?, archive)?;
+    if json_output(m) {
+        return print_json(&json!({ "file": file, "objects": objects }));
+    }
+    println!("{} objects restored from {}", objects, file);
+    Ok(())
+}
+
+/// `certifika export-all <file>` packs the accounts and the certificates into a tarball,
+/// encrypted with `CERTIFIKA_EXPORT_PASSWORD` with `--encrypt`; `certifika import-all <file>`
+/// writes them into the store on the other machine. See `storage::export_all()`.
+fn export_all(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let file = m.value_of("file").unwrap_or_default();
+    let password = match std::env::var("CERTIFIKA_EXPORT_PASSWORD") {
+        _ if !m.is_present("encrypt") => None,
+        Ok(password) if !password.is_empty() => Some(password.into_bytes()),
+        _ => {
+            return Err(anyhow!(
+                "--encrypt takes CERTIFIKA_EXPORT_PASSWORD, it's not set"
+            ))
+        }
+    };
+    let (tarball, files) = storage::export_all(config.store.as_ref(), password.as_deref())?;
+    export::write(std::path::Path::new(file), &tarball)
+        .with_context(|| format!("can't write the tarball {}", file))?;
+    if json_output(m) {
+        return print_json(
+            &json!({ "file": file, "files": files, "encrypted": password.is_some() }),
+        );
+    }
+    println!("{} files exported into {}", files, file);
+    Ok(())
+}
+
+fn import_all(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let file = m.value_of("file").unwrap_or_default();
+    let tarball =
+        std::fs::read(file).with_context(|| format!("can't read the tarball {}", file))?;
+    let password = std::env::var("CERTIFIKA_EXPORT_PASSWORD")
+        .ok()
+        .filter(|password| !password.is_empty())
+        .map(String::into_bytes);
+    let objects = storage::import_all(config.store.as_ref(), password.as_deref(), tarball)?;
+    if json_output(m) {
+        return print_json(&json!({ "file": file, "objects": objects }));
+    }
+    println!("{} objects imported from {}", objects, file);
+    Ok(())
+}
+
+/// `certifika migrate-layout --from <template>` moves the objects written with the layout
+/// `--from` (the default one if not given) to the configured one, see `storage::Layout`.
+fn migrate_layout(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let from = storage::Layout::parse(m.value_of("from").unwrap_or_default())?;
+    let moved = storage::migrate(config.store.as_ref(), &from)?;
+    if json_output(m) {
+        return print_json(&json!({ "moved": moved }));
+    }
+    println!("{} objects moved", moved);
+    Ok(())
+}
+
+/// `certifika import certbot [<dir>]` and `certifika import acme-sh [<dir>]` take over
+/// the accounts of certbot and acme.sh, see the `import` module. They are named by their email contact, or by `--email`.
+fn import(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let mut report = Vec::new();
+    let (accounts, m) = match m.subcommand() {
+        ("certbot", Some(m)) => (
+            import::certbot(std::path::Path::new(m.value_of("dir").unwrap_or_default()))?,
+            m,
+        ),
+        ("acme-sh", Some(m)) => {
+            let home = match m.value_of("dir") {
+                Some(dir) => std::path::PathBuf::from(dir),
+                None => std::path::Path::new(&std::env::var("HOME")?).join(".acme.sh"),
+            };
+            (import::acme_sh(&home)?, m)
+        }
+        _ => return Err(anyhow!("Unknown import subcommand!")),
+    };
+    if accounts.len() > 1 && m.is_present("email") {
+        return Err(anyhow!(
+            "{} accounts found, --email can name only one of them",
+            accounts.len()
+        ));
+    }
+    for imported in accounts {
+        let email = match (m.value_of("email"), imported.email) {
+            (Some(email), _) => email.to_string(),
+            (None, Some(email)) => email,
+            (None, None) => {
+                return Err(anyhow!(
+                    "the account {} has no email contact, name it with --email",
+                    imported.kid
+                ))
+            }
+        };
+        let staging = acme::Ca::is_staging_url(&imported.directory_url);
+        acme::Account::import(
+            email.to_owned(),
+            &*config.store,
+            imported.pkcs8,
+            &imported.directory_url,
+            &imported.kid,
+            staging,
+        )?;
+        if !json_output(m) {
+            println!("imported {} ({})", email, imported.kid);
+        }
+        report.push(json!({ "email": email, "kid": imported.kid, "staging": staging }));
+    }
+    if json_output(m) {
+        print_json(&report)?;
+    }
+    Ok(())
+}
+
+/// `certifika check <domain> --warn-days 21 --crit-days 7` checks when the stored
+/// certificate (or with `--remote`, the one the server serves) expires, the way Nagios
+/// plugins do: prints the status line and exits with 0 (OK), 1 (WARNING),
+/// 2 (CRITICAL) or 3 (UNKNOWN).
+fn check(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let domain = m.value_of("domain").unwrap_or_default();
+    let warn_days: i64 = m.value_of("warn-days").unwrap_or_default().parse()?;
+    let crit_days: i64 = m.value_of("crit-days").unwrap_or_default().parse()?;
+    let info = if m.is_present("remote") {
+        let port = m.value_of("port").unwrap_or_default().parse()?;
+        tls::served_chain(domain, port).and_then(|chain| match chain.first() {
+            Some(leaf) => cert::info_der(leaf),
+            None => Err(anyhow!("no certificate served")),
+        })
+    } else {
+        config
+            .store
+            .read(storage::ObjectKind::Certificate, domain)
+            .map_err(anyhow::Error::from)
+            .and_then(|chain| cert::info(&String::from_utf8(chain)?))
+    };
+    let now = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs() as i64;
+    let (code, line, document) = match info {
+        Ok(info) => {
+            let days = (info.not_after - now).div_euclid(86400);
+            let status = if days < crit_days {
+                (2, "CRITICAL")
+            } else if days < warn_days {
+                (1, "WARNING")
+            } else {
+                (0, "OK")
+            };
+            (
+                status.0,
+                format!(
+                    "{} - {} expires in {} days ({}) | days={};{};{}",
+                    status.1,
+                    domain,
+                    days,
+                    timestamp(info.not_after),
+                    days,
+                    warn_days,
+                    crit_days
+                ),
+                json!({
+                    "status": status.1,
+                    "domain": domain,
+                    "daysLeft": days,
+                    "notAfter": timestamp(info.not_after),
+                    "serial": info.serial,
+                }),
+            )
+        }
+        Err(e) => (
+            3,
+            format!("UNKNOWN - {}: {}", domain, e),
+            json!({ "status": "UNKNOWN", "domain": domain, "error": e.to_string() }),
+        ),
+    };
+    if json_output(m) {
+        print_json(&document)?;
+    } else {
+        println!("{}", line);
+    }
+    std::process::exit(code)
+}
+
+/// Compares the leaf certificate the host serves with the stored one, by their SHA-256
+/// fingerprints. A mismatch means the deployment is stale (or ahead of the store),
+/// and the exit code is 1 then.
+fn verify(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let domain = m.value_of("domain").unwrap_or_default();
+    let name = m.value_of("name").unwrap_or(domain);
+    let port = m.value_of("port").unwrap_or_default().parse()?;
+    let chain = config
+        .store
+        .read(storage::ObjectKind::Certificate, name)
+        .with_context(|| format!("no stored certificate {}", name))?;
+    let stored = cert::details(&String::from_utf8(chain)?)
+        .with_context(|| format!("certificate {}", name))?;
+    let served = match tls::served_chain(domain, port)?.first() {
+        Some(leaf) => cert::details_der(leaf)?,
+        None => return Err(anyhow!("{} serves no certificate", domain)),
+    };
+    let current = served.sha256 == stored.sha256;
+    if json_output(m) {
+        print_json(&json!({
+            "domain": domain,
+            "name": name,
+            "current": current,
+            "stored": {
+                "serial": stored.serial,
+                "sha256": stored.sha256,
+                "notAfter": timestamp(stored.not_after),
+            },
+            "served": {
+                "serial": served.serial,
+                "sha256": served.sha256,
+                "notAfter": timestamp(served.not_after),
+            },
+        }))?;
+    } else if current {
+        println!("{} serves the stored certificate {}", domain, stored.serial);
+    } else {
+        println!(
+            "STALE: {} serves the certificate {} (expires {}), the stored one is {} (expires {})",
+            domain,
+            served.serial,
+            timestamp(served.not_after),
+            stored.serial,
+            timestamp(stored.not_after)
+        );
+    }
+    if !current {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// `certifika doctor <email>` checks the stored account against the CA: whether
+/// the CA still recognizes the account key, under the same kid, and whether
+/// the account is still valid. With `--output json` the verdict is a part of the
+/// output, and an unhealthy account only sets the exit code.
+fn doctor(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let mut account = load_account(config, m)?;
+    let lookup = account.lookup()?;
+    let status = lookup.status.clone().unwrap_or_default();
+    let problem = match &lookup.kid {
+        None => Some("the CA does not recognize the account key".to_string()),
+        Some(kid) if !lookup.kid_matches => Some(format!(
+            "the CA knows the account key under a different kid: {}",
+            kid
+        )),
+        Some(_) if status != "valid" => Some(format!("the account is {}", status)),
+        Some(_) => None,
+    };
+    if json_output(m) {
+        print_json(&json!({
+            "kid": lookup.kid,
+            "kidMatches": lookup.kid_matches,
+            "status": lookup.status,
+            "healthy": problem.is_none(),
+            "problem": problem,
+        }))?;
+        if problem.is_some() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(kid) = &lookup.kid {
+        println!("account: {}", kid);
+        if lookup.kid_matches {
+            println!("status: {}", status);
+        }
+    }
+    match problem {
+        Some(problem) => Err(anyhow!(problem)),
+        None => Ok(()),
+    }
+}
+
+/// Shows the certificate profiles, the meta object, or the probed capabilities
+/// of the CA's directory.
+fn directory(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let url = directory_url(config, m)?;
+    if m.is_present("profiles") {
+        let profiles = acme::directory_meta(&url)?.profiles;
+        if json_output(m) {
+            return print_json(&profiles);
+        }
+        for (name, description) in profiles {
+            println!("{}\t{}", name, description.as_str().unwrap_or_default());
+        }
+    } else if m.is_present("meta") {
+        let meta = acme::directory_meta(&url)?;
+        println!("{}", serde_json::to_string_pretty(&meta)?);
+    } else {
+        let capabilities =
+            acme::Capabilities::fetch(&*config.store, &url, m.is_present("refresh"))?;
+        println!("{}", serde_json::to_string_pretty(&capabilities)?);
+    }
+    Ok(())
+}
+
+/// Shows how long the phases of issuance take with the CA, see `acme::Stats`.
+fn stats(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    let url = directory_url(config, m)?;
+    let stats = acme::Stats::load(&*config.store, &url)?;
+    if json_output(m) {
+        return print_json(
+            &json!({ "directoryUrl": url, "runs": stats.runs, "phases": stats.phases }),
+        );
+    }
+    println!("{} runs against {}", stats.runs, url);
+    for (phase, s) in &stats.phases {
+        println!(
+            "{}\tcount {}\tavg {}ms\tmax {}ms",
+            phase,
+            s.count,
+            s.average_millis(),
+            s.max_millis
+        );
+    }
+    Ok(())
+}
+
+/// Developer utilities, e.g. `certifika debug decode-jws <email> <file>`
+/// to decode a recorded JWS and verify it against the stored account key.
+/// `-` as the file name reads the JWS from stdin.
+fn debug(config: &config::Config, m: &ArgMatches) -> Result<()> {
+    match m.subcommand() {
+        ("decode-jws", Some(m)) => {
+            let path = m.value_of("file").unwrap_or_default();
+            let jws = if path == "-" {
+                let mut buffer = String::new();
+                io::stdin().read_to_string(&mut buffer)?;
+                buffer
+            } else {
+                fs::read_to_string(path)?
+            };
+            let name = acme::store_name(&email(m), staging(config, m));
+            let decoded = acme::decode_jws(&name, &*config.store, &jws)?;
+            println!("{}", serde_json::to_string_pretty(&decoded)?);
+            Ok(())
+        }
+        _ => Err(anyhow!("Unknown debug subcommand!")),
+    }
+}
@@ -0,0 +1,128 @@
+#![deny(clippy::mem_forget)]
+//! internal counters (requests, retries, issued certificates, challenge outcomes).
+//!
+//! The counters are kept in a built-in, lock-free registry. Applications embedding
+//! certifika can bridge them into their own Prometheus/StatsD/whatever registries
+//! by installing a sink, which gets every increment as it happens:
+//! ```
+//! struct Statsd;
+//! impl metrics::Metrics for Statsd {
+//!     fn increment(&self, counter: metrics::Counter, by: u64) {
+//!         statsd_client.count(counter.name(), by);
+//!     }
+//! }
+//! metrics::set_sink(Box::new(Statsd));
+//! ```
+use once_cell::sync::OnceCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static REGISTRY: Registry = Registry {
+    counters: [
+        AtomicU64::new(0),
+        AtomicU64::new(0),
+        AtomicU64::new(0),
+        AtomicU64::new(0),
+        AtomicU64::new(0),
+        AtomicU64::new(0),
+        AtomicU64::new(0),
+        AtomicU64::new(0),
+        AtomicU64::new(0),
+        AtomicU64::new(0),
+    ],
+};
+static SINK: OnceCell<Box<dyn Metrics>> = OnceCell::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Counter {
+    Requests,
+    Retries,
+    Orders,
+    Renewals,
+    ChallengesValid,
+    ChallengesInvalid,
+    ConnectionsCreated,
+    ConnectionsReused,
+    /// total time of the requests that opened a new connection
+    NewConnectionMillis,
+    /// total time of the requests over a pooled connection
+    ReusedConnectionMillis,
+}
+
+impl Counter {
+    pub const ALL: [Counter; 10] = [
+        Counter::Requests,
+        Counter::Retries,
+        Counter::Orders,
+        Counter::Renewals,
+        Counter::ChallengesValid,
+        Counter::ChallengesInvalid,
+        Counter::ConnectionsCreated,
+        Counter::ConnectionsReused,
+        Counter::NewConnectionMillis,
+        Counter::ReusedConnectionMillis,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Counter::Requests => "certifika_requests_total",
+            Counter::Retries => "certifika_retries_total",
+            Counter::Orders => "certifika_orders_total",
+            Counter::Renewals => "certifika_renewals_total",
+            Counter::ChallengesValid => "certifika_challenges_valid_total",
+            Counter::ChallengesInvalid => "certifika_challenges_invalid_total",
+            Counter::ConnectionsCreated => "certifika_http_connections_created_total",
+            Counter::ConnectionsReused => "certifika_http_connections_reused_total",
+            Counter::NewConnectionMillis => "certifika_http_new_connection_milliseconds_total",
+            Counter::ReusedConnectionMillis => {
+                "certifika_http_reused_connection_milliseconds_total"
+            }
+        }
+    }
+}
+
+/// Anything that can take the counter increments.
+pub trait Metrics: Send + Sync {
+    fn increment(&self, counter: Counter, by: u64);
+}
+
+/// The built-in registry, safe to share between threads.
+pub struct Registry {
+    counters: [AtomicU64; 10],
+}
+
+impl Registry {
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize].load(Ordering::Relaxed)
+    }
+
+    /// Returns the current values of all the counters.
+    pub fn snapshot(&self) -> Vec<(Counter, u64)> {
+        Counter::ALL.iter().map(|c| (*c, self.get(*c))).collect()
+    }
+}
+
+impl Metrics for Registry {
+    fn increment(&self, counter: Counter, by: u64) {
+        self.counters[counter as usize].fetch_add(by, Ordering::Relaxed);
+    }
+}
+
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+/// Installs a sink for the increments, can be done only once.
+pub fn set_sink(sink: Box<dyn Metrics>) -> Result<(), Box<dyn Metrics>> {
+    SINK.set(sink)
+}
+
+pub fn increment(counter: Counter) {
+    increment_by(counter, 1);
+}
+
+pub fn increment_by(counter: Counter, by: u64) {
+    REGISTRY.increment(counter, by);
+    if let Some(sink) = SINK.get() {
+        sink.increment(counter, by);
+    }
+}
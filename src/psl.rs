@@ -0,0 +1,149 @@
+#![deny(clippy::mem_forget)]
+//! Minimal implementation of the [public suffix list](https://publicsuffix.org/list/) algorithm.
+//!
+//! Used to figure out the registered domain of an identifier, i.e. the public suffix
+//! plus one more label: `foo.bar.example.co.uk` -> `example.co.uk`.
+//!
+//! With the `bundled-psl` feature the list from `data/public_suffix_list.dat` is
+//! compiled in. A newer copy can be used without rebuilding by pointing
+//! `CERTIFIKA_PSL_FILE` to it.
+use anyhow::{anyhow, Result};
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use std::{env, fs};
+
+#[cfg(feature = "bundled-psl")]
+const DEFAULT_RULES: &str = include_str!("../data/public_suffix_list.dat");
+#[cfg(not(feature = "bundled-psl"))]
+const DEFAULT_RULES: &str = BUILTIN_RULES;
+
+/// A handful of multi-label suffixes, enough for the most common second-level
+/// registrations. Names not covered here fall back to the implicit `*` rule,
+/// i.e. the last label is treated as the public suffix.
+#[cfg(not(feature = "bundled-psl"))]
+const BUILTIN_RULES: &str = "
+co.uk
+org.uk
+ac.uk
+gov.uk
+me.uk
+com.au
+net.au
+org.au
+co.nz
+co.jp
+ne.jp
+or.jp
+com.br
+com.cn
+com.tr
+com.ua
+co.za
+co.in
+*.ck
+!www.ck
+";
+
+static LIST: Lazy<List> = Lazy::new(|| match env::var("CERTIFIKA_PSL_FILE") {
+    Ok(path) => match fs::read_to_string(&path) {
+        Ok(text) => List::parse(&text),
+        Err(e) => {
+            log::warn!(r#"{{"op":"psl load","file":"{}","error":"{}"}}"#, path, e);
+            List::parse(DEFAULT_RULES)
+        }
+    },
+    Err(_) => List::parse(DEFAULT_RULES),
+});
+
+/// Parsed public suffix list rules.
+pub struct List {
+    rules: HashSet<String>,
+    wildcards: HashSet<String>,
+    exceptions: HashSet<String>,
+}
+
+impl List {
+    /// Parses rules in the `public_suffix_list.dat` format: one rule per line,
+    /// `//` comments, `*.` wildcard rules and `!` exception rules.
+    pub fn parse(text: &str) -> Self {
+        let mut list = List {
+            rules: HashSet::new(),
+            wildcards: HashSet::new(),
+            exceptions: HashSet::new(),
+        };
+        for line in text.lines() {
+            let rule = match line.split_whitespace().next() {
+                Some(r) if !r.starts_with("//") => r.to_lowercase(),
+                _ => continue,
+            };
+            if let Some(r) = rule.strip_prefix('!') {
+                list.exceptions.insert(r.to_string());
+            } else if let Some(r) = rule.strip_prefix("*.") {
+                list.wildcards.insert(r.to_string());
+            } else {
+                list.rules.insert(rule);
+            }
+        }
+        list
+    }
+
+    /// Returns the public suffix of the `domain`.
+    pub fn public_suffix(&self, domain: &str) -> String {
+        let labels: Vec<&str> = domain.split('.').collect();
+        for i in 0..labels.len() {
+            let candidate = labels[i..].join(".");
+            if self.exceptions.contains(&candidate) {
+                return labels[i + 1..].join(".");
+            }
+            if self.rules.contains(&candidate)
+                || (i + 1 < labels.len() && self.wildcards.contains(&labels[i + 1..].join(".")))
+            {
+                return candidate;
+            }
+        }
+        labels[labels.len() - 1].to_string()
+    }
+
+    /// Returns the registered domain of the `domain`, or `None` if
+    /// the `domain` is a public suffix itself.
+    pub fn registered_domain(&self, domain: &str) -> Option<String> {
+        let suffix = self.public_suffix(domain);
+        if domain.len() <= suffix.len() {
+            return None;
+        }
+        let rest = &domain[..domain.len() - suffix.len() - 1];
+        let label = rest.rsplit('.').next()?;
+        Some(format!("{}.{}", label, suffix))
+    }
+}
+
+/// Computes the registered domain of the `domain`. Rate limits of Let's Encrypt
+/// are counted per registered domain, too.
+pub fn registered_domain(domain: &str) -> Option<String> {
+    LIST.registered_domain(&normalize(domain))
+}
+
+/// Rejects wildcard identifiers covering a whole public suffix, e.g. `*.co.uk`,
+/// CAs refuse to issue those.
+pub fn check_wildcard(identifier: &str) -> Result<()> {
+    if let Some(base) = identifier.strip_prefix("*.") {
+        let base = normalize(base);
+        if base.contains('*') {
+            return Err(anyhow!(
+                "only the leftmost label can be a wildcard: {}",
+                identifier
+            ));
+        }
+        if LIST.registered_domain(&base).is_none() {
+            return Err(anyhow!(
+                "wildcard over a public suffix is not allowed: {}",
+                identifier
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn normalize(domain: &str) -> String {
+    domain.trim_end_matches('.').to_lowercase()
+}
@@ -0,0 +1,368 @@
+#![deny(clippy::mem_forget)]
+//! the single model of "what certificate to get, and what to do with it".
+//!
+//! Every entry point (CLI flags, config profiles, the control API) builds a `CertRequest`,
+//! so they can all express the same things. It's serializable, profiles are
+//! just stored requests:
+//! ```json
+//! {
+//!   "identifiers": ["example.com", "*.example.com"],
+//!   "key_type": "ecdsa-p384",
+//!   "challenge_prefs": ["dns-01"],
+//!   "domain_challenge_prefs": {"example.com": ["http-01", "dns-01"]},
+//!   "profile_name": "tlsserver",
+//!   "deploy_targets": ["keychain"],
+//!   "pre_validation_hooks": ["systemctl stop nginx"],
+//!   "post_validation_hooks": ["systemctl start nginx"],
+//!   "hooks": ["systemctl reload nginx"],
+//!   "reuse_key": false,
+//!   "must_staple": false,
+//!   "not_after": "2021-06-01T00:00:00Z",
+//!   "short_lived": false
+//! }
+//! ```
+use crate::acme::{IssuedCertificate, PendingChallenge};
+use crate::export;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::process::Command;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CertRequest {
+    pub identifiers: Vec<String>,
+    pub key_type: KeyType,
+    /// challenge types in the order of preference, dns-01 only when empty
+    pub challenge_prefs: Vec<ChallengeType>,
+    /// the preferences for particular domains (and their subdomains), take precedence
+    /// over the `challenge_prefs`
+    pub domain_challenge_prefs: BTreeMap<String, Vec<ChallengeType>>,
+    /// the CA's certificate profile, see `Capabilities::profiles`
+    pub profile_name: Option<String>,
+    /// names of the deploy targets, the configured ones when empty
+    pub deploy_targets: Vec<String>,
+    /// shell commands, run once per order before its challenges are validated, e.g. to
+    /// publish the TXT records or to free the port 80. Left out when empty, so the requests
+    /// in the order bundles exported before they existed keep their signatures.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pre_validation_hooks: Vec<String>,
+    /// shell commands, run once per order after the validation, whether it succeeded
+    /// or not, a failed pre-validation hook included
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub post_validation_hooks: Vec<String>,
+    /// the deploy hooks, shell commands run once the certificate is issued and deployed
+    pub hooks: Vec<String>,
+    /// renew with the stored key of the certificate rather than a fresh one (the default),
+    /// for the deployments that pin the key (HPKP-style, DANE). Overrides the `key_type`.
+    pub reuse_key: bool,
+    /// request the OCSP Must-Staple extension
+    pub must_staple: bool,
+    /// the requested validity window (RFC3339), for the CAs that honor it
+    pub not_before: Option<String>,
+    pub not_after: Option<String>,
+    /// order a short-lived certificate (days rather than months), with the CA's
+    /// short-lived profile when it has one, or a notAfter a few days ahead
+    pub short_lived: bool,
+}
+
+/// When the hooks of a request run, see `CertRequest::run_hooks()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookStage {
+    PreValidation,
+    PostValidation,
+    Deploy,
+}
+
+impl HookStage {
+    pub fn name(self) -> &'static str {
+        match self {
+            HookStage::PreValidation => "pre-validation",
+            HookStage::PostValidation => "post-validation",
+            HookStage::Deploy => "deploy",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyType {
+    EcdsaP256,
+    EcdsaP384,
+    Rsa2048,
+    Rsa4096,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ChallengeType {
+    #[serde(rename = "dns-01")]
+    Dns01,
+    #[serde(rename = "http-01")]
+    Http01,
+}
+
+impl Default for KeyType {
+    fn default() -> Self {
+        KeyType::EcdsaP256
+    }
+}
+
+impl Default for ChallengeType {
+    fn default() -> Self {
+        ChallengeType::Dns01
+    }
+}
+
+impl FromStr for KeyType {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "ecdsa-p256" | "p256" => Ok(KeyType::EcdsaP256),
+            "ecdsa-p384" | "p384" => Ok(KeyType::EcdsaP384),
+            "rsa-2048" | "rsa2048" => Ok(KeyType::Rsa2048),
+            "rsa-4096" | "rsa4096" => Ok(KeyType::Rsa4096),
+            _ => Err(anyhow!("unknown key type: {}", name)),
+        }
+    }
+}
+
+impl KeyType {
+    pub fn algorithm(self) -> &'static rcgen::SignatureAlgorithm {
+        match self {
+            KeyType::EcdsaP256 => &rcgen::PKCS_ECDSA_P256_SHA256,
+            KeyType::EcdsaP384 => &rcgen::PKCS_ECDSA_P384_SHA384,
+            KeyType::Rsa2048 | KeyType::Rsa4096 => &rcgen::PKCS_RSA_SHA256,
+        }
+    }
+
+    /// The size of RSA keys, rcgen can't generate them.
+    pub fn rsa_bits(self) -> Option<usize> {
+        match self {
+            KeyType::Rsa2048 => Some(2048),
+            KeyType::Rsa4096 => Some(4096),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ChallengeType {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "dns-01" => Ok(ChallengeType::Dns01),
+            "http-01" => Ok(ChallengeType::Http01),
+            "tls-alpn-01" => Err(anyhow!(
+                "tls-alpn-01 challenges are not supported, use dns-01 or http-01"
+            )),
+            _ => Err(anyhow!("unknown challenge type: {}", name)),
+        }
+    }
+}
+
+impl ChallengeType {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChallengeType::Dns01 => "dns-01",
+            ChallengeType::Http01 => "http-01",
+        }
+    }
+}
+
+/// Parses comma separated challenge types, e.g. `dns-01,http-01`.
+pub fn parse_challenge_prefs(list: &str) -> Result<Vec<ChallengeType>> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Parses `;` separated `domain=types` pairs, e.g. `example.com=http-01,dns-01;example.org=dns-01`.
+pub fn parse_domain_challenge_prefs(spec: &str) -> Result<BTreeMap<String, Vec<ChallengeType>>> {
+    let mut prefs = BTreeMap::new();
+    for pair in spec.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+        let mut kv = pair.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(domain), Some(types)) if !domain.trim().is_empty() => {
+                prefs.insert(domain.trim().to_lowercase(), parse_challenge_prefs(types)?);
+            }
+            _ => return Err(anyhow!("invalid domain challenge preference: {}", pair)),
+        }
+    }
+    Ok(prefs)
+}
+
+impl CertRequest {
+    /// The primary identifier, the issued certificate is stored under it.
+    pub fn name(&self) -> Result<&str> {
+        self.identifiers
+            .first()
+            .map(String::as_str)
+            .ok_or_else(|| anyhow!("no identifiers in the certificate request"))
+    }
+
+    /// The challenge preferences for the `domain`: the most specific domain
+    /// preferences win, i.e. for `a.dev.example.com` the ones of `dev.example.com`
+    /// are used rather than the ones of `example.com`.
+    pub fn challenge_prefs(&self, domain: &str) -> Vec<ChallengeType> {
+        let domain = domain.trim_start_matches("*.").to_lowercase();
+        let mut name = domain.as_str();
+        loop {
+            if let Some(prefs) = self.domain_challenge_prefs.get(name) {
+                return prefs.clone();
+            }
+            match name.find('.') {
+                Some(i) => name = &name[i + 1..],
+                None => break,
+            }
+        }
+        if self.challenge_prefs.is_empty() {
+            vec![ChallengeType::Dns01]
+        } else {
+            self.challenge_prefs.clone()
+        }
+    }
+
+    /// Checks the requested validity window: both ends have to be RFC3339
+    /// timestamps, and the window can't be empty or already over.
+    pub fn check_validity(&self) -> Result<()> {
+        let parse = |t: &Option<String>| -> Result<Option<DateTime<FixedOffset>>> {
+            match t {
+                Some(t) => {
+                    Ok(Some(DateTime::parse_from_rfc3339(t).map_err(|e| {
+                        anyhow!("invalid RFC3339 timestamp {}: {}", t, e)
+                    })?))
+                }
+                None => Ok(None),
+            }
+        };
+        let not_before = parse(&self.not_before)?;
+        let not_after = parse(&self.not_after)?;
+        if let (Some(not_before), Some(not_after)) = (not_before, not_after) {
+            if not_after <= not_before {
+                return Err(anyhow!("notAfter has to be later than notBefore"));
+            }
+        }
+        if matches!(not_after, Some(t) if t.with_timezone(&Utc) <= Utc::now()) {
+            return Err(anyhow!("notAfter is in the past"));
+        }
+        Ok(())
+    }
+
+    /// Runs the validation hooks of the `stage` once for the order, so a service is
+    /// stopped and started once however many identifiers there are. The challenges
+    /// are in `CERTIFIKA_CHALLENGES`, on top of the environment of `run_hooks()`,
+    /// a line each: the identifier, the challenge type, the token, the record (the TXT
+    /// record to publish, or the URL path to serve for http-01), its value and the zone
+    /// of the TXT record (as `CERTIFIKA_ZONE_OVERRIDES` or else the public suffix list
+    /// have it, missing for http-01), separated by spaces:
+    /// ```text
+    /// echo "$CERTIFIKA_CHALLENGES" | while read domain challenge token record value zone; do ...
+    /// ```
+    pub fn run_validation_hooks(
+        &self,
+        stage: HookStage,
+        challenges: &[PendingChallenge],
+    ) -> Result<()> {
+        let lines: Vec<String> = challenges
+            .iter()
+            .map(|c| {
+                format!(
+                    "{} {} {} {} {} {}",
+                    c.domain,
+                    c.challenge.name(),
+                    c.token,
+                    c.record,
+                    c.value,
+                    c.zone.as_deref().unwrap_or_default()
+                )
+                .trim_end()
+                .to_string()
+            })
+            .collect();
+        self.run_hooks(stage, &[("CERTIFIKA_CHALLENGES", lines.join("\n"))])
+    }
+
+    /// Runs the deploy hooks with the `issued` certificate details in the environment,
+    /// on top of the ones of `run_hooks()`, the way certbot provides them:
+    /// `CERTIFIKA_CERT_PATH`, `CERTIFIKA_CHAIN_PATH` (the intermediates),
+    /// `CERTIFIKA_FULLCHAIN_PATH`, `CERTIFIKA_KEY_PATH`, `CERTIFIKA_EXPIRY` (RFC3339)
+    /// and `CERTIFIKA_EXPIRES` (seconds since the UNIX epoch). The files are exported
+    /// into a fresh temporary directory (see `export::temp_dir()`), removed once
+    /// the hooks are done.
+    pub fn run_deploy_hooks(&self, issued: &IssuedCertificate) -> Result<()> {
+        if self.hooks.is_empty() {
+            return Ok(());
+        }
+        let name = self.name()?;
+        let dir = export::temp_dir(name)?;
+        if let Err(e) = export::export_new(issued, name, &dir, export::Format::Pem, "") {
+            let _ = std::fs::remove_dir_all(&dir);
+            return Err(e);
+        }
+        let path = |suffix: &str| {
+            dir.join(format!("{}.{}", name, suffix))
+                .to_string_lossy()
+                .into_owned()
+        };
+        let expiry = DateTime::<Utc>::from_utc(
+            chrono::NaiveDateTime::from_timestamp(issued.expires, 0),
+            Utc,
+        );
+        let result = self.run_hooks(
+            HookStage::Deploy,
+            &[
+                ("CERTIFIKA_CERT_PATH", path("crt")),
+                ("CERTIFIKA_CHAIN_PATH", path("chain.pem")),
+                ("CERTIFIKA_FULLCHAIN_PATH", path("full.pem")),
+                ("CERTIFIKA_KEY_PATH", path("key")),
+                ("CERTIFIKA_EXPIRY", expiry.to_rfc3339()),
+                ("CERTIFIKA_EXPIRES", issued.expires.to_string()),
+            ],
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+
+    /// Runs the hooks of the `stage` one by one, the first failure stops them. They get
+    /// `CERTIFIKA_HOOK` (the stage), `CERTIFIKA_DOMAIN` and `CERTIFIKA_DOMAINS`
+    /// in the environment, along with the `env` of the stage.
+    fn run_hooks(&self, stage: HookStage, env: &[(&str, String)]) -> Result<()> {
+        let hooks = match stage {
+            HookStage::PreValidation => &self.pre_validation_hooks,
+            HookStage::PostValidation => &self.post_validation_hooks,
+            HookStage::Deploy => &self.hooks,
+        };
+        for hook in hooks {
+            let mut command = Command::new("sh");
+            command
+                .args(&["-c", hook])
+                .env("CERTIFIKA_HOOK", stage.name())
+                .env("CERTIFIKA_DOMAIN", self.name()?)
+                .env("CERTIFIKA_DOMAINS", self.identifiers.join(","));
+            for (name, value) in env {
+                command.env(name, value);
+            }
+            let status = command.status()?;
+            if !status.success() {
+                return Err(anyhow!(
+                    "{} hook failed with {}: {}",
+                    stage.name(),
+                    status,
+                    hook
+                ));
+            }
+            log::info!(
+                r#"{{"op":"hook","stage":"{}","domain":"{}","hook":{:?}}}"#,
+                stage.name(),
+                self.name()?,
+                hook
+            );
+        }
+        Ok(())
+    }
+}
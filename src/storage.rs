@@ -1,88 +1,304 @@
 use std::env;
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
 
+mod archive;
+mod azure;
+mod backup;
+#[cfg(feature = "sled")]
+mod db;
+mod encryption;
+mod layout;
+mod lock;
+mod mem;
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(test)]
+mod tests;
+mod vault;
+
+#[cfg(feature = "redis")]
+pub use self::redis::RedisStore;
+pub use archive::{export_all, import_all};
+pub use azure::KeyVaultStore;
+pub use backup::{restore, snapshot};
+#[cfg(feature = "sled")]
+pub use db::DbStore;
+pub use layout::{migrate, set as set_layout, Layout, DEFAULT as DEFAULT_LAYOUT};
+pub use lock::{lock, renew as renew_lock, StoreLock};
+pub use mem::MemStore;
+pub use vault::{VaultAuth, VaultClient, VaultOptions, VaultStore};
+
+#[derive(Clone, Copy)]
 pub enum ObjectKind {
     Directory,
     KeyPair,
     Account,
+    Eab,
+    Certificate,
+    CertKey,
+    /// the intermediates of a certificate, without the leaf
+    Chain,
+    /// the final state of the order a certificate was issued for
+    Order,
+    Capabilities,
+    RateLimits,
+    TermsOfService,
+    Stats,
+    Lineage,
+    Created,
+    /// see `lock`
+    Lock,
+}
+
+impl ObjectKind {
+    /// the kinds of the stored objects, the locks aside
+    pub const ALL: &'static [ObjectKind] = &[
+        ObjectKind::Directory,
+        ObjectKind::KeyPair,
+        ObjectKind::Account,
+        ObjectKind::Eab,
+        ObjectKind::Certificate,
+        ObjectKind::CertKey,
+        ObjectKind::Chain,
+        ObjectKind::Order,
+        ObjectKind::Capabilities,
+        ObjectKind::RateLimits,
+        ObjectKind::TermsOfService,
+        ObjectKind::Stats,
+        ObjectKind::Lineage,
+        ObjectKind::Created,
+    ];
+
+    /// The dir of the objects of the kind and their extension, see `layout`.
+    fn dir_ext(&self) -> (&'static str, &'static str) {
+        match self {
+            ObjectKind::Directory => ("accounts", "dir"),
+            ObjectKind::Account => ("accounts", "acc"),
+            ObjectKind::KeyPair => ("accounts", "key"),
+            ObjectKind::Eab => ("accounts", "eab"),
+            ObjectKind::Certificate => ("certs", "crt"),
+            ObjectKind::CertKey => ("certs", "key"),
+            ObjectKind::Chain => ("certs", "chain"),
+            ObjectKind::Order => ("certs", "order"),
+            ObjectKind::Capabilities => ("directories", "cap"),
+            ObjectKind::RateLimits => ("accounts", "rl"),
+            ObjectKind::TermsOfService => ("accounts", "tos"),
+            ObjectKind::Stats => ("directories", "stats"),
+            ObjectKind::Lineage => ("certs", "json"),
+            ObjectKind::Created => ("accounts", "created"),
+            ObjectKind::Lock => ("locks", "lock"),
+        }
+    }
+
+    /// The path of the object under the `prefix`, in the current layout. Every store
+    /// keys the objects by it, so it's where the names are checked, see `check_name()`.
+    fn path(&self, prefix: &str, name: &str) -> Result<String, StoreError> {
+        check_name(name)?;
+        Ok(layout::current(|layout| layout.path(*self, prefix, name)))
+    }
+
+    /// Whether the objects of the kind can be rebuilt or are of use for a while only,
+    /// so the stores may let them expire.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ObjectKind::Capabilities | ObjectKind::RateLimits | ObjectKind::Order
+        )
+    }
+
+    /// The directory the objects of the kind are kept in, and their suffix.
+    fn location(&self, prefix: &str) -> (String, String) {
+        split_location(&layout::current(|layout| layout.path(*self, prefix, "")))
+    }
+}
+
+/// Rejects the names that aren't a single path segment, e.g. `../../x` from a
+/// crafted tarball, they'd reach out of the dir of the objects of their kind.
+pub(crate) fn check_name(name: &str) -> Result<(), StoreError> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.contains(|c| c == '/' || c == '\\' || c == '\0')
+    {
+        return Err(StoreError::Name(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Splits the path of an object with an empty name into its directory and suffix.
+fn split_location(path: &str) -> (String, String) {
+    match path.rfind('/') {
+        Some(i) => (path[..i].to_string(), path[i + 1..].to_string()),
+        None => (String::new(), path.to_string()),
+    }
+}
+
+/// The names of the `entries` with the `suffix`, which is stripped.
+fn names_with_suffix<I: Iterator<Item = String>>(entries: I, suffix: &str) -> Vec<String> {
+    let mut names: Vec<String> = entries
+        .filter(|e| e.ends_with(suffix) && e.len() > suffix.len())
+        .map(|e| e[..e.len() - suffix.len()].to_string())
+        .collect();
+    names.sort();
+    names
 }
 
 #[derive(Error, Debug)]
 pub enum StoreError {
-    #[error("Both VAULT_ADDR and VAULT_TOKEN must be set: {0:?}")]
+    #[error("Both VAULT_ADDR and VAULT_TOKEN (or another auth method) must be set: {0:?}")]
     Init(env::VarError),
     #[error("Vault API: {0:?}")]
     Vault(ureq::Error),
+    #[error("Vault login with the {0} auth method returned no token")]
+    VaultLogin(String),
+    #[error("Azure Key Vault: {0:?}")]
+    Azure(ureq::Error),
     #[error("JSON encode: {0:?}")]
     JsonEncode(std::io::Error),
     #[error("Base64 decode: {0:?}")]
     Base64Decode(base64::DecodeError),
     #[error("File I/O: {0:?}")]
     File(std::io::Error),
+    #[cfg(feature = "sled")]
+    #[error("sled: {0:?}")]
+    Db(sled::Error),
+    #[cfg(feature = "redis")]
+    #[error("Redis: {0:?}")]
+    Redis(::redis::RedisError),
+    #[error("Wrong passphrase or corrupted object: {0:?}")]
+    Encryption(ring::error::Unspecified),
+    #[error("The store is locked by {0}")]
+    Locked(String),
+    #[error("The store lock of {0} has been taken over")]
+    LockLost(String),
+    #[error("Invalid store layout {0}: it needs {{dir}}, {{ext}}, and {{name}} starting the last segment")]
+    Layout(String),
+    #[error("Archive: {0}")]
+    Archive(String),
+    #[error("No such object: {0}")]
+    NotFound(String),
+    #[error("Invalid object name {0:?}")]
+    Name(String),
 }
 
 pub trait Store {
     fn read(&self, kind: ObjectKind, account_name: &str) -> Result<Vec<u8>, StoreError>;
     fn write(&self, kind: ObjectKind, account_name: &str, payload: &[u8])
         -> Result<(), StoreError>;
+    /// The names of all the stored objects of the `kind`, sorted.
+    fn list(&self, kind: ObjectKind) -> Result<Vec<String>, StoreError>;
+    /// Removes the object, there being none is fine.
+    fn delete(&self, kind: ObjectKind, account_name: &str) -> Result<(), StoreError>;
+    /// Writes the object unless there is one already, returns whether it did.
+    /// The locks are made of it, so it's atomic wherever the backend allows.
+    fn create(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<bool, StoreError>;
+    /// Replaces the object with `new` if it still is `current`, returns whether it did.
+    /// The locks are taken over, renewed and released with it, so it's atomic
+    /// wherever the backend allows.
+    fn swap(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        current: &[u8],
+        new: &[u8],
+    ) -> Result<bool, StoreError>;
 }
 
 pub struct FileStore {
     base_dir: String,
+    /// the objects are encrypted with it, see `encryption`
+    passphrase: Option<Vec<u8>>,
 }
 
-pub struct VaultStore {
-    addr: String,
-    token: String,
-    prefix: String,
+impl FileStore {
+    pub fn init(base_dir: &str) -> Result<Self, StoreError> {
+        Ok(FileStore {
+            base_dir: base_dir.to_string(),
+            passphrase: None,
+        })
+    }
+
+    /// Encrypts the objects with the `passphrase`, the plaintext ones are still read.
+    pub fn encrypted(self, passphrase: Vec<u8>) -> Self {
+        FileStore {
+            passphrase: Some(passphrase),
+            ..self
+        }
+    }
 }
 
-impl VaultStore {
-    pub fn init(prefix: &str) -> Result<Self, StoreError> {
-        Ok(VaultStore {
-            addr: env::var("VAULT_ADDR").map_err(StoreError::Init)?,
-            token: env::var("VAULT_TOKEN").map_err(StoreError::Init)?,
-            prefix: prefix.to_string(),
-        })
+impl FileStore {
+    /// How long ago the object was written, `None` if there is no such object.
+    fn age(&self, kind: ObjectKind, account_name: &str) -> Option<Duration> {
+        let modified = fs::metadata(kind.path(&self.base_dir, account_name).ok()?)
+            .and_then(|m| m.modified())
+            .ok()?;
+        SystemTime::now().duration_since(modified).ok()
     }
-    fn put(&self, path: &str, payload: &[u8]) -> Result<(), StoreError> {
-        let agent = ureq::AgentBuilder::new().build();
-        let url = format!("{}/v1/secret/data/{}", &self.addr, path);
-        let _ = agent
-            .post(&url)
-            .set("X-Vault-Token", &self.token)
-            .send_json(ureq::json!({"data": { "value" : base64::encode(payload)}}))
-            .map_err(StoreError::Vault)?;
-        Ok(())
+
+    /// Creates the missing directories of the object, accessible by the owner only.
+    fn create_dirs(&self, kind: ObjectKind, account_name: &str) -> Result<(), StoreError> {
+        let path = kind.path(&self.base_dir, account_name)?;
+        let dir = match Path::new(&path).parent() {
+            Some(dir) => dir,
+            None => return Ok(()),
+        };
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(dir).map_err(StoreError::File)
     }
-    fn get(&self, path: &str) -> Result<Vec<u8>, StoreError> {
-        let agent = ureq::AgentBuilder::new().build();
-        let url = format!("{}/v1/secret/data/{}", &self.addr, path);
-        let json: serde_json::Value = agent
-            .get(&url)
-            .set("X-Vault-Token", &self.token)
-            .call()
-            .map_err(StoreError::Vault)?
-            .into_json()
-            .map_err(StoreError::JsonEncode)?;
-        let value = &json["data"]["data"]["value"].as_str().unwrap();
-        Ok(value.to_string().into_bytes())
+
+    /// The `payload` as it's written into the file, encrypted if there is a passphrase.
+    fn seal(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, StoreError> {
+        match &self.passphrase {
+            Some(passphrase) => {
+                encryption::seal(passphrase, &kind.path("", account_name)?, payload)
+            }
+            None => Ok(payload.to_vec()),
+        }
+    }
+
+    /// The object as read from the file, decrypted if there is a passphrase.
+    fn unseal(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        buffer: Vec<u8>,
+    ) -> Result<Vec<u8>, StoreError> {
+        match &self.passphrase {
+            Some(passphrase) => encryption::open(passphrase, &kind.path("", account_name)?, buffer),
+            None => Ok(buffer),
+        }
     }
 }
 
-impl Store for VaultStore {
+impl Store for FileStore {
     fn read(&self, kind: ObjectKind, account_name: &str) -> Result<Vec<u8>, StoreError> {
-        let path = match kind {
-            ObjectKind::Directory => {
-                format!("{}/accounts/{}.dir", self.prefix, account_name)
-            }
-            ObjectKind::Account => format!("{}/accounts/{}.acc", self.prefix, account_name),
-            ObjectKind::KeyPair => format!("{}/accounts/{}.key", self.prefix, account_name),
-        };
-        let buffer = base64::decode(self.get(&path)?).map_err(StoreError::Base64Decode)?;
-        Ok(buffer)
+        let filename = kind.path(&self.base_dir, account_name)?;
+        let mut file = File::open(filename).map_err(StoreError::File)?;
+        let mut buffer: Vec<u8> = Vec::new();
+        file.read_to_end(&mut buffer).map_err(StoreError::File)?;
+        self.unseal(kind, account_name, buffer)
     }
 
     fn write(
@@ -91,37 +307,239 @@ impl Store for VaultStore {
         account_name: &str,
         payload: &[u8],
     ) -> Result<(), StoreError> {
-        let path = match kind {
-            ObjectKind::Directory => {
-                format!("{}/accounts/{}.dir", self.prefix, account_name)
+        let filename = kind.path(&self.base_dir, account_name)?;
+        let payload = self.seal(kind, account_name, payload)?;
+        self.create_dirs(kind, account_name)?;
+        replace(Path::new(&filename), &payload).map_err(StoreError::File)
+    }
+
+    fn list(&self, kind: ObjectKind) -> Result<Vec<String>, StoreError> {
+        let (dir, suffix) = kind.location(&self.base_dir);
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(StoreError::File(e)),
+        };
+        let mut files = Vec::new();
+        for entry in entries {
+            let entry = entry.map_err(StoreError::File)?;
+            if let Some(file) = entry.file_name().to_str() {
+                files.push(file.to_string());
             }
-            ObjectKind::Account => format!("{}/accounts/{}.acc", self.prefix, account_name),
-            ObjectKind::KeyPair => format!("{}/accounts/{}.key", self.prefix, account_name),
+        }
+        Ok(names_with_suffix(files.into_iter(), &suffix))
+    }
+
+    fn delete(&self, kind: ObjectKind, account_name: &str) -> Result<(), StoreError> {
+        match fs::remove_file(kind.path(&self.base_dir, account_name)?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(StoreError::File(e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The written temp file is linked to the object's path, which fails if it exists.
+    fn create(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<bool, StoreError> {
+        let filename = kind.path(&self.base_dir, account_name)?;
+        let payload = self.seal(kind, account_name, payload)?;
+        self.create_dirs(kind, account_name)?;
+        let path = Path::new(&filename);
+        let temp = temp_file(path, &payload).map_err(StoreError::File)?;
+        let linked = fs::hard_link(&temp, path);
+        let _ = fs::remove_file(&temp);
+        match linked {
+            Ok(()) => sync_dir(path).map(|_| true).map_err(StoreError::File),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(StoreError::File(e)),
+        }
+    }
+
+    /// The swapping processes take turns on a guard file next to the object (see
+    /// `SwapGuard`), and the `new` one is renamed over the `current` one, so the path
+    /// is never free for a `create` in the meantime.
+    fn swap(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        current: &[u8],
+        new: &[u8],
+    ) -> Result<bool, StoreError> {
+        let filename = kind.path(&self.base_dir, account_name)?;
+        let path = Path::new(&filename);
+        let new = self.seal(kind, account_name, new)?;
+        self.create_dirs(kind, account_name)?;
+        let _guard = SwapGuard::take(path).map_err(StoreError::File)?;
+        let buffer = match fs::read(path) {
+            Ok(buffer) => buffer,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(StoreError::File(e)),
         };
-        self.put(&path, payload)?;
-        Ok(())
+        if self.unseal(kind, account_name, buffer)? != current {
+            return Ok(false);
+        }
+        replace(path, &new).map(|_| true).map_err(StoreError::File)
     }
 }
 
-impl FileStore {
-    pub fn init(base_dir: &str) -> Result<Self, StoreError> {
-        Ok(FileStore {
-            base_dir: base_dir.to_string(),
-        })
+/// The turn of a process to swap the object at a path, taken by locking the guard
+/// file next to it (`flock` where there is one, so a process that dies holding it
+/// doesn't hold up the others), and given up when dropped. The guard file is left
+/// in place, removing it would race with the processes waiting for it.
+struct SwapGuard {
+    #[cfg(not(unix))]
+    path: PathBuf,
+    _file: File,
+}
+
+impl SwapGuard {
+    fn take(path: &Path) -> std::io::Result<Self> {
+        let mut guard = path.as_os_str().to_owned();
+        guard.push(".guard");
+        let guard = PathBuf::from(guard);
+        let mut options = OpenOptions::new();
+        options.write(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            use std::os::unix::io::AsRawFd;
+            let file = options.create(true).mode(0o600).open(&guard)?;
+            while unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                let e = std::io::Error::last_os_error();
+                if e.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+            Ok(SwapGuard { _file: file })
+        }
+        #[cfg(not(unix))]
+        {
+            // the guard file exists while it's taken
+            let deadline = std::time::Instant::now() + Duration::from_secs(10);
+            loop {
+                match options.create_new(true).open(&guard) {
+                    Ok(file) => {
+                        return Ok(SwapGuard {
+                            path: guard,
+                            _file: file,
+                        })
+                    }
+                    Err(e)
+                        if e.kind() == std::io::ErrorKind::AlreadyExists
+                            && std::time::Instant::now() < deadline =>
+                    {
+                        std::thread::sleep(Duration::from_millis(50))
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
     }
 }
 
-impl Store for FileStore {
+#[cfg(not(unix))]
+impl Drop for SwapGuard {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Writes the `content` into a temp file next to the `path` and syncs it.
+/// The temp file is for the owner only, there are private keys among the objects.
+/// Its name is unique to the call, the threads of a process may write the same object.
+fn temp_file(path: &Path, content: &[u8]) -> std::io::Result<PathBuf> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp = PathBuf::from(temp);
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let written = options
+        .open(&temp)
+        .and_then(|mut file| file.write_all(content).and_then(|_| file.sync_all()));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    Ok(temp)
+}
+
+/// Replaces the file at `path` with the `content` atomically: it's written to a temp file
+/// next to it, synced and renamed over, so a crash leaves either the old or the new one.
+fn replace(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let temp = temp_file(path, content)?;
+    if let Err(e) = fs::rename(&temp, path) {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+    sync_dir(path)
+}
+
+/// Syncs the directory of the `path`, so the renames and links in it are durable.
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        if let Some(dir) = path.parent() {
+            File::open(dir)?.sync_all()?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// A local file cache over a remote store, which stays the source of truth:
+/// reads are served from the cache while it's younger than the `ttl`, writes go to both.
+pub struct TieredStore {
+    cache: FileStore,
+    remote: Box<dyn Store>,
+    ttl: Duration,
+}
+
+impl TieredStore {
+    pub fn init(cache: FileStore, remote: Box<dyn Store>, ttl: Duration) -> Self {
+        TieredStore { cache, remote, ttl }
+    }
+
+    /// A failing cache only costs latency, so the errors are logged and ignored.
+    /// The locks are only good fresh from the remote, they are never cached.
+    fn refresh_cache(&self, kind: ObjectKind, account_name: &str, payload: &[u8]) {
+        if let ObjectKind::Lock = kind {
+            return;
+        }
+        if let Err(e) = self.cache.write(kind, account_name, payload) {
+            log::warn!(
+                r#"{{"op":"store cache write","name":"{}","error":"{}"}}"#,
+                account_name,
+                e
+            );
+        }
+    }
+}
+
+impl Store for TieredStore {
     fn read(&self, kind: ObjectKind, account_name: &str) -> Result<Vec<u8>, StoreError> {
-        let filename = match kind {
-            ObjectKind::Directory => format!("{}/accounts/{}.dir", self.base_dir, account_name),
-            ObjectKind::Account => format!("{}/accounts/{}.acc", self.base_dir, account_name),
-            ObjectKind::KeyPair => format!("{}/accounts/{}.key", self.base_dir, account_name),
-        };
-        let mut file = File::open(filename).map_err(StoreError::File)?;
-        let mut buffer: Vec<u8> = Vec::new();
-        file.read_to_end(&mut buffer).map_err(StoreError::File)?;
-        Ok(buffer)
+        let fresh = matches!(self.cache.age(kind, account_name), Some(age) if age < self.ttl);
+        if fresh {
+            if let Ok(payload) = self.cache.read(kind, account_name) {
+                return Ok(payload);
+            }
+        }
+        let payload = self.remote.read(kind, account_name)?;
+        self.refresh_cache(kind, account_name, &payload);
+        Ok(payload)
     }
 
     fn write(
@@ -130,13 +548,46 @@ impl Store for FileStore {
         account_name: &str,
         payload: &[u8],
     ) -> Result<(), StoreError> {
-        let filename = match kind {
-            ObjectKind::Directory => format!("{}/accounts/{}.dir", self.base_dir, account_name),
-            ObjectKind::Account => format!("{}/accounts/{}.acc", self.base_dir, account_name),
-            ObjectKind::KeyPair => format!("{}/accounts/{}.key", self.base_dir, account_name),
-        };
-        let mut file = File::create(filename).map_err(StoreError::File)?;
-        file.write_all(payload).map_err(StoreError::File)?;
+        self.remote.write(kind, account_name, payload)?;
+        self.refresh_cache(kind, account_name, payload);
         Ok(())
     }
+
+    /// The cache may miss the objects written elsewhere, so the remote is asked.
+    fn list(&self, kind: ObjectKind) -> Result<Vec<String>, StoreError> {
+        self.remote.list(kind)
+    }
+
+    /// A stale cached copy would be served until the `ttl` expires, so it goes too.
+    fn delete(&self, kind: ObjectKind, account_name: &str) -> Result<(), StoreError> {
+        self.remote.delete(kind, account_name)?;
+        self.cache.delete(kind, account_name)
+    }
+
+    fn create(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<bool, StoreError> {
+        let created = self.remote.create(kind, account_name, payload)?;
+        if created {
+            self.refresh_cache(kind, account_name, payload);
+        }
+        Ok(created)
+    }
+
+    fn swap(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        current: &[u8],
+        new: &[u8],
+    ) -> Result<bool, StoreError> {
+        let swapped = self.remote.swap(kind, account_name, current, new)?;
+        if swapped {
+            self.refresh_cache(kind, account_name, new);
+        }
+        Ok(swapped)
+    }
 }
@@ -0,0 +1,93 @@
+//! tarballs of the accounts and the certificates, for `certifika export-all` and
+//! `import-all`, to move an installation to another machine. The files are laid out
+//! the way the file store keeps them, under `certifika/`, so an unencrypted tarball
+//! unpacks into a store dir. With a passphrase the whole tarball is encrypted
+//! (see `encryption`), which `import_all()` tells by itself.
+use super::backup::parse_path;
+use super::{encryption, Layout, ObjectKind, Store, StoreError};
+use std::io::Read;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// the dir the files are under in the tarball
+const PREFIX: &str = "certifika";
+/// authenticated along with the encrypted tarball
+const AAD: &str = "certifika-export";
+
+/// Packs the accounts and the certificates of the `store` into a tarball, encrypted
+/// with the `passphrase` if given. Returns it along with the number of the files in it.
+pub fn export_all(
+    store: &dyn Store,
+    passphrase: Option<&[u8]>,
+) -> Result<(Vec<u8>, usize), StoreError> {
+    let layout = Layout::default();
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut tarball = tar::Builder::new(Vec::new());
+    let mut count = 0;
+    for kind in ObjectKind::ALL {
+        if !matches!(kind.dir_ext().0, "accounts" | "certs") {
+            continue;
+        }
+        for name in store.list(*kind)? {
+            let payload = store.read(*kind, &name)?;
+            let mut header = tar::Header::new_gnu();
+            header.set_size(payload.len() as u64);
+            header.set_mode(0o600);
+            header.set_mtime(mtime);
+            header.set_cksum();
+            tarball
+                .append_data(&mut header, layout.path(*kind, PREFIX, &name), &payload[..])
+                .map_err(StoreError::File)?;
+            count += 1;
+        }
+    }
+    let tarball = tarball.into_inner().map_err(StoreError::File)?;
+    match passphrase {
+        Some(passphrase) => Ok((encryption::seal(passphrase, AAD, &tarball)?, count)),
+        None => Ok((tarball, count)),
+    }
+}
+
+/// Writes the files of the `tarball` of `export_all()` into the `store`, replacing
+/// the objects it has. The `passphrase` is needed for an encrypted one.
+/// Returns the number of the objects imported.
+pub fn import_all(
+    store: &dyn Store,
+    passphrase: Option<&[u8]>,
+    tarball: Vec<u8>,
+) -> Result<usize, StoreError> {
+    let tarball = match (encryption::is_sealed(&tarball), passphrase) {
+        (true, Some(passphrase)) => encryption::open(passphrase, AAD, tarball)?,
+        (true, None) => {
+            return Err(StoreError::Archive(
+                "the tarball is encrypted, the passphrase is not set".to_string(),
+            ))
+        }
+        (false, _) => tarball,
+    };
+    // checked all at once, so a bad tarball leaves the store as it was
+    let mut objects = Vec::new();
+    let mut archive = tar::Archive::new(&tarball[..]);
+    for entry in archive.entries().map_err(StoreError::File)? {
+        let mut entry = entry.map_err(StoreError::File)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry
+            .path()
+            .map_err(StoreError::File)?
+            .to_string_lossy()
+            .into_owned();
+        let (kind, name) = parse_path(PREFIX, &path)
+            .ok_or_else(|| StoreError::Archive(format!("unknown file {}", path)))?;
+        let mut payload = Vec::new();
+        entry.read_to_end(&mut payload).map_err(StoreError::File)?;
+        objects.push((kind, name, payload));
+    }
+    for (kind, name, payload) in &objects {
+        store.write(*kind, name, payload)?;
+    }
+    Ok(objects.len())
+}
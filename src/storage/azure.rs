@@ -0,0 +1,261 @@
+//! the store in the secrets of an [Azure Key Vault](https://learn.microsoft.com/azure/key-vault/secrets/).
+//!
+//! Key Vault authenticates with the client credentials of a service principal when
+//! `AZURE_TENANT_ID`, `AZURE_CLIENT_ID` and `AZURE_CLIENT_SECRET` are set, and with
+//! the managed identity of the host (user-assigned if `AZURE_CLIENT_ID` is set) otherwise.
+//!
+//! Secret names take letters, digits and dashes only, so the other characters of
+//! the object paths are escaped as a dash followed by their hex code,
+//! e.g. `certifika/certs/example.com.crt` is `certifika-2fcerts-2fexample-2ecom-2ecrt`.
+use super::{names_with_suffix, ObjectKind, Store, StoreError};
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const API_VERSION: &str = "7.4";
+const RESOURCE: &str = "https://vault.azure.net";
+/// the Instance Metadata Service, where the managed identities get their tokens
+const IMDS_TOKEN_URL: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+/// the tokens are refreshed this long before they expire
+const TOKEN_MARGIN: Duration = Duration::from_secs(300);
+
+pub struct KeyVaultStore {
+    agent: ureq::Agent,
+    /// e.g. `https://my-vault.vault.azure.net`
+    vault_url: String,
+    prefix: String,
+    /// the access token, and when it has to be refreshed
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl KeyVaultStore {
+    pub fn init(vault_url: &str, prefix: &str) -> Result<Self, StoreError> {
+        Ok(KeyVaultStore {
+            agent: ureq::AgentBuilder::new().build(),
+            vault_url: vault_url.trim_end_matches('/').to_string(),
+            prefix: prefix.to_string(),
+            token: Mutex::new(None),
+        })
+    }
+
+    /// The bearer token for the vault, fetched anew once the cached one is about to expire.
+    fn token(&self) -> Result<String, StoreError> {
+        let mut cached = self.token.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((token, refresh_at)) = &*cached {
+            if Instant::now() < *refresh_at {
+                return Ok(token.to_owned());
+            }
+        }
+        let client_id = env::var("AZURE_CLIENT_ID").ok();
+        let response = match (
+            env::var("AZURE_TENANT_ID"),
+            &client_id,
+            env::var("AZURE_CLIENT_SECRET"),
+        ) {
+            (Ok(tenant), Some(client_id), Ok(secret)) => {
+                let scope = format!("{}/.default", RESOURCE);
+                self.agent
+                    .post(&format!(
+                        "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+                        tenant
+                    ))
+                    .send_form(&[
+                        ("grant_type", "client_credentials"),
+                        ("client_id", client_id.as_str()),
+                        ("client_secret", secret.as_str()),
+                        ("scope", scope.as_str()),
+                    ])
+            }
+            _ => {
+                let mut request = self
+                    .agent
+                    .get(IMDS_TOKEN_URL)
+                    .set("Metadata", "true")
+                    .query("api-version", "2018-02-01")
+                    .query("resource", RESOURCE);
+                if let Some(client_id) = &client_id {
+                    request = request.query("client_id", client_id);
+                }
+                request.call()
+            }
+        };
+        let json: serde_json::Value = response
+            .map_err(StoreError::Azure)?
+            .into_json()
+            .map_err(StoreError::JsonEncode)?;
+        let token = json["access_token"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        // Azure AD has it as a number, IMDS as a string
+        let expires_in = json["expires_in"]
+            .as_u64()
+            .or_else(|| json["expires_in"].as_str().and_then(|s| s.parse().ok()))
+            .unwrap_or_default();
+        let lifetime = Duration::from_secs(expires_in);
+        let refresh_at = Instant::now() + lifetime.checked_sub(TOKEN_MARGIN).unwrap_or_default();
+        *cached = Some((token.to_owned(), refresh_at));
+        Ok(token)
+    }
+
+    fn secret_url(&self, name: &str) -> String {
+        format!("{}/secrets/{}", self.vault_url, name)
+    }
+}
+
+/// Escapes the object `path` into a secret name, see the module docs.
+fn secret_name(path: &str) -> String {
+    let mut name = String::new();
+    for c in path.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                name.push_str(&format!("-{:02x}", b));
+            }
+        }
+    }
+    name
+}
+
+/// The object path of the secret `name`, `None` if it's not one of ours.
+fn object_path(name: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c == '-' {
+            let hex: String = chars.by_ref().take(2).collect();
+            bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+        } else {
+            bytes.push(c as u8);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+impl Store for KeyVaultStore {
+    fn read(&self, kind: ObjectKind, account_name: &str) -> Result<Vec<u8>, StoreError> {
+        let path = kind.path(&self.prefix, account_name)?;
+        let json: serde_json::Value = match self
+            .agent
+            .get(&self.secret_url(&secret_name(&path)))
+            .set("Authorization", &format!("Bearer {}", self.token()?))
+            .query("api-version", API_VERSION)
+            .call()
+        {
+            Ok(response) => response.into_json().map_err(StoreError::JsonEncode)?,
+            Err(ureq::Error::Status(404, _)) => return Err(StoreError::NotFound(path)),
+            Err(e) => return Err(StoreError::Azure(e)),
+        };
+        base64::decode(json["value"].as_str().unwrap_or_default()).map_err(StoreError::Base64Decode)
+    }
+
+    fn write(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<(), StoreError> {
+        let path = kind.path(&self.prefix, account_name)?;
+        self.agent
+            .put(&self.secret_url(&secret_name(&path)))
+            .set("Authorization", &format!("Bearer {}", self.token()?))
+            .query("api-version", API_VERSION)
+            .send_json(ureq::json!({ "value": base64::encode(payload) }))
+            .map_err(StoreError::Azure)?;
+        Ok(())
+    }
+
+    fn list(&self, kind: ObjectKind) -> Result<Vec<String>, StoreError> {
+        let (dir, suffix) = kind.location(&self.prefix);
+        let dir = format!("{}/", dir);
+        let mut paths = Vec::new();
+        let mut next = Some(format!(
+            "{}/secrets?api-version={}",
+            self.vault_url, API_VERSION
+        ));
+        while let Some(url) = next {
+            let json: serde_json::Value = self
+                .agent
+                .get(&url)
+                .set("Authorization", &format!("Bearer {}", self.token()?))
+                .call()
+                .map_err(StoreError::Azure)?
+                .into_json()
+                .map_err(StoreError::JsonEncode)?;
+            for secret in json["value"].as_array().into_iter().flatten() {
+                let name = secret["id"].as_str().and_then(|id| id.rsplit('/').next());
+                if let Some(path) = name.and_then(object_path) {
+                    if let Some(rest) = path.strip_prefix(&dir) {
+                        paths.push(rest.to_string());
+                    }
+                }
+            }
+            next = json["nextLink"].as_str().map(str::to_string);
+        }
+        Ok(names_with_suffix(paths.into_iter(), &suffix))
+    }
+
+    /// With soft-delete (the default of Key Vault) the deleted secret is kept under
+    /// its name, and it couldn't be written again, so it's purged as well. A vault
+    /// with purge protection keeps it for its retention period regardless.
+    fn delete(&self, kind: ObjectKind, account_name: &str) -> Result<(), StoreError> {
+        let name = secret_name(&kind.path(&self.prefix, account_name)?);
+        match self
+            .agent
+            .delete(&self.secret_url(&name))
+            .set("Authorization", &format!("Bearer {}", self.token()?))
+            .query("api-version", API_VERSION)
+            .call()
+        {
+            Ok(_) => (),
+            Err(ureq::Error::Status(404, _)) => return Ok(()),
+            Err(e) => return Err(StoreError::Azure(e)),
+        }
+        let purged = self
+            .agent
+            .delete(&format!("{}/deletedsecrets/{}", self.vault_url, name))
+            .set("Authorization", &format!("Bearer {}", self.token()?))
+            .query("api-version", API_VERSION)
+            .call();
+        if let Err(e) = purged {
+            log::warn!(
+                r#"{{"op":"key vault purge","name":"{}","error":"{}"}}"#,
+                name,
+                e
+            );
+        }
+        Ok(())
+    }
+
+    /// Key Vault has no conditional writes, so the secret is read first
+    /// and another writer may slip in between.
+    fn create(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<bool, StoreError> {
+        match self.read(kind, account_name) {
+            Err(StoreError::NotFound(_)) => self.write(kind, account_name, payload).map(|_| true),
+            Err(e) => Err(e),
+            Ok(_) => Ok(false),
+        }
+    }
+
+    /// Not atomic either, for the same reason as `create()`.
+    fn swap(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        current: &[u8],
+        new: &[u8],
+    ) -> Result<bool, StoreError> {
+        match self.read(kind, account_name) {
+            Ok(payload) if payload == current => self.write(kind, account_name, new).map(|_| true),
+            Ok(_) | Err(StoreError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! snapshots of a whole store, for `certifika backup` and `restore`: every object of
+//! every kind in one JSON document, encrypted with a passphrase (see `encryption`).
+//! A snapshot taken of one backend can be restored into any other, whatever
+//! their layouts: the paths in it are in the default one.
+use super::{check_name, encryption, split_location, Layout, ObjectKind, Store, StoreError};
+use serde::{Deserialize, Serialize};
+
+/// authenticated along with the snapshot, the way the path is with an object
+const AAD: &str = "certifika-backup";
+const VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    objects: Vec<Object>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Object {
+    /// the path of the object relative to the store, e.g. `/accounts/<name>.acc`
+    path: String,
+    /// base64
+    data: String,
+}
+
+/// Takes a snapshot of the `store`, returns it along with the number of the objects in it.
+pub fn snapshot(store: &dyn Store, passphrase: &[u8]) -> Result<(Vec<u8>, usize), StoreError> {
+    let layout = Layout::default();
+    let mut objects = Vec::new();
+    for kind in ObjectKind::ALL {
+        for name in store.list(*kind)? {
+            objects.push(Object {
+                path: layout.path(*kind, "", &name),
+                data: base64::encode(store.read(*kind, &name)?),
+            });
+        }
+    }
+    let count = objects.len();
+    let snapshot = serde_json::to_vec(&Snapshot {
+        version: VERSION,
+        objects,
+    })
+    .map_err(|e| StoreError::JsonEncode(e.into()))?;
+    Ok((encryption::seal(passphrase, AAD, &snapshot)?, count))
+}
+
+/// Writes the objects of the `snapshot` into the `store`, replacing the ones it has.
+/// Returns the number of the objects restored.
+pub fn restore(
+    store: &dyn Store,
+    passphrase: &[u8],
+    snapshot: Vec<u8>,
+) -> Result<usize, StoreError> {
+    if !encryption::is_sealed(&snapshot) {
+        return Err(StoreError::Archive("not a certifika backup".to_string()));
+    }
+    let snapshot: Snapshot = serde_json::from_slice(&encryption::open(passphrase, AAD, snapshot)?)
+        .map_err(|e| StoreError::JsonEncode(e.into()))?;
+    if snapshot.version != VERSION {
+        return Err(StoreError::Archive(format!(
+            "unsupported version {}",
+            snapshot.version
+        )));
+    }
+    // checked all at once, so a bad snapshot leaves the store as it was
+    let mut objects = Vec::new();
+    for object in snapshot.objects {
+        let (kind, name) = parse_path("", &object.path)
+            .ok_or_else(|| StoreError::Archive(format!("unknown object {}", object.path)))?;
+        let data = base64::decode(&object.data).map_err(StoreError::Base64Decode)?;
+        objects.push((kind, name, data));
+    }
+    for (kind, name, data) in &objects {
+        store.write(*kind, name, data)?;
+    }
+    Ok(objects.len())
+}
+
+/// The kind and the name of the object at the `path` under the `prefix`,
+/// in the default layout. The names that would reach out of the store aren't
+/// taken, see `check_name()`.
+pub(super) fn parse_path(prefix: &str, path: &str) -> Option<(ObjectKind, String)> {
+    let layout = Layout::default();
+    ObjectKind::ALL.iter().find_map(|kind| {
+        let (dir, suffix) = split_location(&layout.path(*kind, prefix, ""));
+        let name = path
+            .strip_prefix(&format!("{}/", dir))?
+            .strip_suffix(suffix.as_str())?;
+        check_name(name).ok()?;
+        Some((*kind, name.to_string()))
+    })
+}
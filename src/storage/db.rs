@@ -0,0 +1,97 @@
+//! the store in an embedded [sled](https://docs.rs/sled) database, for the hosts
+//! that would rather keep one file than a tree of them.
+use super::{names_with_suffix, ObjectKind, Store, StoreError};
+
+/// the objects are keyed by their paths under this prefix, the same as in Vault
+const PREFIX: &str = "certifika";
+
+pub struct DbStore {
+    db: sled::Db,
+}
+
+impl DbStore {
+    /// Opens (or creates) the database at `path`.
+    pub fn init(path: &str) -> Result<Self, StoreError> {
+        Ok(DbStore {
+            db: sled::open(path).map_err(StoreError::Db)?,
+        })
+    }
+}
+
+impl Store for DbStore {
+    fn read(&self, kind: ObjectKind, account_name: &str) -> Result<Vec<u8>, StoreError> {
+        let path = kind.path(PREFIX, account_name)?;
+        match self.db.get(&path).map_err(StoreError::Db)? {
+            Some(payload) => Ok(payload.to_vec()),
+            None => Err(StoreError::NotFound(path)),
+        }
+    }
+
+    fn write(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<(), StoreError> {
+        self.db
+            .insert(kind.path(PREFIX, account_name)?, payload)
+            .map_err(StoreError::Db)?;
+        // the keys are worth more than the throughput
+        self.db.flush().map_err(StoreError::Db)?;
+        Ok(())
+    }
+
+    fn list(&self, kind: ObjectKind) -> Result<Vec<String>, StoreError> {
+        let (dir, suffix) = kind.location(PREFIX);
+        let dir = format!("{}/", dir);
+        let mut keys = Vec::new();
+        for entry in self.db.scan_prefix(&dir) {
+            let (key, _) = entry.map_err(StoreError::Db)?;
+            if let Ok(key) = std::str::from_utf8(&key) {
+                keys.push(key[dir.len()..].to_string());
+            }
+        }
+        Ok(names_with_suffix(keys.into_iter(), &suffix))
+    }
+
+    fn delete(&self, kind: ObjectKind, account_name: &str) -> Result<(), StoreError> {
+        self.db
+            .remove(kind.path(PREFIX, account_name)?)
+            .map_err(StoreError::Db)?;
+        self.db.flush().map_err(StoreError::Db)?;
+        Ok(())
+    }
+
+    fn create(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<bool, StoreError> {
+        let swapped = self
+            .db
+            .compare_and_swap(
+                kind.path(PREFIX, account_name)?,
+                None as Option<&[u8]>,
+                Some(payload),
+            )
+            .map_err(StoreError::Db)?;
+        self.db.flush().map_err(StoreError::Db)?;
+        Ok(swapped.is_ok())
+    }
+
+    fn swap(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        current: &[u8],
+        new: &[u8],
+    ) -> Result<bool, StoreError> {
+        let swapped = self
+            .db
+            .compare_and_swap(kind.path(PREFIX, account_name)?, Some(current), Some(new))
+            .map_err(StoreError::Db)?;
+        self.db.flush().map_err(StoreError::Db)?;
+        Ok(swapped.is_ok())
+    }
+}
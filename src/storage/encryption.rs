@@ -0,0 +1,77 @@
+//! encryption at rest for the file store: ChaCha20-Poly1305, with the key derived
+//! from a passphrase (or the content of a key file) with PBKDF2-HMAC-SHA256.
+//!
+//! An encrypted object is the `MAGIC`, the salt, the nonce, then the ciphertext with
+//! its tag. The relative path of the object is authenticated along with it, so the
+//! files can't be swapped (e.g. one account's key for another's) unnoticed.
+//! Objects without the `MAGIC` are taken for the plaintext ones written before
+//! the encryption was turned on.
+use super::StoreError;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::num::NonZeroU32;
+
+const MAGIC: &[u8] = b"certifika-enc-v1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Encrypts the `plaintext` of the object at the relative `path`.
+pub fn seal(passphrase: &[u8], path: &str, plaintext: &[u8]) -> Result<Vec<u8>, StoreError> {
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut salt).map_err(StoreError::Encryption)?;
+    rng.fill(&mut nonce).map_err(StoreError::Encryption)?;
+    let mut sealed = plaintext.to_vec();
+    key(passphrase, &salt)?
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(path.as_bytes()),
+            &mut sealed,
+        )
+        .map_err(StoreError::Encryption)?;
+    Ok([MAGIC, &salt, &nonce, &sealed].concat())
+}
+
+/// Whether the `data` is encrypted, rather than a plaintext object.
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC) && data.len() >= MAGIC.len() + SALT_LEN + NONCE_LEN
+}
+
+/// Decrypts the object at the relative `path`, the plaintext ones are returned as they are.
+pub fn open(passphrase: &[u8], path: &str, data: Vec<u8>) -> Result<Vec<u8>, StoreError> {
+    if !is_sealed(&data) {
+        return Ok(data);
+    }
+    let (salt, rest) = data[MAGIC.len()..].split_at(SALT_LEN);
+    let (nonce, sealed) = rest.split_at(NONCE_LEN);
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    nonce_bytes.copy_from_slice(nonce);
+    let mut sealed = sealed.to_vec();
+    let plaintext = key(passphrase, salt)?
+        .open_in_place(
+            Nonce::assume_unique_for_key(nonce_bytes),
+            Aad::from(path.as_bytes()),
+            &mut sealed,
+        )
+        .map_err(StoreError::Encryption)?;
+    Ok(plaintext.to_vec())
+}
+
+fn key(passphrase: &[u8], salt: &[u8]) -> Result<LessSafeKey, StoreError> {
+    let mut key = [0u8; 32];
+    // a non-zero constant
+    let iterations = NonZeroU32::new(PBKDF2_ITERATIONS).unwrap();
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        salt,
+        passphrase,
+        &mut key,
+    );
+    Ok(LessSafeKey::new(
+        UnboundKey::new(&aead::CHACHA20_POLY1305, &key).map_err(StoreError::Encryption)?,
+    ))
+}
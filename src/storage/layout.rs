@@ -0,0 +1,105 @@
+//! the layout of the object paths (the keys of the KV stores), from a template:
+//! `{prefix}/{dir}/{name}.{ext}` by default, e.g. `certifika/accounts/<email>.key`.
+//! `{dir}` is `accounts`, `certs`, `directories` or `locks`, and `{ext}` tells apart
+//! the kinds of objects in a dir. E.g. with `{prefix}/staging/{dir}/{name}.{ext}`
+//! the staging objects stay apart from the production ones sharing the store.
+//! The objects written with another layout are moved over with `migrate()`.
+use super::{ObjectKind, Store, StoreError};
+use once_cell::sync::OnceCell;
+use std::cell::RefCell;
+
+pub const DEFAULT: &str = "{prefix}/{dir}/{name}.{ext}";
+
+static LAYOUT: OnceCell<Layout> = OnceCell::new();
+
+thread_local! {
+    /// the layout `with_layout()` runs with, instead of the configured one
+    static OVERRIDE: RefCell<Option<Layout>> = RefCell::new(None);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    template: String,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            template: DEFAULT.to_string(),
+        }
+    }
+}
+
+impl Layout {
+    /// The stores list the objects of a kind by their dir and what follows the name,
+    /// so the `template` must have `{dir}` and `{ext}`, and `{name}` must start its
+    /// last segment.
+    pub fn parse(template: &str) -> Result<Self, StoreError> {
+        let last = template.rsplit('/').next().unwrap_or_default();
+        let valid = template.contains("{dir}")
+            && template.contains("{ext}")
+            && template.matches("{name}").count() == 1
+            && last.starts_with("{name}");
+        if !valid {
+            return Err(StoreError::Layout(template.to_string()));
+        }
+        Ok(Layout {
+            template: template.to_string(),
+        })
+    }
+
+    pub fn path(&self, kind: ObjectKind, prefix: &str, name: &str) -> String {
+        let (dir, ext) = kind.dir_ext();
+        // the name last, whatever it has in it stays as it is
+        self.template
+            .replace("{prefix}", prefix)
+            .replace("{dir}", dir)
+            .replace("{ext}", ext)
+            .replace("{name}", name)
+    }
+}
+
+/// Sets the layout of the paths, before any store is used. Only the first call counts.
+pub fn set(layout: Layout) {
+    let _ = LAYOUT.set(layout);
+}
+
+/// Runs `f` with the current layout.
+pub fn current<T>(f: impl FnOnce(&Layout) -> T) -> T {
+    OVERRIDE.with(|layout| match &*layout.borrow() {
+        Some(layout) => f(layout),
+        None => f(LAYOUT.get_or_init(Layout::default)),
+    })
+}
+
+/// Runs `f` with the stores using the `layout` on this thread.
+pub(super) fn with_layout<T>(layout: &Layout, f: impl FnOnce() -> T) -> T {
+    let previous = OVERRIDE.with(|o| o.replace(Some(layout.clone())));
+    let result = f();
+    OVERRIDE.with(|o| *o.borrow_mut() = previous);
+    result
+}
+
+/// Moves the objects of the `store` written with the layout `from` over to the current
+/// one, returns how many were moved. Each is written anew before the old one is removed,
+/// so an interrupted migration can be run again.
+pub fn migrate(store: &dyn Store, from: &Layout) -> Result<usize, StoreError> {
+    let mut moved = 0;
+    for kind in ObjectKind::ALL {
+        for name in with_layout(from, || store.list(*kind))? {
+            if from.path(*kind, "", &name) == kind.path("", &name)? {
+                continue;
+            }
+            let payload = with_layout(from, || store.read(*kind, &name))?;
+            store.write(*kind, &name, &payload)?;
+            with_layout(from, || store.delete(*kind, &name))?;
+            log::info!(
+                r#"{{"op":"store layout migrate","name":"{}","path":"{}"}}"#,
+                name,
+                kind.path("", &name)?
+            );
+            moved += 1;
+        }
+    }
+    Ok(moved)
+}
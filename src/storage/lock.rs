@@ -0,0 +1,210 @@
+//! advisory locking of the whole store, so a renewal started by cron and one started
+//! by hand (or on another host sharing the store) don't interleave their writes of
+//! the accounts and certificates. The lock is an object naming its holder and when
+//! it expires, created only if there is none (see `Store::create`). From then on it's
+//! only ever swapped for another one (see `Store::swap`): the holder renews it while
+//! it waits (see `renew()`) and releases it with a lock that has expired already,
+//! and the lock left by a process that died holding it is taken over once it has
+//! expired. Whoever swaps the lock they read loses to whoever swapped it first.
+use super::{ObjectKind, Store, StoreError};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// the name of the lock object
+const NAME: &str = "store";
+/// how long a lock is good for unless it's renewed, well over what a renewal run
+/// takes between its waits
+const LEASE: Duration = Duration::from_secs(3600);
+/// how often a lock held by another process is checked again
+const POLL: Duration = Duration::from_secs(2);
+
+thread_local! {
+    /// the lock this thread holds, see `renew()`
+    static HELD: RefCell<Option<Held>> = RefCell::new(None);
+}
+
+#[derive(Serialize, Deserialize)]
+struct Holder {
+    owner: String,
+    /// unix time
+    expires: u64,
+}
+
+/// The lock as this thread holds it.
+struct Held {
+    owner: String,
+    payload: Vec<u8>,
+    /// half of the lease
+    renew_at: Instant,
+    /// taken over by another process, see `renew()`
+    lost: bool,
+}
+
+impl Held {
+    /// A lock of the `owner` that is good for the `LEASE`.
+    fn new(owner: String) -> Result<Self, StoreError> {
+        let holder = Holder {
+            owner: owner.clone(),
+            expires: now() + LEASE.as_secs(),
+        };
+        Ok(Held {
+            payload: serde_json::to_vec(&holder).map_err(|e| StoreError::JsonEncode(e.into()))?,
+            owner,
+            renew_at: Instant::now() + LEASE / 2,
+            lost: false,
+        })
+    }
+}
+
+/// The lock of a store, released when dropped.
+pub struct StoreLock<'s> {
+    store: &'s dyn Store,
+}
+
+impl<'s> StoreLock<'s> {
+    fn held(store: &'s dyn Store, held: Held) -> Self {
+        HELD.with(|current| *current.borrow_mut() = Some(held));
+        StoreLock { store }
+    }
+}
+
+/// Takes the lock of the `store`, waiting for up to `wait` while another process holds it.
+pub fn lock(store: &dyn Store, wait: Duration) -> Result<StoreLock<'_>, StoreError> {
+    let owner = owner();
+    let deadline = Instant::now() + wait;
+    loop {
+        let held = Held::new(owner.clone())?;
+        if store.create(ObjectKind::Lock, NAME, &held.payload)? {
+            return Ok(StoreLock::held(store, held));
+        }
+        let (payload, holder) = match holder_of(store)? {
+            Some(holder) => holder,
+            // removed in the meantime
+            None => continue,
+        };
+        if holder.expires <= now() {
+            if !holder.owner.is_empty() {
+                log::warn!(
+                    r#"{{"op":"store lock expired","owner":"{}"}}"#,
+                    holder.owner
+                );
+            }
+            if store.swap(ObjectKind::Lock, NAME, &payload, &held.payload)? {
+                return Ok(StoreLock::held(store, held));
+            }
+            // another process was quicker
+            continue;
+        }
+        if Instant::now() >= deadline {
+            return Err(StoreError::Locked(holder.owner));
+        }
+        log::info!(r#"{{"op":"store lock wait","owner":"{}"}}"#, holder.owner);
+        thread::sleep(POLL);
+    }
+}
+
+/// Renews the lock this thread holds on the `store` once half of its lease has passed,
+/// to be called by the long waits of a locked run. A lock that has been taken over
+/// in the meantime is lost, and the run has to stop writing to the store: that's
+/// the error, returned on every call from then on. A store that fails is tried
+/// again on the next call.
+pub fn renew(store: &dyn Store) -> Result<(), StoreError> {
+    HELD.with(|current| {
+        let mut current = current.borrow_mut();
+        let mut held = match current.take() {
+            Some(held) if held.lost => {
+                let owner = held.owner.clone();
+                *current = Some(held);
+                return Err(StoreError::LockLost(owner));
+            }
+            Some(held) if held.renew_at <= Instant::now() => held,
+            held => {
+                *current = held;
+                return Ok(());
+            }
+        };
+        let renewed = Held::new(held.owner.clone()).and_then(|renewed| {
+            store
+                .swap(ObjectKind::Lock, NAME, &held.payload, &renewed.payload)
+                .map(|swapped| (swapped, renewed))
+        });
+        match renewed {
+            Ok((true, renewed)) => *current = Some(renewed),
+            Ok((false, _)) => {
+                log::error!(r#"{{"op":"store lock lost","owner":"{}"}}"#, held.owner);
+                let owner = held.owner.clone();
+                held.lost = true;
+                *current = Some(held);
+                return Err(StoreError::LockLost(owner));
+            }
+            Err(e) => {
+                log::warn!(r#"{{"op":"store lock renewal","error":"{}"}}"#, e);
+                *current = Some(held);
+            }
+        }
+        Ok(())
+    })
+}
+
+impl Drop for StoreLock<'_> {
+    /// The lock is released if it's still ours, it may have been taken over.
+    fn drop(&mut self) {
+        let held = match HELD.with(|current| current.borrow_mut().take()) {
+            Some(held) if !held.lost => held,
+            _ => return,
+        };
+        let released = Holder {
+            owner: String::new(),
+            expires: 0,
+        };
+        let released = serde_json::to_vec(&released)
+            .map_err(|e| StoreError::JsonEncode(e.into()))
+            .and_then(|released| {
+                self.store
+                    .swap(ObjectKind::Lock, NAME, &held.payload, &released)
+            });
+        if let Err(e) = released {
+            log::warn!(r#"{{"op":"store lock release","error":"{}"}}"#, e);
+        }
+    }
+}
+
+/// The current holder of the lock, along with the lock as it's stored, `None` if
+/// there is none. A lock that can't be made sense of is taken for an expired one.
+fn holder_of(store: &dyn Store) -> Result<Option<(Vec<u8>, Holder)>, StoreError> {
+    match store.read(ObjectKind::Lock, NAME) {
+        Ok(payload) => {
+            let holder = serde_json::from_slice(&payload).unwrap_or(Holder {
+                owner: "unknown".to_string(),
+                expires: 0,
+            });
+            Ok(Some((payload, holder)))
+        }
+        Err(StoreError::NotFound(_)) => Ok(None),
+        Err(StoreError::File(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Names this process, for the others to report who holds the lock.
+fn owner() -> String {
+    let host = std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .unwrap_or_else(|| "localhost".to_string());
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("pid {} on {} ({})", std::process::id(), host, started)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
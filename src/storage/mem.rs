@@ -0,0 +1,91 @@
+//! the store in memory, gone with the process: for the tests, and for the one-off
+//! runs that print the certificate and keep nothing (`CERTIFIKA_STORE_TYPE=memory`).
+use super::{names_with_suffix, ObjectKind, Store, StoreError};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+#[derive(Default)]
+pub struct MemStore {
+    /// the objects by their paths
+    objects: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemStore {
+    pub fn new() -> Self {
+        MemStore::default()
+    }
+
+    /// A panic elsewhere leaves the map as consistent as it was, so the poisoning is ignored.
+    fn objects(&self) -> MutexGuard<'_, HashMap<String, Vec<u8>>> {
+        self.objects.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Store for MemStore {
+    fn read(&self, kind: ObjectKind, account_name: &str) -> Result<Vec<u8>, StoreError> {
+        let path = kind.path("", account_name)?;
+        match self.objects().get(&path) {
+            Some(payload) => Ok(payload.clone()),
+            None => Err(StoreError::NotFound(path)),
+        }
+    }
+
+    fn write(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<(), StoreError> {
+        self.objects()
+            .insert(kind.path("", account_name)?, payload.to_vec());
+        Ok(())
+    }
+
+    fn list(&self, kind: ObjectKind) -> Result<Vec<String>, StoreError> {
+        let (dir, suffix) = kind.location("");
+        let dir = format!("{}/", dir);
+        let names: Vec<String> = self
+            .objects()
+            .keys()
+            .filter_map(|path| path.strip_prefix(&dir))
+            .map(str::to_string)
+            .collect();
+        Ok(names_with_suffix(names.into_iter(), &suffix))
+    }
+
+    fn delete(&self, kind: ObjectKind, account_name: &str) -> Result<(), StoreError> {
+        self.objects().remove(&kind.path("", account_name)?);
+        Ok(())
+    }
+
+    fn create(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<bool, StoreError> {
+        let mut objects = self.objects();
+        let path = kind.path("", account_name)?;
+        if objects.contains_key(&path) {
+            return Ok(false);
+        }
+        objects.insert(path, payload.to_vec());
+        Ok(true)
+    }
+
+    fn swap(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        current: &[u8],
+        new: &[u8],
+    ) -> Result<bool, StoreError> {
+        match self.objects().get_mut(&kind.path("", account_name)?) {
+            Some(payload) if payload.as_slice() == current => {
+                *payload = new.to_vec();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
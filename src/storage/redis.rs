@@ -0,0 +1,126 @@
+//! the store in [Redis](https://redis.io), for the containers that keep no state
+//! of their own.
+use super::{names_with_suffix, ObjectKind, Store, StoreError};
+use redis::Commands;
+use std::time::Duration;
+
+/// replaces the value of the key with the second argument if it's still the first one,
+/// the third one is the TTL to set, if any
+const SWAP: &str = r"
+if redis.call('GET', KEYS[1]) ~= ARGV[1] then
+    return 0
+end
+if ARGV[3] then
+    redis.call('SET', KEYS[1], ARGV[2], 'EX', ARGV[3])
+else
+    redis.call('SET', KEYS[1], ARGV[2])
+end
+return 1
+";
+
+pub struct RedisStore {
+    client: redis::Client,
+    /// the keys are the paths of the objects under it
+    prefix: String,
+    /// how long the transient objects live, see `ObjectKind::is_transient()`;
+    /// forever if `None`
+    ttl: Option<Duration>,
+}
+
+impl RedisStore {
+    /// Connects to the server at `url`, e.g. `redis://127.0.0.1/`.
+    pub fn init(url: &str, prefix: &str, ttl: Option<Duration>) -> Result<Self, StoreError> {
+        Ok(RedisStore {
+            client: redis::Client::open(url).map_err(StoreError::Redis)?,
+            prefix: prefix.to_string(),
+            ttl,
+        })
+    }
+
+    fn connection(&self) -> Result<redis::Connection, StoreError> {
+        self.client.get_connection().map_err(StoreError::Redis)
+    }
+}
+
+impl Store for RedisStore {
+    fn read(&self, kind: ObjectKind, account_name: &str) -> Result<Vec<u8>, StoreError> {
+        let key = kind.path(&self.prefix, account_name)?;
+        let payload: Option<Vec<u8>> = self.connection()?.get(&key).map_err(StoreError::Redis)?;
+        payload.ok_or(StoreError::NotFound(key))
+    }
+
+    fn write(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<(), StoreError> {
+        let key = kind.path(&self.prefix, account_name)?;
+        let mut connection = self.connection()?;
+        match self.ttl {
+            Some(ttl) if kind.is_transient() => {
+                connection.set_ex(&key, payload, ttl.as_secs() as usize)
+            }
+            _ => connection.set(&key, payload),
+        }
+        .map_err(StoreError::Redis)
+    }
+
+    fn list(&self, kind: ObjectKind) -> Result<Vec<String>, StoreError> {
+        let (dir, suffix) = kind.location(&self.prefix);
+        let mut connection = self.connection()?;
+        let keys: Vec<String> = connection
+            .scan_match(format!("{}/*", dir))
+            .map_err(StoreError::Redis)?
+            .collect();
+        let names = keys.into_iter().map(|key| key[dir.len() + 1..].to_string());
+        Ok(names_with_suffix(names, &suffix))
+    }
+
+    fn delete(&self, kind: ObjectKind, account_name: &str) -> Result<(), StoreError> {
+        self.connection()?
+            .del(kind.path(&self.prefix, account_name)?)
+            .map_err(StoreError::Redis)
+    }
+
+    fn create(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<bool, StoreError> {
+        let key = kind.path(&self.prefix, account_name)?;
+        let mut connection = self.connection()?;
+        let mut set = redis::cmd("SET");
+        set.arg(&key).arg(payload).arg("NX");
+        if let Some(ttl) = self.ttl {
+            if kind.is_transient() {
+                set.arg("EX").arg(ttl.as_secs());
+            }
+        }
+        let reply: Option<String> = set.query(&mut connection).map_err(StoreError::Redis)?;
+        Ok(reply.is_some())
+    }
+
+    /// Compared and set in a script, which Redis runs atomically.
+    fn swap(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        current: &[u8],
+        new: &[u8],
+    ) -> Result<bool, StoreError> {
+        let script = redis::Script::new(SWAP);
+        let mut swap = script.key(kind.path(&self.prefix, account_name)?);
+        swap.arg(current).arg(new);
+        if let Some(ttl) = self.ttl {
+            if kind.is_transient() {
+                swap.arg(ttl.as_secs());
+            }
+        }
+        let swapped: i64 = swap
+            .invoke(&mut self.connection()?)
+            .map_err(StoreError::Redis)?;
+        Ok(swapped == 1)
+    }
+}
@@ -0,0 +1,237 @@
+//! the behaviour every store shares, checked on the memory store, and what's
+//! particular to the file store: encryption and the atomic create and swap.
+use super::*;
+use std::time::Duration;
+
+const NAME: &str = "test@example.com";
+
+#[test]
+fn mem_store_round_trip() {
+    let store = MemStore::new();
+    assert!(store.read(ObjectKind::KeyPair, NAME).is_err());
+    store.write(ObjectKind::KeyPair, NAME, b"key").unwrap();
+    store.write(ObjectKind::Account, NAME, b"account").unwrap();
+    store
+        .write(ObjectKind::CertKey, "example.com", b"cert key")
+        .unwrap();
+    assert_eq!(store.read(ObjectKind::KeyPair, NAME).unwrap(), b"key");
+    assert_eq!(store.list(ObjectKind::KeyPair).unwrap(), vec![NAME]);
+    assert_eq!(
+        store.list(ObjectKind::CertKey).unwrap(),
+        vec!["example.com"]
+    );
+    store.delete(ObjectKind::KeyPair, NAME).unwrap();
+    store.delete(ObjectKind::KeyPair, NAME).unwrap();
+    assert!(store.list(ObjectKind::KeyPair).unwrap().is_empty());
+    assert!(store.create(ObjectKind::KeyPair, NAME, b"new").unwrap());
+    assert!(!store.create(ObjectKind::KeyPair, NAME, b"newer").unwrap());
+    assert_eq!(store.read(ObjectKind::KeyPair, NAME).unwrap(), b"new");
+    assert!(!store
+        .swap(ObjectKind::KeyPair, NAME, b"old", b"newer")
+        .unwrap());
+    assert!(store
+        .swap(ObjectKind::KeyPair, NAME, b"new", b"newer")
+        .unwrap());
+    assert_eq!(store.read(ObjectKind::KeyPair, NAME).unwrap(), b"newer");
+    assert!(!store.swap(ObjectKind::Account, NAME, b"", b"new").unwrap());
+}
+
+#[test]
+fn backup_restores_into_another_store() {
+    let source = MemStore::new();
+    source.write(ObjectKind::KeyPair, NAME, b"key").unwrap();
+    source
+        .write(ObjectKind::Certificate, "example.com", b"chain")
+        .unwrap();
+    let (archive, count) = snapshot(&source, b"passphrase").unwrap();
+    assert_eq!(count, 2);
+    let target = MemStore::new();
+    assert!(restore(&target, b"wrong", archive.clone()).is_err());
+    assert!(target.list(ObjectKind::KeyPair).unwrap().is_empty());
+    assert_eq!(restore(&target, b"passphrase", archive).unwrap(), 2);
+    assert_eq!(target.read(ObjectKind::KeyPair, NAME).unwrap(), b"key");
+    assert_eq!(
+        target.read(ObjectKind::Certificate, "example.com").unwrap(),
+        b"chain"
+    );
+}
+
+#[test]
+fn lock_is_exclusive_until_dropped() {
+    let store = MemStore::new();
+    let held = lock(&store, Duration::from_secs(0)).unwrap();
+    assert!(matches!(
+        lock(&store, Duration::from_secs(0)),
+        Err(StoreError::Locked(_))
+    ));
+    drop(held);
+    assert!(lock(&store, Duration::from_secs(0)).is_ok());
+}
+
+#[test]
+fn expired_lock_is_taken_over() {
+    let store = MemStore::new();
+    let expired = br#"{"owner":"pid 1 on elsewhere","expires":1}"#;
+    store.write(ObjectKind::Lock, "store", expired).unwrap();
+    let held = lock(&store, Duration::from_secs(0)).unwrap();
+    let taken = store.read(ObjectKind::Lock, "store").unwrap();
+    assert_ne!(taken, expired.to_vec());
+    // the expired holder comes back, too late to release it
+    assert!(!store
+        .swap(ObjectKind::Lock, "store", expired, b"{}")
+        .unwrap());
+    drop(held);
+    assert_ne!(store.read(ObjectKind::Lock, "store").unwrap(), taken);
+}
+
+#[test]
+fn file_store_encrypts_the_objects() {
+    let dir = std::env::temp_dir().join(format!("certifika-store-{}", std::process::id()));
+    let store = FileStore::init(dir.to_str().unwrap())
+        .unwrap()
+        .encrypted(b"passphrase".to_vec());
+    store
+        .write(ObjectKind::KeyPair, NAME, b"secret key")
+        .unwrap();
+    let raw = fs::read(
+        ObjectKind::KeyPair
+            .path(dir.to_str().unwrap(), NAME)
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(!raw.windows(6).any(|w| w == b"secret"));
+    assert_eq!(
+        store.read(ObjectKind::KeyPair, NAME).unwrap(),
+        b"secret key"
+    );
+    assert!(!store.create(ObjectKind::KeyPair, NAME, b"another").unwrap());
+    assert!(!store
+        .swap(ObjectKind::KeyPair, NAME, b"another", b"newer")
+        .unwrap());
+    assert!(store
+        .swap(ObjectKind::KeyPair, NAME, b"secret key", b"newer")
+        .unwrap());
+    assert_eq!(store.read(ObjectKind::KeyPair, NAME).unwrap(), b"newer");
+    assert!(!store
+        .swap(ObjectKind::Account, NAME, b"secret key", b"newer")
+        .unwrap());
+    let plain = FileStore::init(dir.to_str().unwrap()).unwrap();
+    assert_ne!(
+        plain.read(ObjectKind::KeyPair, NAME).unwrap(),
+        b"secret key"
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn file_store_swap_never_frees_the_path() {
+    let dir = std::env::temp_dir().join(format!("certifika-swap-{}", std::process::id()));
+    let store = std::sync::Arc::new(FileStore::init(dir.to_str().unwrap()).unwrap());
+    store.write(ObjectKind::Lock, "store", b"0").unwrap();
+    let racing: Vec<_> = (1..=8u8)
+        .map(|i| {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                let swapped = store.swap(ObjectKind::Lock, "store", b"0", &[b'0' + i]);
+                let created = store.create(ObjectKind::Lock, "store", b"x");
+                (swapped.unwrap(), created.unwrap())
+            })
+        })
+        .collect();
+    let outcomes: Vec<(bool, bool)> = racing.into_iter().map(|t| t.join().unwrap()).collect();
+    assert_eq!(outcomes.iter().filter(|(swapped, _)| *swapped).count(), 1);
+    assert!(outcomes.iter().all(|(_, created)| !created));
+    assert_ne!(store.read(ObjectKind::Lock, "store").unwrap(), b"0");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn layout_migrates_the_objects() {
+    assert!(Layout::parse("{prefix}/{name}.{ext}").is_err());
+    assert!(Layout::parse("{prefix}/{name}/{dir}.{ext}").is_err());
+    let old = Layout::parse("{prefix}/old/{dir}/{name}.{ext}").unwrap();
+    let store = MemStore::new();
+    layout::with_layout(&old, || {
+        store.write(ObjectKind::KeyPair, NAME, b"key").unwrap();
+        store
+            .write(ObjectKind::Lineage, "example.com", b"{}")
+            .unwrap();
+    });
+    assert!(store.read(ObjectKind::KeyPair, NAME).is_err());
+    assert_eq!(migrate(&store, &old).unwrap(), 2);
+    assert_eq!(store.read(ObjectKind::KeyPair, NAME).unwrap(), b"key");
+    assert_eq!(
+        store.list(ObjectKind::Lineage).unwrap(),
+        vec!["example.com"]
+    );
+    assert!(
+        layout::with_layout(&old, || store.list(ObjectKind::KeyPair))
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(migrate(&store, &old).unwrap(), 0);
+}
+
+#[test]
+fn export_all_takes_the_accounts_and_certs() {
+    let source = MemStore::new();
+    source.write(ObjectKind::KeyPair, NAME, b"key").unwrap();
+    source
+        .write(ObjectKind::CertKey, "example.com", b"cert key")
+        .unwrap();
+    source.write(ObjectKind::Capabilities, "ca", b"{}").unwrap();
+    let (tarball, count) = export_all(&source, Some(b"passphrase")).unwrap();
+    assert_eq!(count, 2);
+    let target = MemStore::new();
+    assert!(import_all(&target, None, tarball.clone()).is_err());
+    assert_eq!(
+        import_all(&target, Some(b"passphrase"), tarball).unwrap(),
+        2
+    );
+    assert_eq!(
+        target.read(ObjectKind::CertKey, "example.com").unwrap(),
+        b"cert key"
+    );
+    assert!(target.list(ObjectKind::Capabilities).unwrap().is_empty());
+    let (plain, _) = export_all(&source, None).unwrap();
+    assert_eq!(import_all(&MemStore::new(), None, plain).unwrap(), 2);
+}
+
+#[test]
+fn import_all_rejects_names_out_of_the_store() {
+    let mut tarball = tar::Builder::new(Vec::new());
+    for path in &[
+        "certifika/certs/example.com.crt",
+        "certifika/certs/../../../x.crt",
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o600);
+        // `set_path()` refuses `..`, the crafted tarballs don't
+        header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_cksum();
+        tarball.append(&header, &b"chain"[..]).unwrap();
+    }
+    let tarball = tarball.into_inner().unwrap();
+    let target = MemStore::new();
+    assert!(import_all(&target, None, tarball).is_err());
+    assert!(target.list(ObjectKind::Certificate).unwrap().is_empty());
+}
+
+#[test]
+fn stores_reject_names_out_of_their_dirs() {
+    let dir = std::env::temp_dir().join(format!("certifika-names-{}", std::process::id()));
+    let file = FileStore::init(dir.to_str().unwrap()).unwrap();
+    let stores: [&dyn Store; 2] = [&MemStore::new(), &file];
+    for store in stores.iter() {
+        for name in &["", ".", "..", "../x", "a/b", "a\\b"] {
+            assert!(matches!(
+                store.write(ObjectKind::Certificate, name, b"chain"),
+                Err(StoreError::Name(_))
+            ));
+            assert!(store.read(ObjectKind::Certificate, name).is_err());
+        }
+    }
+    assert!(!dir.join("x.crt").exists());
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,529 @@
+//! the store in [HashiCorp Vault](https://www.vaultproject.io), in a KV secrets engine
+//! of either version. The objects are base64 encoded, in the `value` field of the secrets.
+//!
+//! Vault is reached at `VAULT_ADDR`, in the `VAULT_NAMESPACE` of Vault Enterprise
+//! if it's set. The store either uses the `VAULT_TOKEN` it's given, or logs in itself,
+//! see `VaultAuth`.
+use super::{names_with_suffix, ObjectKind, Store, StoreError};
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// tokens with less time left are not renewed anymore, but replaced by logging in again
+const MIN_TOKEN_TTL: u64 = 60;
+
+/// Where the secrets are kept in Vault.
+pub struct VaultOptions {
+    /// the mount point of the KV secrets engine
+    pub mount: String,
+    /// the objects are kept under it
+    pub prefix: String,
+    /// the version of the KV engine, 1 or 2, detected from `sys/mounts` if `None`
+    pub kv_version: Option<u8>,
+    /// the Vault Enterprise namespace
+    pub namespace: Option<String>,
+    pub auth: VaultAuth,
+}
+
+/// How the store gets its Vault token.
+pub enum VaultAuth {
+    /// a token issued beforehand, `VAULT_TOKEN`
+    Token,
+    /// logs in with [AppRole](https://developer.hashicorp.com/vault/docs/auth/approle),
+    /// the auth method enabled at `mount`
+    AppRole {
+        mount: String,
+        role_id: String,
+        secret_id: String,
+    },
+    /// logs in with [Kubernetes](https://developer.hashicorp.com/vault/docs/auth/kubernetes)
+    /// as the `role`, with the service account token at `token_path`
+    Kubernetes {
+        mount: String,
+        role: String,
+        token_path: String,
+    },
+}
+
+impl VaultAuth {
+    /// The auth method of the settings `setting` looks up by their env var names:
+    /// AppRole with `VAULT_ROLE_ID` and `VAULT_SECRET_ID`, Kubernetes with
+    /// `CERTIFIKA_VAULT_KUBERNETES_ROLE`, the `VAULT_TOKEN` otherwise.
+    pub fn from_settings(setting: impl Fn(&str) -> Option<String>) -> Self {
+        match (
+            setting("VAULT_ROLE_ID"),
+            setting("VAULT_SECRET_ID"),
+            setting("CERTIFIKA_VAULT_KUBERNETES_ROLE"),
+        ) {
+            (Some(role_id), Some(secret_id), _) => VaultAuth::AppRole {
+                mount: setting("CERTIFIKA_VAULT_APPROLE_MOUNT")
+                    .unwrap_or_else(|| "approle".to_string()),
+                role_id,
+                secret_id,
+            },
+            (_, _, Some(role)) => VaultAuth::Kubernetes {
+                mount: setting("CERTIFIKA_VAULT_KUBERNETES_MOUNT")
+                    .unwrap_or_else(|| "kubernetes".to_string()),
+                role,
+                token_path: setting("CERTIFIKA_VAULT_KUBERNETES_TOKEN_PATH").unwrap_or_else(|| {
+                    "/var/run/secrets/kubernetes.io/serviceaccount/token".to_string()
+                }),
+            },
+            _ => VaultAuth::Token,
+        }
+    }
+}
+
+impl Default for VaultOptions {
+    fn default() -> Self {
+        VaultOptions {
+            mount: "secret".to_string(),
+            prefix: "certifika".to_string(),
+            kv_version: None,
+            namespace: None,
+            auth: VaultAuth::Token,
+        }
+    }
+}
+
+/// A Vault token, and when it's due for renewal.
+struct Token {
+    value: String,
+    /// `None` for the tokens that never expire
+    renew_at: Option<Instant>,
+    renewable: bool,
+}
+
+impl Token {
+    /// The token valid for the `ttl` seconds (0 is forever), it's renewed once two thirds
+    /// of the TTL are over.
+    fn new(value: String, ttl: u64, renewable: bool) -> Self {
+        Token {
+            value,
+            renew_at: match ttl {
+                0 => None,
+                ttl => Some(Instant::now() + Duration::from_secs(ttl * 2 / 3)),
+            },
+            renewable,
+        }
+    }
+
+    /// The token of the `auth` section of a login or renewal response.
+    fn from_auth(auth: &serde_json::Value) -> Option<Self> {
+        Some(Token::new(
+            auth["client_token"].as_str()?.to_string(),
+            auth["lease_duration"].as_u64().unwrap_or_default(),
+            auth["renewable"].as_bool().unwrap_or_default(),
+        ))
+    }
+}
+
+/// A Vault API client, authenticated the way the `VaultAuth` says. The transit
+/// account keys (see `acme::transit`) share it with the store.
+pub struct VaultClient {
+    addr: String,
+    /// renewed, or replaced by logging in again, when it's about to expire
+    token: Mutex<Token>,
+    namespace: Option<String>,
+    auth: VaultAuth,
+}
+
+impl VaultClient {
+    /// Connects to the Vault at `VAULT_ADDR` and logs in.
+    pub fn init(namespace: Option<String>, auth: VaultAuth) -> Result<Self, StoreError> {
+        let mut client = VaultClient {
+            addr: env::var("VAULT_ADDR").map_err(StoreError::Init)?,
+            token: Mutex::new(Token::new(String::new(), 0, false)),
+            namespace,
+            auth,
+        };
+        client.token = Mutex::new(client.login()?);
+        Ok(client)
+    }
+
+    /// The URL of the API `path`, e.g. `sys/mounts`.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/v1/{}", &self.addr, path)
+    }
+
+    /// Gets a token the way the `auth` says.
+    fn login(&self) -> Result<Token, StoreError> {
+        let (mount, payload) = match &self.auth {
+            VaultAuth::Token => return self.given_token(),
+            VaultAuth::AppRole {
+                mount,
+                role_id,
+                secret_id,
+            } => (
+                mount,
+                ureq::json!({ "role_id": role_id, "secret_id": secret_id }),
+            ),
+            VaultAuth::Kubernetes {
+                mount,
+                role,
+                token_path,
+            } => {
+                // read anew for every login, the kubelet rotates the projected tokens
+                let jwt = std::fs::read_to_string(token_path).map_err(StoreError::File)?;
+                (mount, ureq::json!({ "role": role, "jwt": jwt.trim() }))
+            }
+        };
+        let url = self.url(&format!("auth/{}/login", mount.trim_matches('/')));
+        let json: serde_json::Value = self
+            .unauthenticated("POST", &url)
+            .send_json(payload)
+            .map_err(StoreError::Vault)?
+            .into_json()
+            .map_err(StoreError::JsonEncode)?;
+        match Token::from_auth(&json["auth"]) {
+            Some(token) => {
+                log::info!(r#"{{"op":"vault login","mount":"{}"}}"#, mount);
+                Ok(token)
+            }
+            None => Err(StoreError::VaultLogin(mount.to_owned())),
+        }
+    }
+
+    /// The `VAULT_TOKEN`, with its TTL looked up. A token that can't look itself up
+    /// is taken for one that never expires.
+    fn given_token(&self) -> Result<Token, StoreError> {
+        let value = env::var("VAULT_TOKEN").map_err(StoreError::Init)?;
+        let url = self.url("auth/token/lookup-self");
+        let lookup = self
+            .unauthenticated("GET", &url)
+            .set("X-Vault-Token", &value)
+            .call()
+            .map_err(StoreError::Vault)
+            .and_then(|response| {
+                response
+                    .into_json::<serde_json::Value>()
+                    .map_err(StoreError::JsonEncode)
+            });
+        match lookup {
+            Ok(json) => Ok(Token::new(
+                value,
+                json["data"]["ttl"].as_u64().unwrap_or_default(),
+                json["data"]["renewable"].as_bool().unwrap_or_default(),
+            )),
+            Err(e) => {
+                log::warn!(r#"{{"op":"vault token lookup","error":"{}"}}"#, e);
+                Ok(Token::new(value, 0, false))
+            }
+        }
+    }
+
+    /// Extends the TTL of the `token` with `auth/token/renew-self`, `None` if it can't be
+    /// renewed (anymore).
+    fn renew(&self, token: &Token) -> Option<Token> {
+        if !token.renewable {
+            return None;
+        }
+        let url = self.url("auth/token/renew-self");
+        let renewed = self
+            .unauthenticated("POST", &url)
+            .set("X-Vault-Token", &token.value)
+            .send_json(ureq::json!({}))
+            .map_err(StoreError::Vault)
+            .and_then(|response| {
+                response
+                    .into_json::<serde_json::Value>()
+                    .map_err(StoreError::JsonEncode)
+            });
+        let json = match renewed {
+            Ok(json) => json,
+            Err(e) => {
+                log::warn!(r#"{{"op":"vault token renewal","error":"{}"}}"#, e);
+                return None;
+            }
+        };
+        // the renewals don't go past the max TTL of the token
+        match json["auth"]["lease_duration"].as_u64() {
+            Some(ttl) if ttl >= MIN_TOKEN_TTL => {
+                log::info!(r#"{{"op":"vault token renewed","ttl":{}}}"#, ttl);
+                Token::from_auth(&json["auth"])
+            }
+            _ => None,
+        }
+    }
+
+    /// The current token, renewed or replaced first if it's due.
+    fn fresh_token(&self) -> Result<String, StoreError> {
+        let mut token = self.token.lock().unwrap_or_else(|e| e.into_inner());
+        if matches!(token.renew_at, Some(at) if Instant::now() >= at) {
+            *token = match self.renew(&token) {
+                Some(renewed) => renewed,
+                None => self.login()?,
+            };
+        }
+        Ok(token.value.to_owned())
+    }
+
+    /// Logs in again, returns the new token.
+    fn relogin(&self) -> Result<String, StoreError> {
+        let mut token = self.token.lock().unwrap_or_else(|e| e.into_inner());
+        *token = self.login()?;
+        Ok(token.value.to_owned())
+    }
+
+    /// A request to the Vault API, in the namespace if there is one.
+    fn unauthenticated(&self, method: &str, url: &str) -> ureq::Request {
+        let request = ureq::AgentBuilder::new().build().request(method, url);
+        match &self.namespace {
+            Some(namespace) => request.set("X-Vault-Namespace", namespace),
+            None => request,
+        }
+    }
+
+    /// Sends an authenticated request to the Vault API, with the JSON `body` if given.
+    /// When Vault refuses the token, it's sent again with a new one.
+    pub fn call(
+        &self,
+        method: &str,
+        url: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<ureq::Response, StoreError> {
+        let send = |token: &str| {
+            let request = self
+                .unauthenticated(method, url)
+                .set("X-Vault-Token", token);
+            match &body {
+                Some(body) => request.send_json(body.clone()),
+                None => request.call(),
+            }
+        };
+        match send(&self.fresh_token()?) {
+            // revoked, or expired sooner than expected
+            Err(ureq::Error::Status(403, _)) => send(&self.relogin()?).map_err(StoreError::Vault),
+            result => result.map_err(StoreError::Vault),
+        }
+    }
+}
+
+pub struct VaultStore {
+    client: VaultClient,
+    mount: String,
+    prefix: String,
+    kv_version: u8,
+}
+
+impl VaultStore {
+    pub fn init(options: VaultOptions) -> Result<Self, StoreError> {
+        let mut store = VaultStore {
+            client: VaultClient::init(options.namespace, options.auth)?,
+            mount: options.mount.trim_matches('/').to_string(),
+            prefix: options.prefix,
+            kv_version: 2,
+        };
+        store.kv_version = match options.kv_version {
+            Some(version) => version,
+            None => store.detect_kv_version(),
+        };
+        Ok(store)
+    }
+
+    /// Looks the version of the KV engine up in `sys/mounts`. Reading it takes a policy
+    /// not every token has, the current version (2) is assumed then.
+    fn detect_kv_version(&self) -> u8 {
+        let url = self.client.url("sys/mounts");
+        let mounts: serde_json::Value = match self
+            .client
+            .call("GET", &url, None)
+            .and_then(|response| response.into_json().map_err(StoreError::JsonEncode))
+        {
+            Ok(mounts) => mounts,
+            Err(e) => {
+                log::warn!(
+                    r#"{{"op":"vault kv version","mount":"{}","error":"{}"}}"#,
+                    self.mount,
+                    e
+                );
+                return 2;
+            }
+        };
+        let mount = format!("{}/", self.mount);
+        // newer Vaults have the mounts under `data` too
+        let options = match mounts["data"].get(&mount) {
+            Some(mount) => &mount["options"],
+            None => &mounts[&mount]["options"],
+        };
+        match options["version"].as_str() {
+            Some("2") => 2,
+            _ => 1,
+        }
+    }
+
+    /// The URL of the secret's data at the `path`.
+    fn data_url(&self, path: &str) -> String {
+        match self.kv_version {
+            1 => self.client.url(&format!("{}/{}", self.mount, path)),
+            _ => self.client.url(&format!("{}/data/{}", self.mount, path)),
+        }
+    }
+
+    /// The URL of the secret's metadata at the `path`, where all its versions
+    /// are listed and deleted. KV version 1 keeps no versions.
+    fn metadata_url(&self, path: &str) -> String {
+        match self.kv_version {
+            1 => self.client.url(&format!("{}/{}", self.mount, path)),
+            _ => self
+                .client
+                .url(&format!("{}/metadata/{}", self.mount, path)),
+        }
+    }
+
+    fn put(&self, path: &str, payload: &[u8]) -> Result<(), StoreError> {
+        let secret = ureq::json!({ "value": base64::encode(payload) });
+        let body = match self.kv_version {
+            1 => secret,
+            _ => ureq::json!({ "data": secret }),
+        };
+        let _ = self.client.call("POST", &self.data_url(path), Some(body))?;
+        Ok(())
+    }
+
+    /// Writes the secret at the `path` unless there is one, returns whether it did.
+    /// KV version 2 checks it in the same request (`cas` 0), version 1 can't,
+    /// so it's read first there and another writer may slip in between.
+    fn put_new(&self, path: &str, payload: &[u8]) -> Result<bool, StoreError> {
+        if self.kv_version == 1 {
+            return match self.get(path) {
+                Err(StoreError::NotFound(_)) => self.put(path, payload).map(|_| true),
+                Err(e) => Err(e),
+                Ok(_) => Ok(false),
+            };
+        }
+        self.put_cas(path, payload, 0)
+    }
+
+    /// Replaces the secret at the `path` with `new` if it's still `current`, returns
+    /// whether it did. KV version 2 checks the version read is still the latest one
+    /// in the same request, version 1 can't, so another writer may slip in between.
+    fn put_swap(&self, path: &str, current: &[u8], new: &[u8]) -> Result<bool, StoreError> {
+        let (value, version) = match self.get_version(path) {
+            Ok(secret) => secret,
+            Err(StoreError::NotFound(_)) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if value != base64::encode(current).into_bytes() {
+            return Ok(false);
+        }
+        match self.kv_version {
+            1 => self.put(path, new).map(|_| true),
+            _ => self.put_cas(path, new, version),
+        }
+    }
+
+    /// Writes the secret at the `path` if its latest version is still `version`
+    /// (0 if there must be none), KV version 2 only.
+    fn put_cas(&self, path: &str, payload: &[u8], version: u64) -> Result<bool, StoreError> {
+        let secret = ureq::json!({ "value": base64::encode(payload) });
+        let body = ureq::json!({ "options": { "cas": version }, "data": secret });
+        match self.client.call("POST", &self.data_url(path), Some(body)) {
+            Ok(_) => Ok(true),
+            // check-and-set parameter did not match the current version
+            Err(StoreError::Vault(ureq::Error::Status(400, _))) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get(&self, path: &str) -> Result<Vec<u8>, StoreError> {
+        self.get_version(path).map(|(value, _)| value)
+    }
+
+    /// The secret at the `path` (base64 encoded), along with its version,
+    /// which is 0 with KV version 1.
+    fn get_version(&self, path: &str) -> Result<(Vec<u8>, u64), StoreError> {
+        let json: serde_json::Value = match self.client.call("GET", &self.data_url(path), None) {
+            Ok(response) => response.into_json().map_err(StoreError::JsonEncode)?,
+            Err(StoreError::Vault(ureq::Error::Status(404, _))) => {
+                return Err(StoreError::NotFound(path.to_string()))
+            }
+            Err(e) => return Err(e),
+        };
+        let secret = match self.kv_version {
+            1 => &json["data"],
+            _ => &json["data"]["data"],
+        };
+        let version = json["data"]["metadata"]["version"]
+            .as_u64()
+            .unwrap_or_default();
+        match secret["value"].as_str() {
+            Some(value) => Ok((value.to_string().into_bytes(), version)),
+            None => Err(StoreError::NotFound(path.to_string())),
+        }
+    }
+
+    /// Removes all the versions of the secret at the `path`.
+    fn remove(&self, path: &str) -> Result<(), StoreError> {
+        let url = self.metadata_url(path);
+        match self.client.call("DELETE", &url, None) {
+            Ok(_) | Err(StoreError::Vault(ureq::Error::Status(404, _))) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The keys under the `path`, none if there is nothing there yet.
+    fn keys(&self, path: &str) -> Result<Vec<String>, StoreError> {
+        let url = self.metadata_url(path);
+        let json: serde_json::Value = match self.client.call("LIST", &url, None) {
+            Ok(response) => response.into_json().map_err(StoreError::JsonEncode)?,
+            Err(StoreError::Vault(ureq::Error::Status(404, _))) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(json["data"]["keys"]
+            .as_array()
+            .map(|keys| {
+                keys.iter()
+                    .filter_map(|k| k.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+impl Store for VaultStore {
+    fn read(&self, kind: ObjectKind, account_name: &str) -> Result<Vec<u8>, StoreError> {
+        let path = kind.path(&self.prefix, account_name)?;
+        let buffer = base64::decode(self.get(&path)?).map_err(StoreError::Base64Decode)?;
+        Ok(buffer)
+    }
+
+    fn write(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<(), StoreError> {
+        let path = kind.path(&self.prefix, account_name)?;
+        self.put(&path, payload)?;
+        Ok(())
+    }
+
+    fn list(&self, kind: ObjectKind) -> Result<Vec<String>, StoreError> {
+        let (dir, suffix) = kind.location(&self.prefix);
+        Ok(names_with_suffix(self.keys(&dir)?.into_iter(), &suffix))
+    }
+
+    fn delete(&self, kind: ObjectKind, account_name: &str) -> Result<(), StoreError> {
+        self.remove(&kind.path(&self.prefix, account_name)?)
+    }
+
+    fn create(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        payload: &[u8],
+    ) -> Result<bool, StoreError> {
+        self.put_new(&kind.path(&self.prefix, account_name)?, payload)
+    }
+
+    fn swap(
+        &self,
+        kind: ObjectKind,
+        account_name: &str,
+        current: &[u8],
+        new: &[u8],
+    ) -> Result<bool, StoreError> {
+        self.put_swap(&kind.path(&self.prefix, account_name)?, current, new)
+    }
+}
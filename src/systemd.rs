@@ -0,0 +1,180 @@
+#![deny(clippy::mem_forget)]
+//! systemd integration: [sd_notify](https://www.freedesktop.org/software/systemd/man/sd_notify.html)
+//! readiness and watchdog pings, [socket activation](https://www.freedesktop.org/software/systemd/man/sd_listen_fds.html)
+//! and the units `certifika systemd-install` writes.
+//!
+//! Outside of systemd (no `NOTIFY_SOCKET`, no `LISTEN_FDS`) all of it is a no-op.
+use crate::APP_NAME;
+use anyhow::{anyhow, Result};
+use std::env;
+use std::net::TcpListener;
+use std::time::Duration;
+
+/// the first file descriptor passed with socket activation
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// the settings that are secrets, they are left out of the generated units
+const SECRETS: &[&str] = &[
+    "CERTIFIKA_EAB_HMAC_KEY",
+    "CERTIFIKA_EXPORT_PASSWORD",
+    "CERTIFIKA_STORE_PASSPHRASE",
+    "CERTIFIKA_BACKUP_PASSPHRASE",
+];
+
+/// Sends the `state` (e.g. `READY=1` or `WATCHDOG=1`) to the service manager,
+/// if it's listening. Abstract socket addresses are not supported.
+pub fn notify(state: &str) {
+    #[cfg(unix)]
+    {
+        let path = match env::var("NOTIFY_SOCKET") {
+            Ok(path) if path.starts_with('/') => path,
+            _ => return,
+        };
+        if let Ok(socket) = std::os::unix::net::UnixDatagram::unbound() {
+            let _ = socket.send_to(state.as_bytes(), path);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+/// How often systemd expects the watchdog pings, when `WatchdogSec=` is set.
+pub fn watchdog_interval() -> Option<Duration> {
+    if !for_this_process("WATCHDOG_PID") {
+        return None;
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec))
+}
+
+/// Takes the first socket passed with socket activation, if there is one.
+pub fn listener() -> Option<TcpListener> {
+    if !for_this_process("LISTEN_PID") {
+        return None;
+    }
+    let fds: i32 = env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if fds < 1 {
+        return None;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::FromRawFd;
+        // the descriptor is ours, systemd passed it and nothing else took it yet
+        Some(unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START) })
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+/// Whether the env var `pid_var` (`LISTEN_PID`, `WATCHDOG_PID`) names this process.
+/// A missing one does, as systemd doesn't always set it.
+fn for_this_process(pid_var: &str) -> bool {
+    match env::var(pid_var) {
+        Ok(pid) => pid.parse() == Ok(std::process::id()),
+        Err(_) => true,
+    }
+}
+
+/// The systemd units for certifika, file names mapped to their content.
+/// By default it's a oneshot service running `renew --all` and the timer starting it
+/// twice a day. With `daemon`, it's a `Type=notify` service running `certifika daemon`,
+/// along with the socket for its status endpoint at `listen`, if given.
+/// The units run the current executable, with the current `CERTIFIKA_*` settings
+/// (except the secrets, keep them in `/etc/certifika/env`) and `config`.
+pub fn units(
+    daemon: bool,
+    listen: Option<&str>,
+    config: Option<&str>,
+    watchdog: Duration,
+) -> Result<Vec<(String, String)>> {
+    let exe = env::current_exe()?;
+    let exe = exe
+        .to_str()
+        .ok_or_else(|| anyhow!("the path of the executable is not UTF-8"))?;
+    let mut environment = String::new();
+    let mut vars: Vec<(String, String)> = env::vars()
+        .filter(|(name, _)| name.starts_with("CERTIFIKA_") && !SECRETS.contains(&name.as_str()))
+        .collect();
+    vars.sort();
+    for (name, value) in vars {
+        environment.push_str(&format!("Environment=\"{}={}\"\n", name, value));
+    }
+    let config = match config {
+        Some(path) => format!(" --config {}", path),
+        None => String::new(),
+    };
+    let mut units = Vec::new();
+    if daemon {
+        units.push((
+            format!("{}.service", APP_NAME),
+            format!(
+                "[Unit]\n\
+                 Description=certifika, renews the ACME certificates\n\
+                 After=network-online.target\n\
+                 Wants=network-online.target\n\
+                 \n\
+                 [Service]\n\
+                 Type=notify\n\
+                 ExecStart={}{} daemon\n\
+                 WatchdogSec={}\n\
+                 Restart=on-failure\n\
+                 {}EnvironmentFile=-/etc/certifika/env\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy=multi-user.target\n",
+                exe,
+                config,
+                watchdog.as_secs(),
+                environment
+            ),
+        ));
+        if let Some(listen) = listen {
+            units.push((
+                format!("{}.socket", APP_NAME),
+                format!(
+                    "[Unit]\n\
+                     Description=certifika status endpoint\n\
+                     \n\
+                     [Socket]\n\
+                     ListenStream={}\n\
+                     \n\
+                     [Install]\n\
+                     WantedBy=sockets.target\n",
+                    listen
+                ),
+            ));
+        }
+    } else {
+        units.push((
+            format!("{}-renew.service", APP_NAME),
+            format!(
+                "[Unit]\n\
+                 Description=certifika, renews the ACME certificates\n\
+                 After=network-online.target\n\
+                 Wants=network-online.target\n\
+                 \n\
+                 [Service]\n\
+                 Type=oneshot\n\
+                 ExecStart={}{} renew --all\n\
+                 {}EnvironmentFile=-/etc/certifika/env\n",
+                exe, config, environment
+            ),
+        ));
+        units.push((
+            format!("{}-renew.timer", APP_NAME),
+            "[Unit]\n\
+             Description=renews the ACME certificates twice a day\n\
+             \n\
+             [Timer]\n\
+             OnCalendar=*-*-* 00,12:00:00\n\
+             RandomizedDelaySec=1h\n\
+             Persistent=true\n\
+             \n\
+             [Install]\n\
+             WantedBy=timers.target\n"
+                .to_string(),
+        ));
+    }
+    Ok(units)
+}
@@ -0,0 +1,51 @@
+#![deny(clippy::mem_forget)]
+//! a look at the certificates the servers actually serve.
+use anyhow::{anyhow, Result};
+use rustls::{
+    Certificate, ClientConfig, ClientSession, RootCertStore, ServerCertVerified,
+    ServerCertVerifier, Session, TLSError,
+};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+use webpki::DNSNameRef;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts whatever is served: expired, or otherwise broken certificates are
+/// exactly what the callers look for.
+struct AcceptAll;
+
+impl ServerCertVerifier for AcceptAll {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        _presented_certs: &[Certificate],
+        _dns_name: DNSNameRef<'_>,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Connects to the `host` (with SNI) and returns the DER encoded certificates it
+/// serves, leaf first. Nothing is sent once the handshake is done.
+pub fn served_chain(host: &str, port: u16) -> Result<Vec<Vec<u8>>> {
+    let mut config = ClientConfig::new();
+    config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(AcceptAll));
+    let name =
+        DNSNameRef::try_from_ascii_str(host).map_err(|_| anyhow!("invalid host name: {}", host))?;
+    let mut session = ClientSession::new(&Arc::new(config), name);
+    let mut socket = TcpStream::connect((host, port))?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.set_write_timeout(Some(TIMEOUT))?;
+    while session.is_handshaking() {
+        session.complete_io(&mut socket)?;
+    }
+    let certs = session
+        .get_peer_certificates()
+        .ok_or_else(|| anyhow!("{}:{} served no certificates", host, port))?;
+    Ok(certs.into_iter().map(|c| c.0).collect())
+}
@@ -0,0 +1,258 @@
+#![deny(clippy::mem_forget)]
+//! `certifika self-update`: fetches the latest release binary, verifies its
+//! [minisign](https://jedisct1.github.io/minisign/) signature and replaces the running binary.
+//!
+//! Releases are looked up at `CERTIFIKA_UPDATE_URL` (GitHub releases by default), which has to
+//! serve `version`, `certifika-<arch>-<os>` and `certifika-<arch>-<os>.minisig`. The signing
+//! public key is baked in at build time with `CERTIFIKA_RELEASE_PUBLIC_KEY`, and there is no
+//! update without it. Both the legacy (`Ed`) and the prehashed (`ED`) signatures are accepted.
+//!
+//! Only newer releases are installed. The signed trusted comment has to name the version
+//! of the binary, so an older release can't be passed off as the latest one:
+//! ```text
+//! minisign -S -m certifika-x86_64-linux -t "version:0.3.0"
+//! ```
+use crate::{APP_NAME, APP_VERSION};
+use anyhow::{anyhow, Context, Result};
+use blake2::{Blake2b, Digest};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use std::cmp::Ordering;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+#[cfg(test)]
+mod tests;
+
+const DEFAULT_UPDATE_URL: &str =
+    "https://github.com/epicfilemcnulty/certifika/releases/latest/download";
+const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("CERTIFIKA_RELEASE_PUBLIC_KEY");
+
+/// Updates the running binary to the latest release, if it's newer, or with `force`,
+/// the same version. Returns the version it was updated to, if any.
+pub fn self_update(force: bool) -> Result<Option<String>> {
+    let public_key = PublicKey::parse(
+        RELEASE_PUBLIC_KEY.context("this build has no release public key, can't verify updates")?,
+    )?;
+    let base_url =
+        env::var("CERTIFIKA_UPDATE_URL").unwrap_or_else(|_| DEFAULT_UPDATE_URL.to_string());
+    let latest = get(&format!("{}/version", base_url))?;
+    let latest = String::from_utf8(latest)?.trim().to_string();
+    match Version::parse(&latest)?.cmp(&Version::parse(APP_VERSION)?) {
+        Ordering::Greater => (),
+        Ordering::Equal if force => (),
+        _ => return Ok(None),
+    }
+    let asset = format!("{}-{}-{}", APP_NAME, env::consts::ARCH, env::consts::OS);
+    let binary = get(&format!("{}/{}", base_url, asset))?;
+    let signature = get(&format!("{}/{}.minisig", base_url, asset))?;
+    let signature = Signature::parse(&String::from_utf8(signature)?)?;
+    signature.verify(&public_key, &binary)?;
+    // verified along with the binary, unlike the version file
+    if signature.version() != Some(latest.as_str()) {
+        return Err(anyhow!(
+            "the release is signed as version {}, not {}",
+            signature.version().unwrap_or("unknown"),
+            latest
+        ));
+    }
+    replace_binary(&binary)?;
+    log::info!(
+        r#"{{"op":"self update","from":"{}","to":"{}"}}"#,
+        APP_VERSION,
+        latest
+    );
+    Ok(Some(latest))
+}
+
+/// A release version, `major.minor.patch`, optionally with a pre-release tag
+/// (`0.3.0-rc.1`), which comes before the release itself.
+#[derive(Debug, PartialEq, Eq)]
+struct Version {
+    numbers: Vec<u64>,
+    pre: Option<String>,
+}
+
+impl Version {
+    fn parse(version: &str) -> Result<Self> {
+        let version = version.trim().trim_start_matches('v');
+        let mut parts = version.splitn(2, '-');
+        let mut numbers = parts
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<Vec<u64>, _>>()
+            .with_context(|| format!("invalid version {:?}", version))?;
+        // 0.3 is 0.3.0
+        while numbers.len() > 1 && numbers.last() == Some(&0) {
+            numbers.pop();
+        }
+        Ok(Version {
+            numbers,
+            pre: parts.next().map(str::to_string),
+        })
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.numbers
+            .cmp(&other.numbers)
+            .then_with(|| match (&self.pre, &other.pre) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(pre), Some(other)) => compare_pre_releases(pre, other),
+            })
+    }
+}
+
+/// Compares pre-release tags the semver way: identifier by identifier, the numeric
+/// ones as numbers (`rc.2` before `rc.10`) and before the alphanumeric ones, and
+/// a tag before the longer ones it starts.
+fn compare_pre_releases(pre: &str, other: &str) -> Ordering {
+    let mut ids = pre.split('.');
+    let mut other_ids = other.split('.');
+    loop {
+        let ordering = match (ids.next(), other_ids.next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(id), Some(other)) => match (id.parse::<u64>(), other.parse::<u64>()) {
+                (Ok(n), Ok(other)) => n.cmp(&other),
+                (Ok(_), Err(_)) => Ordering::Less,
+                (Err(_), Ok(_)) => Ordering::Greater,
+                (Err(_), Err(_)) => id.cmp(other),
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+struct PublicKey {
+    key_id: [u8; 8],
+    key: [u8; 32],
+}
+
+struct Signature {
+    prehashed: bool,
+    key_id: [u8; 8],
+    signature: Vec<u8>,
+    trusted_comment: String,
+    global_signature: Vec<u8>,
+}
+
+impl PublicKey {
+    /// Parses the base64 line of a minisign public key: `Ed`, key ID, the key itself.
+    fn parse(encoded: &str) -> Result<Self> {
+        let raw = base64::decode(encoded.trim())?;
+        if raw.len() != 42 || &raw[..2] != b"Ed" {
+            return Err(anyhow!("invalid minisign public key"));
+        }
+        let mut key = PublicKey {
+            key_id: [0; 8],
+            key: [0; 32],
+        };
+        key.key_id.copy_from_slice(&raw[2..10]);
+        key.key.copy_from_slice(&raw[10..]);
+        Ok(key)
+    }
+}
+
+impl Signature {
+    /// Parses a `.minisig` file: an untrusted comment, the signature,
+    /// a trusted comment and the signature of the signature and the trusted comment.
+    fn parse(minisig: &str) -> Result<Self> {
+        let lines: Vec<&str> = minisig.lines().collect();
+        if lines.len() < 4 || !lines[2].starts_with("trusted comment: ") {
+            return Err(anyhow!("invalid minisign signature file"));
+        }
+        let raw = base64::decode(lines[1].trim())?;
+        if raw.len() != 74 {
+            return Err(anyhow!("invalid minisign signature"));
+        }
+        let prehashed = match &raw[..2] {
+            b"Ed" => false,
+            b"ED" => true,
+            _ => return Err(anyhow!("unsupported minisign signature algorithm")),
+        };
+        let mut key_id = [0; 8];
+        key_id.copy_from_slice(&raw[2..10]);
+        Ok(Signature {
+            prehashed,
+            key_id,
+            signature: raw[10..].to_vec(),
+            trusted_comment: lines[2]["trusted comment: ".len()..].to_string(),
+            global_signature: base64::decode(lines[3].trim())?,
+        })
+    }
+
+    /// The version the trusted comment names, as `version:<version>`.
+    fn version(&self) -> Option<&str> {
+        self.trusted_comment
+            .split_whitespace()
+            .find_map(|field| field.strip_prefix("version:"))
+    }
+
+    fn verify(&self, public_key: &PublicKey, data: &[u8]) -> Result<()> {
+        if self.key_id != public_key.key_id {
+            return Err(anyhow!("the release is signed with an unknown key"));
+        }
+        let key = UnparsedPublicKey::new(&ED25519, &public_key.key);
+        let signed = if self.prehashed {
+            Blake2b::digest(data).to_vec()
+        } else {
+            data.to_vec()
+        };
+        key.verify(&signed, &self.signature)
+            .map_err(|_| anyhow!("invalid release signature"))?;
+        let mut global = self.signature.clone();
+        global.extend_from_slice(self.trusted_comment.as_bytes());
+        key.verify(&global, &self.global_signature)
+            .map_err(|_| anyhow!("invalid signature of the trusted comment"))?;
+        Ok(())
+    }
+}
+
+fn get(url: &str) -> Result<Vec<u8>> {
+    let response = ureq::get(url)
+        .set("User-Agent", &format!("{} {}", APP_NAME, APP_VERSION))
+        .call()
+        .with_context(|| format!("fetching {}", url))?;
+    let mut body = Vec::new();
+    response.into_reader().read_to_end(&mut body)?;
+    Ok(body)
+}
+
+/// Writes the new binary next to the running one, and renames it over the
+/// running one, so there is never a half written binary in place.
+fn replace_binary(binary: &[u8]) -> Result<()> {
+    let current = env::current_exe()?;
+    let staged = current.with_extension("new");
+    fs::write(&staged, binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+    #[cfg(windows)]
+    {
+        // a running binary can't be replaced on Windows, but it can be renamed
+        fs::rename(&current, current.with_extension("old"))?;
+    }
+    rename(&staged, &current)
+}
+
+fn rename(from: &Path, to: &Path) -> Result<()> {
+    fs::rename(from, to).with_context(|| format!("replacing {}", to.display()))
+}
@@ -0,0 +1,137 @@
+//! the ordering of the release versions, and the minisign public keys and
+//! signatures: parsing them, and verifying signed releases with them.
+use super::*;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+
+const KEY_ID: [u8; 8] = *b"certifka";
+const BINARY: &[u8] = b"\x7fELF the release binary";
+
+fn key_pair() -> Ed25519KeyPair {
+    Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap()
+}
+
+/// The minisign public key line of the `key_pair()`.
+fn public_key() -> String {
+    let mut raw = b"Ed".to_vec();
+    raw.extend_from_slice(&KEY_ID);
+    raw.extend_from_slice(key_pair().public_key().as_ref());
+    base64::encode(raw)
+}
+
+/// A `.minisig` of the `data`, the way `minisign -S` writes it.
+fn minisig(data: &[u8], prehashed: bool, trusted_comment: &str) -> String {
+    let key_pair = key_pair();
+    let (algorithm, signature) = if prehashed {
+        (b"ED", key_pair.sign(&Blake2b::digest(data)))
+    } else {
+        (b"Ed", key_pair.sign(data))
+    };
+    let mut raw = algorithm.to_vec();
+    raw.extend_from_slice(&KEY_ID);
+    raw.extend_from_slice(signature.as_ref());
+    let mut global = signature.as_ref().to_vec();
+    global.extend_from_slice(trusted_comment.as_bytes());
+    format!(
+        "untrusted comment: signature from minisign secret key\n{}\ntrusted comment: {}\n{}\n",
+        base64::encode(raw),
+        trusted_comment,
+        base64::encode(key_pair.sign(&global))
+    )
+}
+
+fn version(version: &str) -> Version {
+    Version::parse(version).unwrap()
+}
+
+#[test]
+fn versions_are_ordered_by_their_numbers() {
+    assert!(version("0.3.0") > version("0.2.9"));
+    assert!(version("0.10.0") > version("0.9.1"));
+    assert!(version("1.0.0") > version("0.99.99"));
+    assert!(version("0.3.1") > version("0.3"));
+    assert_eq!(version("v0.3"), version("0.3.0"));
+    assert_eq!(version(" 0.3.0\n"), version("0.3.0"));
+    assert!(Version::parse("latest").is_err());
+    assert!(Version::parse("0.3.x").is_err());
+}
+
+#[test]
+fn pre_releases_come_before_the_release() {
+    assert!(version("0.3.0-rc.1") < version("0.3.0"));
+    assert!(version("0.3.0-rc.1") > version("0.2.9"));
+    assert!(version("0.3.0-rc.1") < version("0.3.0-rc.2"));
+    assert!(version("0.3.0-rc.2") < version("0.3.0-rc.10"));
+    assert!(version("0.3.0-rc") < version("0.3.0-rc.1"));
+    assert!(version("0.3.0-1") < version("0.3.0-alpha"));
+    assert!(version("0.3.0-alpha") < version("0.3.0-beta"));
+}
+
+#[test]
+fn public_key_is_parsed() {
+    let key = PublicKey::parse(&format!("{}\n", public_key())).unwrap();
+    assert_eq!(key.key_id, KEY_ID);
+    assert_eq!(&key.key[..], key_pair().public_key().as_ref());
+    assert!(PublicKey::parse(&base64::encode(b"Ed too short")).is_err());
+    let mut raw = base64::decode(public_key()).unwrap();
+    raw[..2].copy_from_slice(b"RS");
+    assert!(PublicKey::parse(&base64::encode(raw)).is_err());
+    assert!(PublicKey::parse("not base64!").is_err());
+}
+
+#[test]
+fn signature_is_parsed() {
+    let trusted_comment = "timestamp:1600000000\tfile:certifika-x86_64-linux version:0.3.0";
+    let signature = Signature::parse(&minisig(BINARY, true, trusted_comment)).unwrap();
+    assert!(signature.prehashed);
+    assert_eq!(signature.key_id, KEY_ID);
+    assert_eq!(signature.trusted_comment, trusted_comment);
+    assert_eq!(signature.version(), Some("0.3.0"));
+    let signature = Signature::parse(&minisig(BINARY, false, "timestamp:1600000000")).unwrap();
+    assert!(!signature.prehashed);
+    assert_eq!(signature.version(), None);
+}
+
+#[test]
+fn malformed_signatures_are_rejected() {
+    let minisig = minisig(BINARY, false, "version:0.3.0");
+    let lines: Vec<&str> = minisig.lines().collect();
+    // no trusted comment
+    assert!(Signature::parse(&lines[..2].join("\n")).is_err());
+    let swapped = [lines[0], lines[2], lines[1], lines[3]].join("\n");
+    assert!(Signature::parse(&swapped).is_err());
+    let mut raw = base64::decode(lines[1]).unwrap();
+    raw[..2].copy_from_slice(b"Xy");
+    let unknown = [lines[0], &base64::encode(&raw), lines[2], lines[3]].join("\n");
+    assert!(Signature::parse(&unknown).is_err());
+    let truncated = [lines[0], &base64::encode(&raw[..70]), lines[2], lines[3]].join("\n");
+    assert!(Signature::parse(&truncated).is_err());
+}
+
+#[test]
+fn signed_release_is_verified() {
+    let public_key = PublicKey::parse(&public_key()).unwrap();
+    for prehashed in &[false, true] {
+        let signature = Signature::parse(&minisig(BINARY, *prehashed, "version:0.3.0")).unwrap();
+        signature.verify(&public_key, BINARY).unwrap();
+        assert!(signature.verify(&public_key, b"another binary").is_err());
+    }
+}
+
+#[test]
+fn tampered_trusted_comment_is_rejected() {
+    let public_key = PublicKey::parse(&public_key()).unwrap();
+    let minisig = minisig(BINARY, true, "version:0.2.0");
+    let tampered = minisig.replace("version:0.2.0", "version:0.3.0");
+    let signature = Signature::parse(&tampered).unwrap();
+    assert_eq!(signature.version(), Some("0.3.0"));
+    assert!(signature.verify(&public_key, BINARY).is_err());
+}
+
+#[test]
+fn signature_of_another_key_is_rejected() {
+    let mut raw = base64::decode(public_key()).unwrap();
+    raw[2..10].copy_from_slice(b"otherkey");
+    let public_key = PublicKey::parse(&base64::encode(raw)).unwrap();
+    let signature = Signature::parse(&minisig(BINARY, false, "version:0.3.0")).unwrap();
+    assert!(signature.verify(&public_key, BINARY).is_err());
+}
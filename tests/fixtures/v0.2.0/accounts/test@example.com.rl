@@ -0,0 +1 @@
+{"orders":[1700000000,1700000600],"certificates":[["example.com",1700000600]],"blocked_until":null}
\ No newline at end of file